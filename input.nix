@@ -47,6 +47,12 @@ in {
         default = null;
       };
 
+      prebuildModules = mkOption {
+        type = types.bool;
+        default = false;
+        description = "Load modules of the prebuild kernel from the share directory";
+      };
+
       fat = mkOption {
         type = types.bool;
         default = false;
@@ -70,6 +76,11 @@ in {
       }
     );
   in {
+    # kd copies lib/modules of the prebuild kernel to the share directory.
+    # NixOS kmod looks for modules in MODULE_DIR if it's set.
+    environment.variables.MODULE_DIR = mkIf cfg.prebuildModules "/root/share/modules";
+    systemd.globalEnvironment.MODULE_DIR = mkIf cfg.prebuildModules "/root/share/modules";
+
     services.xfsprogs.enablePython =
       if (cfg.fat)
       then true
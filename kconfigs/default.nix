@@ -28,6 +28,12 @@ with lib.kernel; {
 
     # Support of printk
     PRINTK = yes;
+
+    # Blocked tasks dump (sysrq-w) of hung tests
+    MAGIC_SYSRQ = yes;
+    # VMCOREINFO in QEMU's vmcoreinfo device, 'kd dump' cores need it
+    FW_CFG_SYSFS = yes;
+    CRASH_DUMP = yes;
     PRINTK_TIME = no;
     # Write printk to VGA/serial port
     EARLY_PRINTK = yes;
@@ -132,6 +138,8 @@ with lib.kernel; {
 
     # Enable kernel tracers
     FTRACE = yes;
+    # Block layer trace events for [trace] and blktrace
+    BLK_DEV_IO_TRACE = yes;
     # Creates /proc/pid/stack which shows current stack for each process
     STACKTRACE = yes;
 
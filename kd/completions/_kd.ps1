@@ -25,6 +25,7 @@ Register-ArgumentCompleter -Native -CommandName 'kd' -ScriptBlock {
             [CompletionResult]::new('--config', '--config', [CompletionResultType]::ParameterName, 'Sets a custom config file')
             [CompletionResult]::new('-d', '-d', [CompletionResultType]::ParameterName, 'Turn debugging information on')
             [CompletionResult]::new('--debug', '--debug', [CompletionResultType]::ParameterName, 'Turn debugging information on')
+            [CompletionResult]::new('--offline', '--offline', [CompletionResultType]::ParameterName, 'Don''t access network, use only sources fetched before')
             [CompletionResult]::new('-h', '-h', [CompletionResultType]::ParameterName, 'Print help')
             [CompletionResult]::new('--help', '--help', [CompletionResultType]::ParameterName, 'Print help')
             [CompletionResult]::new('-V', '-V ', [CompletionResultType]::ParameterName, 'Print version')
@@ -32,6 +33,15 @@ Register-ArgumentCompleter -Native -CommandName 'kd' -ScriptBlock {
             [CompletionResult]::new('init', 'init', [CompletionResultType]::ParameterValue, 'Initialize development environment')
             [CompletionResult]::new('build', 'build', [CompletionResultType]::ParameterValue, 'Build image')
             [CompletionResult]::new('run', 'run', [CompletionResultType]::ParameterValue, 'Run QEMU test system')
+            [CompletionResult]::new('deploy', 'deploy', [CompletionResultType]::ParameterValue, 'Deploy image to libvirt')
+            [CompletionResult]::new('results', 'results', [CompletionResultType]::ParameterValue, 'Results of the last run')
+            [CompletionResult]::new('report', 'report', [CompletionResultType]::ParameterValue, 'HTML report of the last run, or a matrix of several runs')
+            [CompletionResult]::new('disks', 'disks', [CompletionResultType]::ParameterValue, 'Manage VM disk images')
+            [CompletionResult]::new('vm', 'vm', [CompletionResultType]::ParameterValue, 'Control the running VM')
+            [CompletionResult]::new('dump', 'dump', [CompletionResultType]::ParameterValue, 'Save guest memory and vmlinux of the running VM for ''kd drgn''')
+            [CompletionResult]::new('drgn', 'drgn', [CompletionResultType]::ParameterValue, 'Debug a memory dump with drgn')
+            [CompletionResult]::new('clean', 'clean', [CompletionResultType]::ParameterValue, 'Remove artifacts created by kd')
+            [CompletionResult]::new('doctor', 'doctor', [CompletionResultType]::ParameterValue, 'Check that kd environment and dependencies are set up correctly')
             [CompletionResult]::new('update', 'update', [CompletionResultType]::ParameterValue, 'Update ''kd'' environment')
             [CompletionResult]::new('config', 'config', [CompletionResultType]::ParameterValue, 'Generate minimal kernel config for VM')
             [CompletionResult]::new('debug', 'debug', [CompletionResultType]::ParameterValue, 'Developer tools')
@@ -39,20 +49,205 @@ Register-ArgumentCompleter -Native -CommandName 'kd' -ScriptBlock {
             break
         }
         'kd;init' {
+            [CompletionResult]::new('--template', '--template', [CompletionResultType]::ParameterName, 'Flake template to use, can be a local path')
+            [CompletionResult]::new('--preset', '--preset', [CompletionResultType]::ParameterName, 'Starting config for .kd.toml')
+            [CompletionResult]::new('--force', '--force', [CompletionResultType]::ParameterName, 'Reinitialize environment and .kd.toml, keeps flake.lock and user modules')
+            [CompletionResult]::new('--upgrade', '--upgrade', [CompletionResultType]::ParameterName, 'Update flake from the template, keeps flake.lock, .kd.toml and user modules')
             [CompletionResult]::new('-h', '-h', [CompletionResultType]::ParameterName, 'Print help')
             [CompletionResult]::new('--help', '--help', [CompletionResultType]::ParameterName, 'Print help')
             break
         }
         'kd;build' {
             [CompletionResult]::new('--name', '--name', [CompletionResultType]::ParameterName, 'Name of a test config to use')
-            [CompletionResult]::new('-t', '-t', [CompletionResultType]::ParameterName, 'kd package to build')
-            [CompletionResult]::new('--target', '--target', [CompletionResultType]::ParameterName, 'kd package to build')
+            [CompletionResult]::new('-t', '-t', [CompletionResultType]::ParameterName, 'kd package to build, lists available packages without a value')
+            [CompletionResult]::new('--target', '--target', [CompletionResultType]::ParameterName, 'kd package to build, lists available packages without a value')
             [CompletionResult]::new('-h', '-h', [CompletionResultType]::ParameterName, 'Print help')
             [CompletionResult]::new('--help', '--help', [CompletionResultType]::ParameterName, 'Print help')
             break
         }
         'kd;run' {
             [CompletionResult]::new('--name', '--name', [CompletionResultType]::ParameterName, 'Name of a test config to use')
+            [CompletionResult]::new('--fresh', '--fresh', [CompletionResultType]::ParameterName, 'Start with a new root disk')
+            [CompletionResult]::new('--image', '--image', [CompletionResultType]::ParameterName, 'Boot the disk image (''kd build'') instead of the VM')
+            [CompletionResult]::new('--plain', '--plain', [CompletionResultType]::ParameterName, 'Show raw console instead of xfstests progress')
+            [CompletionResult]::new('-h', '-h', [CompletionResultType]::ParameterName, 'Print help')
+            [CompletionResult]::new('--help', '--help', [CompletionResultType]::ParameterName, 'Print help')
+            break
+        }
+        'kd;deploy' {
+            [CompletionResult]::new('--name', '--name', [CompletionResultType]::ParameterName, 'Name of a test config to use')
+            [CompletionResult]::new('--uri', '--uri', [CompletionResultType]::ParameterName, 'libvirt connection URI, e.g. qemu+ssh://host/system')
+            [CompletionResult]::new('--domain', '--domain', [CompletionResultType]::ParameterName, 'Domain name, kd-<directory>[-<name>] by default')
+            [CompletionResult]::new('--pool', '--pool', [CompletionResultType]::ParameterName, 'Storage pool for the image volume')
+            [CompletionResult]::new('--network', '--network', [CompletionResultType]::ParameterName, 'libvirt network to attach, user networking if not set')
+            [CompletionResult]::new('-h', '-h', [CompletionResultType]::ParameterName, 'Print help')
+            [CompletionResult]::new('--help', '--help', [CompletionResultType]::ParameterName, 'Print help')
+            break
+        }
+        'kd;results' {
+            [CompletionResult]::new('--name', '--name', [CompletionResultType]::ParameterName, 'Name of a test config to use')
+            [CompletionResult]::new('--format', '--format', [CompletionResultType]::ParameterName, 'Merge xunit reports of all sections into one report')
+            [CompletionResult]::new('--input', '--input', [CompletionResultType]::ParameterName, 'Directory with results instead of the last run, can be repeated')
+            [CompletionResult]::new('-o', '-o', [CompletionResultType]::ParameterName, 'Write report to the file instead of stdout')
+            [CompletionResult]::new('--output', '--output', [CompletionResultType]::ParameterName, 'Write report to the file instead of stdout')
+            [CompletionResult]::new('--upload', '--upload', [CompletionResultType]::ParameterName, 'Upload results with backends from [results]')
+            [CompletionResult]::new('-h', '-h', [CompletionResultType]::ParameterName, 'Print help')
+            [CompletionResult]::new('--help', '--help', [CompletionResultType]::ParameterName, 'Print help')
+            break
+        }
+        'kd;report' {
+            [CompletionResult]::new('--name', '--name', [CompletionResultType]::ParameterName, 'Name of a test config to use')
+            [CompletionResult]::new('--html', '--html', [CompletionResultType]::ParameterName, 'Directory to write the HTML report to')
+            [CompletionResult]::new('--input', '--input', [CompletionResultType]::ParameterName, 'Directory with results of a run instead of the last run, can be repeated')
+            [CompletionResult]::new('-h', '-h', [CompletionResultType]::ParameterName, 'Print help')
+            [CompletionResult]::new('--help', '--help', [CompletionResultType]::ParameterName, 'Print help')
+            break
+        }
+        'kd;disks' {
+            [CompletionResult]::new('-h', '-h', [CompletionResultType]::ParameterName, 'Print help')
+            [CompletionResult]::new('--help', '--help', [CompletionResultType]::ParameterName, 'Print help')
+            [CompletionResult]::new('list', 'list', [CompletionResultType]::ParameterValue, 'Show disk images and their sizes')
+            [CompletionResult]::new('reset', 'reset', [CompletionResultType]::ParameterValue, 'Remove root disk, next boot starts with a new one')
+            [CompletionResult]::new('snapshot', 'snapshot', [CompletionResultType]::ParameterValue, 'Save current state of the root disk')
+            [CompletionResult]::new('restore', 'restore', [CompletionResultType]::ParameterValue, 'Replace root disk with the snapshot')
+            [CompletionResult]::new('help', 'help', [CompletionResultType]::ParameterValue, 'Print this message or the help of the given subcommand(s)')
+            break
+        }
+        'kd;disks;list' {
+            [CompletionResult]::new('-h', '-h', [CompletionResultType]::ParameterName, 'Print help')
+            [CompletionResult]::new('--help', '--help', [CompletionResultType]::ParameterName, 'Print help')
+            break
+        }
+        'kd;disks;reset' {
+            [CompletionResult]::new('-h', '-h', [CompletionResultType]::ParameterName, 'Print help')
+            [CompletionResult]::new('--help', '--help', [CompletionResultType]::ParameterName, 'Print help')
+            break
+        }
+        'kd;disks;snapshot' {
+            [CompletionResult]::new('-h', '-h', [CompletionResultType]::ParameterName, 'Print help')
+            [CompletionResult]::new('--help', '--help', [CompletionResultType]::ParameterName, 'Print help')
+            break
+        }
+        'kd;disks;restore' {
+            [CompletionResult]::new('-h', '-h', [CompletionResultType]::ParameterName, 'Print help')
+            [CompletionResult]::new('--help', '--help', [CompletionResultType]::ParameterName, 'Print help')
+            break
+        }
+        'kd;disks;help' {
+            [CompletionResult]::new('list', 'list', [CompletionResultType]::ParameterValue, 'Show disk images and their sizes')
+            [CompletionResult]::new('reset', 'reset', [CompletionResultType]::ParameterValue, 'Remove root disk, next boot starts with a new one')
+            [CompletionResult]::new('snapshot', 'snapshot', [CompletionResultType]::ParameterValue, 'Save current state of the root disk')
+            [CompletionResult]::new('restore', 'restore', [CompletionResultType]::ParameterValue, 'Replace root disk with the snapshot')
+            [CompletionResult]::new('help', 'help', [CompletionResultType]::ParameterValue, 'Print this message or the help of the given subcommand(s)')
+            break
+        }
+        'kd;disks;help;list' {
+            break
+        }
+        'kd;disks;help;reset' {
+            break
+        }
+        'kd;disks;help;snapshot' {
+            break
+        }
+        'kd;disks;help;restore' {
+            break
+        }
+        'kd;disks;help;help' {
+            break
+        }
+        'kd;vm' {
+            [CompletionResult]::new('-h', '-h', [CompletionResultType]::ParameterName, 'Print help')
+            [CompletionResult]::new('--help', '--help', [CompletionResultType]::ParameterName, 'Print help')
+            [CompletionResult]::new('status', 'status', [CompletionResultType]::ParameterValue, 'Show whether the VM is running')
+            [CompletionResult]::new('stop', 'stop', [CompletionResultType]::ParameterValue, 'Shut the VM down')
+            [CompletionResult]::new('reset', 'reset', [CompletionResultType]::ParameterValue, 'Reset the VM as with the reset button')
+            [CompletionResult]::new('sysrq', 'sysrq', [CompletionResultType]::ParameterValue, 'Send Alt+SysRq+<key>, e.g. ''w'' for blocked tasks')
+            [CompletionResult]::new('dump', 'dump', [CompletionResultType]::ParameterValue, 'Dump guest memory as ELF core')
+            [CompletionResult]::new('help', 'help', [CompletionResultType]::ParameterValue, 'Print this message or the help of the given subcommand(s)')
+            break
+        }
+        'kd;vm;status' {
+            [CompletionResult]::new('-h', '-h', [CompletionResultType]::ParameterName, 'Print help')
+            [CompletionResult]::new('--help', '--help', [CompletionResultType]::ParameterName, 'Print help')
+            break
+        }
+        'kd;vm;stop' {
+            [CompletionResult]::new('--force', '--force', [CompletionResultType]::ParameterName, 'Kill QEMU instead of asking the guest to power off')
+            [CompletionResult]::new('-h', '-h', [CompletionResultType]::ParameterName, 'Print help')
+            [CompletionResult]::new('--help', '--help', [CompletionResultType]::ParameterName, 'Print help')
+            break
+        }
+        'kd;vm;reset' {
+            [CompletionResult]::new('-h', '-h', [CompletionResultType]::ParameterName, 'Print help')
+            [CompletionResult]::new('--help', '--help', [CompletionResultType]::ParameterName, 'Print help')
+            break
+        }
+        'kd;vm;sysrq' {
+            [CompletionResult]::new('-h', '-h', [CompletionResultType]::ParameterName, 'Print help')
+            [CompletionResult]::new('--help', '--help', [CompletionResultType]::ParameterName, 'Print help')
+            break
+        }
+        'kd;vm;dump' {
+            [CompletionResult]::new('-h', '-h', [CompletionResultType]::ParameterName, 'Print help')
+            [CompletionResult]::new('--help', '--help', [CompletionResultType]::ParameterName, 'Print help')
+            break
+        }
+        'kd;vm;help' {
+            [CompletionResult]::new('status', 'status', [CompletionResultType]::ParameterValue, 'Show whether the VM is running')
+            [CompletionResult]::new('stop', 'stop', [CompletionResultType]::ParameterValue, 'Shut the VM down')
+            [CompletionResult]::new('reset', 'reset', [CompletionResultType]::ParameterValue, 'Reset the VM as with the reset button')
+            [CompletionResult]::new('sysrq', 'sysrq', [CompletionResultType]::ParameterValue, 'Send Alt+SysRq+<key>, e.g. ''w'' for blocked tasks')
+            [CompletionResult]::new('dump', 'dump', [CompletionResultType]::ParameterValue, 'Dump guest memory as ELF core')
+            [CompletionResult]::new('help', 'help', [CompletionResultType]::ParameterValue, 'Print this message or the help of the given subcommand(s)')
+            break
+        }
+        'kd;vm;help;status' {
+            break
+        }
+        'kd;vm;help;stop' {
+            break
+        }
+        'kd;vm;help;reset' {
+            break
+        }
+        'kd;vm;help;sysrq' {
+            break
+        }
+        'kd;vm;help;dump' {
+            break
+        }
+        'kd;vm;help;help' {
+            break
+        }
+        'kd;dump' {
+            [CompletionResult]::new('--name', '--name', [CompletionResultType]::ParameterName, 'Name of the test config the VM runs')
+            [CompletionResult]::new('-h', '-h', [CompletionResultType]::ParameterName, 'Print help')
+            [CompletionResult]::new('--help', '--help', [CompletionResultType]::ParameterName, 'Print help')
+            break
+        }
+        'kd;drgn' {
+            [CompletionResult]::new('--dump', '--dump', [CompletionResultType]::ParameterName, 'Dump directory [default: the latest in .kd/dumps]')
+            [CompletionResult]::new('-s', '-s', [CompletionResultType]::ParameterName, 'Run the drgn script instead of the interactive shell')
+            [CompletionResult]::new('--script', '--script', [CompletionResultType]::ParameterName, 'Run the drgn script instead of the interactive shell')
+            [CompletionResult]::new('-h', '-h', [CompletionResultType]::ParameterName, 'Print help')
+            [CompletionResult]::new('--help', '--help', [CompletionResultType]::ParameterName, 'Print help')
+            break
+        }
+        'kd;clean' {
+            [CompletionResult]::new('--older-than', '--older-than', [CompletionResultType]::ParameterName, 'Only remove artifacts older than AGE (e.g. 12h, 7d, 2w)')
+            [CompletionResult]::new('--logs', '--logs', [CompletionResultType]::ParameterName, 'Remove execution logs')
+            [CompletionResult]::new('--images', '--images', [CompletionResultType]::ParameterName, 'Remove disk images, snapshots and memory dumps')
+            [CompletionResult]::new('--results', '--results', [CompletionResultType]::ParameterName, 'Remove build results and test results')
+            [CompletionResult]::new('--backups', '--backups', [CompletionResultType]::ParameterName, 'Remove .bup backups')
+            [CompletionResult]::new('--all', '--all', [CompletionResultType]::ParameterName, 'Remove everything kd created')
+            [CompletionResult]::new('--dry-run', '--dry-run', [CompletionResultType]::ParameterName, 'Show what would be removed')
+            [CompletionResult]::new('--gc-roots', '--gc-roots', [CompletionResultType]::ParameterName, 'Remove ''result'' links ''kd build'' created in the worktree')
+            [CompletionResult]::new('-h', '-h', [CompletionResultType]::ParameterName, 'Print help')
+            [CompletionResult]::new('--help', '--help', [CompletionResultType]::ParameterName, 'Print help')
+            break
+        }
+        'kd;doctor' {
             [CompletionResult]::new('-h', '-h', [CompletionResultType]::ParameterName, 'Print help')
             [CompletionResult]::new('--help', '--help', [CompletionResultType]::ParameterName, 'Print help')
             break
@@ -66,6 +261,8 @@ Register-ArgumentCompleter -Native -CommandName 'kd' -ScriptBlock {
             [CompletionResult]::new('-o', '-o', [CompletionResultType]::ParameterName, 'Output filename')
             [CompletionResult]::new('--output', '--output', [CompletionResultType]::ParameterName, 'Output filename')
             [CompletionResult]::new('--name', '--name', [CompletionResultType]::ParameterName, 'Name of a test config to use')
+            [CompletionResult]::new('--diff', '--diff', [CompletionResultType]::ParameterName, 'Show changes to the output config (diffconfig format) instead of writing it')
+            [CompletionResult]::new('--merge', '--merge', [CompletionResultType]::ParameterName, 'Merge required options into the existing config and run ''make olddefconfig''')
             [CompletionResult]::new('-h', '-h', [CompletionResultType]::ParameterName, 'Print help')
             [CompletionResult]::new('--help', '--help', [CompletionResultType]::ParameterName, 'Print help')
             break
@@ -82,6 +279,15 @@ Register-ArgumentCompleter -Native -CommandName 'kd' -ScriptBlock {
             [CompletionResult]::new('init', 'init', [CompletionResultType]::ParameterValue, 'Initialize development environment')
             [CompletionResult]::new('build', 'build', [CompletionResultType]::ParameterValue, 'Build image')
             [CompletionResult]::new('run', 'run', [CompletionResultType]::ParameterValue, 'Run QEMU test system')
+            [CompletionResult]::new('deploy', 'deploy', [CompletionResultType]::ParameterValue, 'Deploy image to libvirt')
+            [CompletionResult]::new('results', 'results', [CompletionResultType]::ParameterValue, 'Results of the last run')
+            [CompletionResult]::new('report', 'report', [CompletionResultType]::ParameterValue, 'HTML report of the last run, or a matrix of several runs')
+            [CompletionResult]::new('disks', 'disks', [CompletionResultType]::ParameterValue, 'Manage VM disk images')
+            [CompletionResult]::new('vm', 'vm', [CompletionResultType]::ParameterValue, 'Control the running VM')
+            [CompletionResult]::new('dump', 'dump', [CompletionResultType]::ParameterValue, 'Save guest memory and vmlinux of the running VM for ''kd drgn''')
+            [CompletionResult]::new('drgn', 'drgn', [CompletionResultType]::ParameterValue, 'Debug a memory dump with drgn')
+            [CompletionResult]::new('clean', 'clean', [CompletionResultType]::ParameterValue, 'Remove artifacts created by kd')
+            [CompletionResult]::new('doctor', 'doctor', [CompletionResultType]::ParameterValue, 'Check that kd environment and dependencies are set up correctly')
             [CompletionResult]::new('update', 'update', [CompletionResultType]::ParameterValue, 'Update ''kd'' environment')
             [CompletionResult]::new('config', 'config', [CompletionResultType]::ParameterValue, 'Generate minimal kernel config for VM')
             [CompletionResult]::new('debug', 'debug', [CompletionResultType]::ParameterValue, 'Developer tools')
@@ -97,6 +303,69 @@ Register-ArgumentCompleter -Native -CommandName 'kd' -ScriptBlock {
         'kd;help;run' {
             break
         }
+        'kd;help;deploy' {
+            break
+        }
+        'kd;help;results' {
+            break
+        }
+        'kd;help;report' {
+            break
+        }
+        'kd;help;disks' {
+            [CompletionResult]::new('list', 'list', [CompletionResultType]::ParameterValue, 'Show disk images and their sizes')
+            [CompletionResult]::new('reset', 'reset', [CompletionResultType]::ParameterValue, 'Remove root disk, next boot starts with a new one')
+            [CompletionResult]::new('snapshot', 'snapshot', [CompletionResultType]::ParameterValue, 'Save current state of the root disk')
+            [CompletionResult]::new('restore', 'restore', [CompletionResultType]::ParameterValue, 'Replace root disk with the snapshot')
+            break
+        }
+        'kd;help;disks;list' {
+            break
+        }
+        'kd;help;disks;reset' {
+            break
+        }
+        'kd;help;disks;snapshot' {
+            break
+        }
+        'kd;help;disks;restore' {
+            break
+        }
+        'kd;help;vm' {
+            [CompletionResult]::new('status', 'status', [CompletionResultType]::ParameterValue, 'Show whether the VM is running')
+            [CompletionResult]::new('stop', 'stop', [CompletionResultType]::ParameterValue, 'Shut the VM down')
+            [CompletionResult]::new('reset', 'reset', [CompletionResultType]::ParameterValue, 'Reset the VM as with the reset button')
+            [CompletionResult]::new('sysrq', 'sysrq', [CompletionResultType]::ParameterValue, 'Send Alt+SysRq+<key>, e.g. ''w'' for blocked tasks')
+            [CompletionResult]::new('dump', 'dump', [CompletionResultType]::ParameterValue, 'Dump guest memory as ELF core')
+            break
+        }
+        'kd;help;vm;status' {
+            break
+        }
+        'kd;help;vm;stop' {
+            break
+        }
+        'kd;help;vm;reset' {
+            break
+        }
+        'kd;help;vm;sysrq' {
+            break
+        }
+        'kd;help;vm;dump' {
+            break
+        }
+        'kd;help;dump' {
+            break
+        }
+        'kd;help;drgn' {
+            break
+        }
+        'kd;help;clean' {
+            break
+        }
+        'kd;help;doctor' {
+            break
+        }
         'kd;help;update' {
             break
         }
@@ -1,6 +1,6 @@
 # Print an optspec for argparse to handle cmd's options that are independent of any subcommand.
 function __fish_kd_global_optspecs
-    string join \n c/config= d/debug h/help V/version
+    string join \n c/config= d/debug offline h/help V/version
 end
 
 function __fish_kd_needs_command
@@ -26,32 +26,139 @@ end
 
 complete -c kd -n "__fish_kd_needs_command" -s c -l config -d 'Sets a custom config file' -r -F
 complete -c kd -n "__fish_kd_needs_command" -s d -l debug -d 'Turn debugging information on'
+complete -c kd -n "__fish_kd_needs_command" -l offline -d 'Don\'t access network, use only sources fetched before'
 complete -c kd -n "__fish_kd_needs_command" -s h -l help -d 'Print help'
 complete -c kd -n "__fish_kd_needs_command" -s V -l version -d 'Print version'
 complete -c kd -n "__fish_kd_needs_command" -f -a "init" -d 'Initialize development environment'
 complete -c kd -n "__fish_kd_needs_command" -f -a "build" -d 'Build image'
 complete -c kd -n "__fish_kd_needs_command" -f -a "run" -d 'Run QEMU test system'
+complete -c kd -n "__fish_kd_needs_command" -f -a "deploy" -d 'Deploy image to libvirt'
+complete -c kd -n "__fish_kd_needs_command" -f -a "results" -d 'Results of the last run'
+complete -c kd -n "__fish_kd_needs_command" -f -a "report" -d 'HTML report of the last run, or a matrix of several runs'
+complete -c kd -n "__fish_kd_needs_command" -f -a "disks" -d 'Manage VM disk images'
+complete -c kd -n "__fish_kd_needs_command" -f -a "vm" -d 'Control the running VM'
+complete -c kd -n "__fish_kd_needs_command" -f -a "dump" -d 'Save guest memory and vmlinux of the running VM for \'kd drgn\''
+complete -c kd -n "__fish_kd_needs_command" -f -a "drgn" -d 'Debug a memory dump with drgn'
+complete -c kd -n "__fish_kd_needs_command" -f -a "clean" -d 'Remove artifacts created by kd'
+complete -c kd -n "__fish_kd_needs_command" -f -a "doctor" -d 'Check that kd environment and dependencies are set up correctly'
 complete -c kd -n "__fish_kd_needs_command" -f -a "update" -d 'Update \'kd\' environment'
 complete -c kd -n "__fish_kd_needs_command" -f -a "config" -d 'Generate minimal kernel config for VM'
 complete -c kd -n "__fish_kd_needs_command" -f -a "debug" -d 'Developer tools'
 complete -c kd -n "__fish_kd_needs_command" -f -a "help" -d 'Print this message or the help of the given subcommand(s)'
+complete -c kd -n "__fish_kd_using_subcommand init" -l template -d 'Flake template to use, can be a local path' -r
+complete -c kd -n "__fish_kd_using_subcommand init" -l preset -d 'Starting config for .kd.toml' -r -f -a "xfs\t''
+ext4\t''
+script\t''"
+complete -c kd -n "__fish_kd_using_subcommand init" -l force -d 'Reinitialize environment and .kd.toml, keeps flake.lock and user modules'
+complete -c kd -n "__fish_kd_using_subcommand init" -l upgrade -d 'Update flake from the template, keeps flake.lock, .kd.toml and user modules'
 complete -c kd -n "__fish_kd_using_subcommand init" -s h -l help -d 'Print help'
 complete -c kd -n "__fish_kd_using_subcommand build" -l name -d 'Name of a test config to use' -r
-complete -c kd -n "__fish_kd_using_subcommand build" -s t -l target -d 'kd package to build' -r
+complete -c kd -n "__fish_kd_using_subcommand build" -s t -l target -d 'kd package to build, lists available packages without a value' -r
 complete -c kd -n "__fish_kd_using_subcommand build" -s h -l help -d 'Print help'
 complete -c kd -n "__fish_kd_using_subcommand run" -l name -d 'Name of a test config to use' -r
+complete -c kd -n "__fish_kd_using_subcommand run" -l fresh -d 'Start with a new root disk'
+complete -c kd -n "__fish_kd_using_subcommand run" -l image -d 'Boot the disk image (\'kd build\') instead of the VM'
+complete -c kd -n "__fish_kd_using_subcommand run" -l plain -d 'Show raw console instead of xfstests progress'
 complete -c kd -n "__fish_kd_using_subcommand run" -s h -l help -d 'Print help'
+complete -c kd -n "__fish_kd_using_subcommand deploy" -l name -d 'Name of a test config to use' -r
+complete -c kd -n "__fish_kd_using_subcommand deploy" -l uri -d 'libvirt connection URI, e.g. qemu+ssh://host/system' -r
+complete -c kd -n "__fish_kd_using_subcommand deploy" -l domain -d 'Domain name, kd-<directory>[-<name>] by default' -r
+complete -c kd -n "__fish_kd_using_subcommand deploy" -l pool -d 'Storage pool for the image volume' -r
+complete -c kd -n "__fish_kd_using_subcommand deploy" -l network -d 'libvirt network to attach, user networking if not set' -r
+complete -c kd -n "__fish_kd_using_subcommand deploy" -s h -l help -d 'Print help'
+complete -c kd -n "__fish_kd_using_subcommand results" -l name -d 'Name of a test config to use' -r
+complete -c kd -n "__fish_kd_using_subcommand results" -l format -d 'Merge xunit reports of all sections into one report' -r -f -a "junit\t''
+json\t''
+tap\t''
+markdown\t''"
+complete -c kd -n "__fish_kd_using_subcommand results" -l input -d 'Directory with results instead of the last run, can be repeated' -r
+complete -c kd -n "__fish_kd_using_subcommand results" -s o -l output -d 'Write report to the file instead of stdout' -r
+complete -c kd -n "__fish_kd_using_subcommand results" -l upload -d 'Upload results with backends from [results]'
+complete -c kd -n "__fish_kd_using_subcommand results" -s h -l help -d 'Print help'
+complete -c kd -n "__fish_kd_using_subcommand report" -l name -d 'Name of a test config to use' -r
+complete -c kd -n "__fish_kd_using_subcommand report" -l html -d 'Directory to write the HTML report to' -r
+complete -c kd -n "__fish_kd_using_subcommand report" -l input -d 'Directory with results of a run instead of the last run, can be repeated' -r
+complete -c kd -n "__fish_kd_using_subcommand report" -s h -l help -d 'Print help'
+complete -c kd -n "__fish_kd_using_subcommand disks; and not __fish_seen_subcommand_from list reset snapshot restore help" -s h -l help -d 'Print help'
+complete -c kd -n "__fish_kd_using_subcommand disks; and not __fish_seen_subcommand_from list reset snapshot restore help" -f -a "list" -d 'Show disk images and their sizes'
+complete -c kd -n "__fish_kd_using_subcommand disks; and not __fish_seen_subcommand_from list reset snapshot restore help" -f -a "reset" -d 'Remove root disk, next boot starts with a new one'
+complete -c kd -n "__fish_kd_using_subcommand disks; and not __fish_seen_subcommand_from list reset snapshot restore help" -f -a "snapshot" -d 'Save current state of the root disk'
+complete -c kd -n "__fish_kd_using_subcommand disks; and not __fish_seen_subcommand_from list reset snapshot restore help" -f -a "restore" -d 'Replace root disk with the snapshot'
+complete -c kd -n "__fish_kd_using_subcommand disks; and not __fish_seen_subcommand_from list reset snapshot restore help" -f -a "help" -d 'Print this message or the help of the given subcommand(s)'
+complete -c kd -n "__fish_kd_using_subcommand disks; and __fish_seen_subcommand_from list" -s h -l help -d 'Print help'
+complete -c kd -n "__fish_kd_using_subcommand disks; and __fish_seen_subcommand_from reset" -s h -l help -d 'Print help'
+complete -c kd -n "__fish_kd_using_subcommand disks; and __fish_seen_subcommand_from snapshot" -s h -l help -d 'Print help'
+complete -c kd -n "__fish_kd_using_subcommand disks; and __fish_seen_subcommand_from restore" -s h -l help -d 'Print help'
+complete -c kd -n "__fish_kd_using_subcommand disks; and __fish_seen_subcommand_from help" -f -a "list" -d 'Show disk images and their sizes'
+complete -c kd -n "__fish_kd_using_subcommand disks; and __fish_seen_subcommand_from help" -f -a "reset" -d 'Remove root disk, next boot starts with a new one'
+complete -c kd -n "__fish_kd_using_subcommand disks; and __fish_seen_subcommand_from help" -f -a "snapshot" -d 'Save current state of the root disk'
+complete -c kd -n "__fish_kd_using_subcommand disks; and __fish_seen_subcommand_from help" -f -a "restore" -d 'Replace root disk with the snapshot'
+complete -c kd -n "__fish_kd_using_subcommand disks; and __fish_seen_subcommand_from help" -f -a "help" -d 'Print this message or the help of the given subcommand(s)'
+complete -c kd -n "__fish_kd_using_subcommand vm; and not __fish_seen_subcommand_from status stop reset sysrq dump help" -s h -l help -d 'Print help'
+complete -c kd -n "__fish_kd_using_subcommand vm; and not __fish_seen_subcommand_from status stop reset sysrq dump help" -f -a "status" -d 'Show whether the VM is running'
+complete -c kd -n "__fish_kd_using_subcommand vm; and not __fish_seen_subcommand_from status stop reset sysrq dump help" -f -a "stop" -d 'Shut the VM down'
+complete -c kd -n "__fish_kd_using_subcommand vm; and not __fish_seen_subcommand_from status stop reset sysrq dump help" -f -a "reset" -d 'Reset the VM as with the reset button'
+complete -c kd -n "__fish_kd_using_subcommand vm; and not __fish_seen_subcommand_from status stop reset sysrq dump help" -f -a "sysrq" -d 'Send Alt+SysRq+<key>, e.g. \'w\' for blocked tasks'
+complete -c kd -n "__fish_kd_using_subcommand vm; and not __fish_seen_subcommand_from status stop reset sysrq dump help" -f -a "dump" -d 'Dump guest memory as ELF core'
+complete -c kd -n "__fish_kd_using_subcommand vm; and not __fish_seen_subcommand_from status stop reset sysrq dump help" -f -a "help" -d 'Print this message or the help of the given subcommand(s)'
+complete -c kd -n "__fish_kd_using_subcommand vm; and __fish_seen_subcommand_from status" -s h -l help -d 'Print help'
+complete -c kd -n "__fish_kd_using_subcommand vm; and __fish_seen_subcommand_from stop" -l force -d 'Kill QEMU instead of asking the guest to power off'
+complete -c kd -n "__fish_kd_using_subcommand vm; and __fish_seen_subcommand_from stop" -s h -l help -d 'Print help'
+complete -c kd -n "__fish_kd_using_subcommand vm; and __fish_seen_subcommand_from reset" -s h -l help -d 'Print help'
+complete -c kd -n "__fish_kd_using_subcommand vm; and __fish_seen_subcommand_from sysrq" -s h -l help -d 'Print help'
+complete -c kd -n "__fish_kd_using_subcommand vm; and __fish_seen_subcommand_from dump" -s h -l help -d 'Print help'
+complete -c kd -n "__fish_kd_using_subcommand vm; and __fish_seen_subcommand_from help" -f -a "status" -d 'Show whether the VM is running'
+complete -c kd -n "__fish_kd_using_subcommand vm; and __fish_seen_subcommand_from help" -f -a "stop" -d 'Shut the VM down'
+complete -c kd -n "__fish_kd_using_subcommand vm; and __fish_seen_subcommand_from help" -f -a "reset" -d 'Reset the VM as with the reset button'
+complete -c kd -n "__fish_kd_using_subcommand vm; and __fish_seen_subcommand_from help" -f -a "sysrq" -d 'Send Alt+SysRq+<key>, e.g. \'w\' for blocked tasks'
+complete -c kd -n "__fish_kd_using_subcommand vm; and __fish_seen_subcommand_from help" -f -a "dump" -d 'Dump guest memory as ELF core'
+complete -c kd -n "__fish_kd_using_subcommand vm; and __fish_seen_subcommand_from help" -f -a "help" -d 'Print this message or the help of the given subcommand(s)'
+complete -c kd -n "__fish_kd_using_subcommand dump" -l name -d 'Name of the test config the VM runs' -r
+complete -c kd -n "__fish_kd_using_subcommand dump" -s h -l help -d 'Print help'
+complete -c kd -n "__fish_kd_using_subcommand drgn" -l dump -d 'Dump directory [default: the latest in .kd/dumps]' -r -F
+complete -c kd -n "__fish_kd_using_subcommand drgn" -s s -l script -d 'Run the drgn script instead of the interactive shell' -r -F
+complete -c kd -n "__fish_kd_using_subcommand drgn" -s h -l help -d 'Print help'
+complete -c kd -n "__fish_kd_using_subcommand clean" -l older-than -d 'Only remove artifacts older than AGE (e.g. 12h, 7d, 2w)' -r
+complete -c kd -n "__fish_kd_using_subcommand clean" -l logs -d 'Remove execution logs'
+complete -c kd -n "__fish_kd_using_subcommand clean" -l images -d 'Remove disk images, snapshots and memory dumps'
+complete -c kd -n "__fish_kd_using_subcommand clean" -l results -d 'Remove build results and test results'
+complete -c kd -n "__fish_kd_using_subcommand clean" -l backups -d 'Remove .bup backups'
+complete -c kd -n "__fish_kd_using_subcommand clean" -l all -d 'Remove everything kd created'
+complete -c kd -n "__fish_kd_using_subcommand clean" -l dry-run -d 'Show what would be removed'
+complete -c kd -n "__fish_kd_using_subcommand clean" -l gc-roots -d 'Remove \'result\' links \'kd build\' created in the worktree'
+complete -c kd -n "__fish_kd_using_subcommand clean" -s h -l help -d 'Print help'
+complete -c kd -n "__fish_kd_using_subcommand doctor" -s h -l help -d 'Print help'
 complete -c kd -n "__fish_kd_using_subcommand update" -s h -l help -d 'Print help'
 complete -c kd -n "__fish_kd_using_subcommand config" -s o -l output -d 'Output filename' -r
 complete -c kd -n "__fish_kd_using_subcommand config" -l name -d 'Name of a test config to use' -r
+complete -c kd -n "__fish_kd_using_subcommand config" -l diff -d 'Show changes to the output config (diffconfig format) instead of writing it'
+complete -c kd -n "__fish_kd_using_subcommand config" -l merge -d 'Merge required options into the existing config and run \'make olddefconfig\''
 complete -c kd -n "__fish_kd_using_subcommand config" -s h -l help -d 'Print help'
 complete -c kd -n "__fish_kd_using_subcommand debug" -l name -d 'Name of a config to use' -r
 complete -c kd -n "__fish_kd_using_subcommand debug" -s c -l config -d 'Output config'
 complete -c kd -n "__fish_kd_using_subcommand debug" -s h -l help -d 'Print help'
-complete -c kd -n "__fish_kd_using_subcommand help; and not __fish_seen_subcommand_from init build run update config debug help" -f -a "init" -d 'Initialize development environment'
-complete -c kd -n "__fish_kd_using_subcommand help; and not __fish_seen_subcommand_from init build run update config debug help" -f -a "build" -d 'Build image'
-complete -c kd -n "__fish_kd_using_subcommand help; and not __fish_seen_subcommand_from init build run update config debug help" -f -a "run" -d 'Run QEMU test system'
-complete -c kd -n "__fish_kd_using_subcommand help; and not __fish_seen_subcommand_from init build run update config debug help" -f -a "update" -d 'Update \'kd\' environment'
-complete -c kd -n "__fish_kd_using_subcommand help; and not __fish_seen_subcommand_from init build run update config debug help" -f -a "config" -d 'Generate minimal kernel config for VM'
-complete -c kd -n "__fish_kd_using_subcommand help; and not __fish_seen_subcommand_from init build run update config debug help" -f -a "debug" -d 'Developer tools'
-complete -c kd -n "__fish_kd_using_subcommand help; and not __fish_seen_subcommand_from init build run update config debug help" -f -a "help" -d 'Print this message or the help of the given subcommand(s)'
+complete -c kd -n "__fish_kd_using_subcommand help; and not __fish_seen_subcommand_from init build run deploy results report disks vm dump drgn clean doctor update config debug help" -f -a "init" -d 'Initialize development environment'
+complete -c kd -n "__fish_kd_using_subcommand help; and not __fish_seen_subcommand_from init build run deploy results report disks vm dump drgn clean doctor update config debug help" -f -a "build" -d 'Build image'
+complete -c kd -n "__fish_kd_using_subcommand help; and not __fish_seen_subcommand_from init build run deploy results report disks vm dump drgn clean doctor update config debug help" -f -a "run" -d 'Run QEMU test system'
+complete -c kd -n "__fish_kd_using_subcommand help; and not __fish_seen_subcommand_from init build run deploy results report disks vm dump drgn clean doctor update config debug help" -f -a "deploy" -d 'Deploy image to libvirt'
+complete -c kd -n "__fish_kd_using_subcommand help; and not __fish_seen_subcommand_from init build run deploy results report disks vm dump drgn clean doctor update config debug help" -f -a "results" -d 'Results of the last run'
+complete -c kd -n "__fish_kd_using_subcommand help; and not __fish_seen_subcommand_from init build run deploy results report disks vm dump drgn clean doctor update config debug help" -f -a "report" -d 'HTML report of the last run, or a matrix of several runs'
+complete -c kd -n "__fish_kd_using_subcommand help; and not __fish_seen_subcommand_from init build run deploy results report disks vm dump drgn clean doctor update config debug help" -f -a "disks" -d 'Manage VM disk images'
+complete -c kd -n "__fish_kd_using_subcommand help; and not __fish_seen_subcommand_from init build run deploy results report disks vm dump drgn clean doctor update config debug help" -f -a "vm" -d 'Control the running VM'
+complete -c kd -n "__fish_kd_using_subcommand help; and not __fish_seen_subcommand_from init build run deploy results report disks vm dump drgn clean doctor update config debug help" -f -a "dump" -d 'Save guest memory and vmlinux of the running VM for \'kd drgn\''
+complete -c kd -n "__fish_kd_using_subcommand help; and not __fish_seen_subcommand_from init build run deploy results report disks vm dump drgn clean doctor update config debug help" -f -a "drgn" -d 'Debug a memory dump with drgn'
+complete -c kd -n "__fish_kd_using_subcommand help; and not __fish_seen_subcommand_from init build run deploy results report disks vm dump drgn clean doctor update config debug help" -f -a "clean" -d 'Remove artifacts created by kd'
+complete -c kd -n "__fish_kd_using_subcommand help; and not __fish_seen_subcommand_from init build run deploy results report disks vm dump drgn clean doctor update config debug help" -f -a "doctor" -d 'Check that kd environment and dependencies are set up correctly'
+complete -c kd -n "__fish_kd_using_subcommand help; and not __fish_seen_subcommand_from init build run deploy results report disks vm dump drgn clean doctor update config debug help" -f -a "update" -d 'Update \'kd\' environment'
+complete -c kd -n "__fish_kd_using_subcommand help; and not __fish_seen_subcommand_from init build run deploy results report disks vm dump drgn clean doctor update config debug help" -f -a "config" -d 'Generate minimal kernel config for VM'
+complete -c kd -n "__fish_kd_using_subcommand help; and not __fish_seen_subcommand_from init build run deploy results report disks vm dump drgn clean doctor update config debug help" -f -a "debug" -d 'Developer tools'
+complete -c kd -n "__fish_kd_using_subcommand help; and not __fish_seen_subcommand_from init build run deploy results report disks vm dump drgn clean doctor update config debug help" -f -a "help" -d 'Print this message or the help of the given subcommand(s)'
+complete -c kd -n "__fish_kd_using_subcommand help; and __fish_seen_subcommand_from disks" -f -a "list" -d 'Show disk images and their sizes'
+complete -c kd -n "__fish_kd_using_subcommand help; and __fish_seen_subcommand_from disks" -f -a "reset" -d 'Remove root disk, next boot starts with a new one'
+complete -c kd -n "__fish_kd_using_subcommand help; and __fish_seen_subcommand_from disks" -f -a "snapshot" -d 'Save current state of the root disk'
+complete -c kd -n "__fish_kd_using_subcommand help; and __fish_seen_subcommand_from disks" -f -a "restore" -d 'Replace root disk with the snapshot'
+complete -c kd -n "__fish_kd_using_subcommand help; and __fish_seen_subcommand_from vm" -f -a "status" -d 'Show whether the VM is running'
+complete -c kd -n "__fish_kd_using_subcommand help; and __fish_seen_subcommand_from vm" -f -a "stop" -d 'Shut the VM down'
+complete -c kd -n "__fish_kd_using_subcommand help; and __fish_seen_subcommand_from vm" -f -a "reset" -d 'Reset the VM as with the reset button'
+complete -c kd -n "__fish_kd_using_subcommand help; and __fish_seen_subcommand_from vm" -f -a "sysrq" -d 'Send Alt+SysRq+<key>, e.g. \'w\' for blocked tasks'
+complete -c kd -n "__fish_kd_using_subcommand help; and __fish_seen_subcommand_from vm" -f -a "dump" -d 'Dump guest memory as ELF core'
@@ -0,0 +1,271 @@
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use crate::{dump, State};
+
+/// List of 'result' links 'kd build' created in the worktree, in envdir
+pub const OUT_LINKS: &str = "out-links";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ArtifactKind {
+    /// execution_*.log console logs in the share dir
+    Log,
+    /// Root disk, its snapshots and memory dumps
+    Image,
+    /// 'nix build' result links (GC roots) and results of the last run
+    Result,
+    /// .bup backups of .config, .kd.toml and .envrc
+    Backup,
+    /// Anything else in the share dir
+    Share,
+}
+
+impl ArtifactKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ArtifactKind::Log => "log",
+            ArtifactKind::Image => "image",
+            ArtifactKind::Result => "result",
+            ArtifactKind::Backup => "backup",
+            ArtifactKind::Share => "share",
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Selection {
+    pub logs: bool,
+    pub images: bool,
+    pub results: bool,
+    pub backups: bool,
+    pub all: bool,
+    /// 'result' links of 'kd build' in the worktree, see gc_roots()
+    pub gc_roots: bool,
+}
+
+impl Selection {
+    pub fn is_empty(&self) -> bool {
+        !(self.logs
+            || self.images
+            || self.results
+            || self.backups
+            || self.all
+            || self.gc_roots)
+    }
+
+    pub fn includes(&self, kind: ArtifactKind) -> bool {
+        self.all
+            || match kind {
+                ArtifactKind::Log => self.logs,
+                ArtifactKind::Image => self.images,
+                ArtifactKind::Result => self.results,
+                ArtifactKind::Backup => self.backups,
+                ArtifactKind::Share => false,
+            }
+    }
+}
+
+pub struct Artifact {
+    pub kind: ArtifactKind,
+    pub path: PathBuf,
+    pub size: u64,
+    pub modified: SystemTime,
+}
+
+impl Artifact {
+    fn new(kind: ArtifactKind, path: PathBuf) -> Result<Self> {
+        let metadata = fs::symlink_metadata(&path)
+            .with_context(|| format!("Failed to read metadata of {}", path.display()))?;
+
+        Ok(Self {
+            kind,
+            size: disk_usage(&path),
+            modified: metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH),
+            path,
+        })
+    }
+
+    pub fn is_older_than(&self, age: Duration) -> bool {
+        match SystemTime::now().duration_since(self.modified) {
+            Ok(elapsed) => elapsed > age,
+            Err(_) => false,
+        }
+    }
+}
+
+/// Size of a file or a directory, symlinks are not followed
+fn disk_usage(path: &Path) -> u64 {
+    let Ok(metadata) = fs::symlink_metadata(path) else {
+        return 0;
+    };
+
+    if !metadata.is_dir() {
+        return metadata.len();
+    }
+
+    fs::read_dir(path)
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| disk_usage(&entry.path()))
+                .sum()
+        })
+        .unwrap_or(0)
+}
+
+fn entries(dir: &Path) -> Result<Vec<PathBuf>> {
+    if !dir.is_dir() {
+        return Ok(vec![]);
+    }
+
+    let mut entries: Vec<PathBuf> = fs::read_dir(dir)
+        .with_context(|| format!("Failed to read {}", dir.display()))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .collect();
+    entries.sort();
+
+    Ok(entries)
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+/// Everything kd leaves behind in the environment directory and the backups
+/// it makes next to it
+pub fn artifacts(state: &State) -> Result<Vec<Artifact>> {
+    let mut artifacts = vec![];
+
+    for path in entries(&state.envdir)? {
+        let name = file_name(&path);
+        if name.starts_with("result") && path.is_symlink() {
+            artifacts.push(Artifact::new(ArtifactKind::Result, path)?);
+        } else if name.ends_with(".qcow2") {
+            artifacts.push(Artifact::new(ArtifactKind::Image, path)?);
+        }
+    }
+
+    for path in entries(&state.envdir.join("snapshots"))? {
+        if file_name(&path).ends_with(".qcow2") {
+            artifacts.push(Artifact::new(ArtifactKind::Image, path)?);
+        }
+    }
+
+    for path in entries(&dump::dumps_dir(&state.envdir))? {
+        artifacts.push(Artifact::new(ArtifactKind::Image, path)?);
+    }
+
+    for path in entries(&state.envdir.join("share"))? {
+        let name = file_name(&path);
+        let kind = if name.starts_with("execution_") && name.ends_with(".log") {
+            ArtifactKind::Log
+        } else if name == "results" {
+            ArtifactKind::Result
+        } else {
+            ArtifactKind::Share
+        };
+        artifacts.push(Artifact::new(kind, path)?);
+    }
+
+    for name in [".config.bup", ".envrc.bup"] {
+        let path = state.curdir.join(name);
+        if path.exists() {
+            artifacts.push(Artifact::new(ArtifactKind::Backup, path)?);
+        }
+    }
+
+    // Numbered backups made by 'kd config' and 'kd init --force',
+    // .config.1.bup, .kd.toml.1.bup...
+    for path in entries(&state.curdir)? {
+        let name = file_name(&path);
+        let numbered = [".config.", ".kd.toml."]
+            .iter()
+            .any(|prefix| name.starts_with(prefix));
+        if numbered && name.ends_with(".bup") && name != ".config.bup" {
+            artifacts.push(Artifact::new(ArtifactKind::Backup, path)?);
+        }
+    }
+
+    Ok(artifacts)
+}
+
+/// Parse age such as "30m", "12h", "7d" or "2w". Numbers without suffix are
+/// days.
+pub fn parse_age(age: &str) -> Result<Duration> {
+    let age = age.trim();
+    let (number, seconds) = match age.char_indices().last() {
+        Some((index, 's')) => (&age[..index], 1),
+        Some((index, 'm')) => (&age[..index], 60),
+        Some((index, 'h')) => (&age[..index], 60 * 60),
+        Some((index, 'd')) => (&age[..index], 24 * 60 * 60),
+        Some((index, 'w')) => (&age[..index], 7 * 24 * 60 * 60),
+        _ => (age, 24 * 60 * 60),
+    };
+
+    let number: u64 = number
+        .trim()
+        .parse()
+        .with_context(|| format!("Unable to parse age '{}'", age))?;
+
+    Ok(Duration::from_secs(number * seconds))
+}
+
+pub fn remove(artifact: &Artifact) -> Result<()> {
+    let path = &artifact.path;
+    let metadata = match fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(_) => return Ok(()),
+    };
+
+    if metadata.is_dir() {
+        fs::remove_dir_all(path)
+    } else {
+        fs::remove_file(path)
+    }
+    .with_context(|| format!("Failed to remove {}", path.display()))
+}
+
+fn out_links(envdir: &Path) -> Vec<PathBuf> {
+    fs::read_to_string(envdir.join(OUT_LINKS))
+        .map(|data| data.lines().map(PathBuf::from).collect())
+        .unwrap_or_default()
+}
+
+/// Remember 'result' links in the worktree pointing to `outputs` of the build
+/// which just finished, so gc_roots() doesn't touch links made by hand
+pub fn record_gc_roots(curdir: &Path, envdir: &Path, outputs: &[PathBuf]) -> Result<()> {
+    let mut links = out_links(envdir);
+
+    for path in entries(curdir)? {
+        let built = fs::read_link(&path).is_ok_and(|target| outputs.contains(&target));
+        if file_name(&path).starts_with("result") && built && !links.contains(&path) {
+            links.push(path);
+        }
+    }
+
+    let data: String = links
+        .iter()
+        .map(|link| format!("{}\n", link.display()))
+        .collect();
+    let path = envdir.join(OUT_LINKS);
+    fs::write(&path, data).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// 'result' links 'kd build' left in the worktree. They are GC roots of the
+/// builds, Nix drops its own reference to them once the link is gone.
+pub fn gc_roots(state: &State) -> Result<Vec<Artifact>> {
+    let mut links = vec![];
+
+    for path in out_links(&state.envdir) {
+        let into_store = fs::read_link(&path).is_ok_and(|target| target.starts_with("/nix/store"));
+        if into_store {
+            links.push(Artifact::new(ArtifactKind::Result, path)?);
+        }
+    }
+
+    Ok(links)
+}
@@ -14,6 +14,10 @@ pub struct Cli {
     #[arg(short, long)]
     pub debug: bool,
 
+    /// Don't access network, use only sources fetched before
+    #[arg(long)]
+    pub offline: bool,
+
     #[command(subcommand)]
     pub command: Option<Commands>,
 }
@@ -22,22 +26,130 @@ pub struct Cli {
 #[derive(Subcommand)]
 pub enum Commands {
     /// Initialize development environment
-    Init {},
+    Init {
+        #[arg(long, action = clap::ArgAction::SetTrue, help = "Reinitialize environment and .kd.toml, keeps flake.lock and user modules")]
+        force: bool,
+        #[arg(long, action = clap::ArgAction::SetTrue, conflicts_with = "force", help = "Update flake from the template, keeps flake.lock, .kd.toml and user modules")]
+        upgrade: bool,
+        #[arg(long, value_name = "FLAKEREF", help = "Flake template to use, can be a local path")]
+        template: Option<String>,
+        #[arg(long, value_parser = ["xfs", "ext4", "script"], help = "Starting config for .kd.toml")]
+        preset: Option<String>,
+    },
 
     /// Build image
     Build {
         #[arg(long, help = "Name of a test config to use")]
         name: Option<String>,
-        #[arg(short, long, help = "kd package to build")]
-        target: Option<String>,
+        #[arg(
+            short,
+            long,
+            help = "kd package to build, lists available packages without a value"
+        )]
+        target: Option<Option<String>>,
     },
 
     /// Run QEMU test system
     Run {
         #[arg(long, help = "Name of a test config to use")]
         name: Option<String>,
+        #[arg(long, action = clap::ArgAction::SetTrue, help = "Start with a new root disk")]
+        fresh: bool,
+        #[arg(long, action = clap::ArgAction::SetTrue, help = "Boot the disk image ('kd build') instead of the VM")]
+        image: bool,
+        #[arg(long, action = clap::ArgAction::SetTrue, help = "Show raw console instead of xfstests progress")]
+        plain: bool,
+    },
+
+    /// Deploy image to libvirt
+    Deploy {
+        #[arg(long, help = "Name of a test config to use")]
+        name: Option<String>,
+        #[arg(long, default_value = "qemu:///session", help = "libvirt connection URI, e.g. qemu+ssh://host/system")]
+        uri: String,
+        #[arg(long, help = "Domain name, kd-<directory>[-<name>] by default")]
+        domain: Option<String>,
+        #[arg(long, default_value = "default", help = "Storage pool for the image volume")]
+        pool: String,
+        #[arg(long, help = "libvirt network to attach, user networking if not set")]
+        network: Option<String>,
     },
 
+    /// Results of the last run
+    Results {
+        #[arg(long, help = "Name of a test config to use")]
+        name: Option<String>,
+        #[arg(long, action = clap::ArgAction::SetTrue, help = "Upload results with backends from [results]")]
+        upload: bool,
+        #[arg(long, value_parser = ["junit", "json", "tap", "markdown"], help = "Merge xunit reports of all sections into one report")]
+        format: Option<String>,
+        #[arg(long, help = "Directory with results instead of the last run, can be repeated")]
+        input: Vec<String>,
+        #[arg(short, long, help = "Write report to the file instead of stdout")]
+        output: Option<String>,
+    },
+
+    /// HTML report of the last run, or a matrix of several runs
+    Report {
+        #[arg(long, help = "Name of a test config to use")]
+        name: Option<String>,
+        #[arg(long, value_name = "DIR", help = "Directory to write the HTML report to")]
+        html: String,
+        #[arg(long, help = "Directory with results of a run instead of the last run, can be repeated")]
+        input: Vec<String>,
+    },
+
+    /// Manage VM disk images
+    Disks {
+        #[command(subcommand)]
+        command: DisksCommands,
+    },
+
+    /// Control the running VM
+    Vm {
+        #[command(subcommand)]
+        command: VmCommands,
+    },
+
+    /// Save guest memory and vmlinux of the running VM for 'kd drgn'
+    Dump {
+        #[arg(long, help = "Name of the test config the VM runs")]
+        name: Option<String>,
+    },
+
+    /// Debug a memory dump with drgn
+    Drgn {
+        #[arg(long, value_name = "DIR", help = "Dump directory [default: the latest in .kd/dumps]")]
+        dump: Option<PathBuf>,
+        #[arg(short, long, value_name = "SCRIPT", help = "Run the drgn script instead of the interactive shell")]
+        script: Option<PathBuf>,
+        #[arg(requires = "script", trailing_var_arg = true, allow_hyphen_values = true, help = "Arguments of the script")]
+        args: Vec<String>,
+    },
+
+    /// Remove artifacts created by kd
+    Clean {
+        #[arg(long, action = clap::ArgAction::SetTrue, help = "Remove execution logs")]
+        logs: bool,
+        #[arg(long, action = clap::ArgAction::SetTrue, help = "Remove disk images, snapshots and memory dumps")]
+        images: bool,
+        #[arg(long, action = clap::ArgAction::SetTrue, help = "Remove build results and test results")]
+        results: bool,
+        #[arg(long, action = clap::ArgAction::SetTrue, help = "Remove .bup backups")]
+        backups: bool,
+        #[arg(long, action = clap::ArgAction::SetTrue, help = "Remove everything kd created")]
+        all: bool,
+        #[arg(long, value_name = "AGE", help = "Only remove artifacts older than AGE (e.g. 12h, 7d, 2w)")]
+        older_than: Option<String>,
+        #[arg(long, action = clap::ArgAction::SetTrue, help = "Show what would be removed")]
+        dry_run: bool,
+        #[arg(long, action = clap::ArgAction::SetTrue, help = "Remove 'result' links 'kd build' created in the worktree")]
+        gc_roots: bool,
+    },
+
+    /// Check that kd environment and dependencies are set up correctly
+    Doctor {},
+
     /// Update 'kd' environment
     Update {},
 
@@ -47,6 +159,10 @@ pub enum Commands {
         output: Option<String>,
         #[arg(long, help = "Name of a test config to use")]
         name: Option<String>,
+        #[arg(long, action = clap::ArgAction::SetTrue, help = "Show changes to the output config (diffconfig format) instead of writing it")]
+        diff: bool,
+        #[arg(long, action = clap::ArgAction::SetTrue, help = "Merge required options into the existing config and run 'make olddefconfig'")]
+        merge: bool,
     },
 
     /// Developer tools
@@ -57,3 +173,51 @@ pub enum Commands {
         name: Option<String>,
     },
 }
+
+#[derive(Subcommand)]
+pub enum DisksCommands {
+    /// Show disk images and their sizes
+    List {},
+
+    /// Remove root disk, next boot starts with a new one
+    Reset {},
+
+    /// Save current state of the root disk
+    Snapshot {
+        #[arg(help = "Name of the snapshot")]
+        name: String,
+    },
+
+    /// Replace root disk with the snapshot
+    Restore {
+        #[arg(help = "Name of the snapshot")]
+        name: String,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum VmCommands {
+    /// Show whether the VM is running
+    Status {},
+
+    /// Shut the VM down
+    Stop {
+        #[arg(long, action = clap::ArgAction::SetTrue, help = "Kill QEMU instead of asking the guest to power off")]
+        force: bool,
+    },
+
+    /// Reset the VM as with the reset button
+    Reset {},
+
+    /// Send Alt+SysRq+<key>, e.g. 'w' for blocked tasks
+    Sysrq {
+        #[arg(help = "SysRq key, a-z or 0-9")]
+        key: char,
+    },
+
+    /// Dump guest memory as ELF core
+    Dump {
+        #[arg(help = "Output file [default: .kd/vmcore]")]
+        output: Option<PathBuf>,
+    },
+}
@@ -1,10 +1,14 @@
 use anyhow::{bail, Context, Result};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::fs;
-use std::path::{absolute, Path, PathBuf};
+use std::path::{absolute, Path};
+use std::time::Duration;
 use toml;
 use toml::Table;
 
+use crate::{clean, hooks, kconfig, share, upload};
+
 #[derive(Serialize, Deserialize, Clone, Default, Debug)]
 pub struct KernelConfigOption {
     pub name: String,
@@ -21,11 +25,16 @@ pub struct KernelHeaders {
 #[derive(Serialize, Deserialize, Default, Clone, Debug)]
 pub struct KernelConfig {
     pub prebuild: Option<String>,
+    pub source: Option<String>,
     pub version: Option<String>,
     pub rev: Option<String>,
     pub repo: Option<String>,
     pub flavors: Option<Vec<String>>,
     pub config: Option<Table>,
+    /// Staging directory (INSTALL_MOD_PATH) with modules of the prebuild kernel
+    pub modules: Option<String>,
+    /// Run 'make modules_install' into `modules` before every run
+    pub modules_install: Option<bool>,
 }
 
 #[derive(Serialize, Deserialize, Default, Clone, Debug)]
@@ -40,18 +49,170 @@ pub struct XfstestsDevices {
 
 #[derive(Serialize, Deserialize, Default, Clone, Debug)]
 pub struct XfstestsConfig {
+    pub path: Option<String>,
     pub repo: Option<String>,
     pub rev: Option<String>,
     pub devices: Option<XfstestsDevices>,
     pub args: Option<String>,
     pub extra_env: Option<String>,
     pub filesystem: Option<String>,
+    /// Directory with start/ and end/ hooks, copied to the share dir
     pub hooks: Option<String>,
+    /// Hooks shipped with kd, e.g. "dmesg", "slabinfo" or "trace"
+    pub builtin_hooks: Option<Vec<String>>,
     pub kernel_headers: Option<KernelHeaders>,
+    pub timeouts: Option<XfstestsTimeouts>,
+}
+
+/// How long a single test may run before kd collects diagnostics, e.g. "30m"
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+pub struct XfstestsTimeouts {
+    pub default: Option<String>,
+    /// By xfstests group, e.g. stress = "1h"
+    pub groups: Option<BTreeMap<String, String>>,
+    /// By test directory, e.g. generic = "1h", these are not xfstests groups
+    pub dirs: Option<BTreeMap<String, String>>,
+    /// By test, e.g. "generic/475" = "2h"
+    pub tests: Option<BTreeMap<String, String>>,
+    /// Reset the VM after collecting diagnostics and run the remaining tests
+    pub reboot: Option<bool>,
+}
+
+impl XfstestsTimeouts {
+    /// Timeout of the test in `groups`. The one set for the test wins, then
+    /// the longest one of its groups, then its directory.
+    pub fn timeout(&self, test: &str, groups: &[String]) -> Result<Option<Duration>> {
+        let parse = |timeout: &String| {
+            parse_timeout(timeout)
+                .with_context(|| format!("Invalid timeout '{}' of {}", timeout, test))
+        };
+
+        if let Some(timeout) = self.tests.as_ref().and_then(|tests| tests.get(test)) {
+            return parse(timeout).map(Some);
+        }
+
+        let mut longest = None;
+        for timeout in groups
+            .iter()
+            .filter_map(|group| self.groups.as_ref()?.get(group))
+        {
+            longest = longest.max(Some(parse(timeout)?));
+        }
+        if longest.is_some() {
+            return Ok(longest);
+        }
+
+        let dir = test.split_once('/').map_or(test, |(dir, _)| dir);
+        self.dirs
+            .as_ref()
+            .and_then(|dirs| dirs.get(dir))
+            .or(self.default.as_ref())
+            .map(parse)
+            .transpose()
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        let all = self
+            .default
+            .iter()
+            .chain(self.groups.iter().flat_map(|groups| groups.values()))
+            .chain(self.dirs.iter().flat_map(|dirs| dirs.values()))
+            .chain(self.tests.iter().flat_map(|tests| tests.values()));
+        for timeout in all {
+            if parse_timeout(timeout)?.is_zero() {
+                bail!("Timeout '{}' has to be longer than zero", timeout);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Parse timeout such as "90", "30m" or "2h", numbers without suffix are
+/// seconds
+pub fn parse_timeout(timeout: &str) -> Result<Duration> {
+    let timeout = timeout.trim();
+    if !timeout.is_empty() && timeout.chars().all(|c| c.is_ascii_digit()) {
+        return Ok(Duration::from_secs(timeout.parse()?));
+    }
+
+    clean::parse_age(timeout)
+}
+
+/// trace-cmd recording around every xfstests test, traces are saved as
+/// <test>.trace.dat in the results
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+pub struct TraceConfig {
+    /// As in 'trace-cmd start -e', e.g. "xfs:*" or "block:block_rq_issue"
+    pub events: Vec<String>,
+    /// Ring buffer per CPU, e.g. "64M"
+    pub buffer: Option<String>,
+    /// "all" traces every test, "failed" re-runs failed tests with tracing
+    pub mode: Option<String>,
+}
+
+impl TraceConfig {
+    /// Ring buffer size in KB, as trace-cmd takes it
+    pub fn buffer_kb(&self) -> Result<Option<u64>> {
+        self.buffer
+            .as_ref()
+            .map(|buffer| parse_size(buffer).map(|megabytes| megabytes * 1024))
+            .transpose()
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if self.events.is_empty() {
+            bail!("'events' are empty, nothing to trace");
+        }
+
+        for event in &self.events {
+            let valid = !event.is_empty()
+                && event
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "_:*".contains(c));
+            if !valid {
+                bail!("Invalid event '{}', expected e.g. 'xfs:*'", event);
+            }
+        }
+
+        self.buffer_kb()?;
+
+        if let Some(mode) = &self.mode {
+            if mode != "all" && mode != "failed" {
+                bail!("Unknown mode '{}', expected 'all' or 'failed'", mode);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Host file or directory available in the VM, directories are mounted and
+/// files are copied to the share dir before every run
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+pub struct ShareEntry {
+    /// Relative to the directory with .kd.toml
+    pub source: String,
+    /// Path in the VM, /root/<name> by default
+    pub target: Option<String>,
+    /// Read-only by default, changes to read-write files are copied back
+    /// after the run
+    pub readonly: Option<bool>,
+}
+
+impl ShareEntry {
+    pub fn target(&self, name: &str) -> String {
+        self.target.clone().unwrap_or(format!("/root/{name}"))
+    }
+
+    pub fn readonly(&self) -> bool {
+        self.readonly.unwrap_or(true)
+    }
 }
 
 #[derive(Serialize, Deserialize, Default, Clone, Debug)]
 pub struct XfsprogsConfig {
+    pub path: Option<String>,
     pub repo: Option<String>,
     pub rev: Option<String>,
     pub kernel_headers: Option<KernelHeaders>,
@@ -67,16 +228,178 @@ pub struct QemuConfig {
     pub options: Option<Vec<String>>,
 }
 
+/// What the disk is used for in the VM. Roles match fields of [XfstestsDevices]
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum DiskRole {
+    Test,
+    TestRtdev,
+    TestLogdev,
+    Scratch,
+    ScratchRtdev,
+    ScratchLogdev,
+}
+
+impl DiskRole {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DiskRole::Test => "test",
+            DiskRole::TestRtdev => "test_rtdev",
+            DiskRole::TestLogdev => "test_logdev",
+            DiskRole::Scratch => "scratch",
+            DiskRole::ScratchRtdev => "scratch_rtdev",
+            DiskRole::ScratchLogdev => "scratch_logdev",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+pub struct VmDisk {
+    pub size: String,
+    pub role: Option<DiskRole>,
+}
+
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+pub struct VmConfig {
+    pub memory: Option<String>,
+    pub cpus: Option<u32>,
+    pub disk_size: Option<String>,
+    pub disks: Option<Vec<VmDisk>>,
+}
+
+impl VmConfig {
+    /// Device name of the n-th extra disk. The root disk is always /dev/vda and
+    /// extra disks are attached in the order they are listed in the config.
+    pub fn disk_device(index: usize) -> String {
+        format!("/dev/vd{}", (b'b' + index as u8) as char)
+    }
+
+    /// xfstests devices derived from the disk roles, None if no disk has a role
+    pub fn devices(&self) -> Option<XfstestsDevices> {
+        let disks = self.disks.as_ref()?;
+        let mut devices = XfstestsDevices::default();
+        let mut found = false;
+
+        for (index, disk) in disks.iter().enumerate() {
+            let Some(role) = disk.role else {
+                continue;
+            };
+            let device = Some(VmConfig::disk_device(index));
+            found = true;
+            match role {
+                DiskRole::Test => devices.test = device,
+                DiskRole::TestRtdev => devices.test_rtdev = device,
+                DiskRole::TestLogdev => devices.test_logdev = device,
+                DiskRole::Scratch => devices.scratch = device,
+                DiskRole::ScratchRtdev => devices.scratch_rtdev = device,
+                DiskRole::ScratchLogdev => devices.scratch_logdev = device,
+            }
+        }
+
+        if found {
+            Some(devices)
+        } else {
+            None
+        }
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if let Some(memory) = &self.memory {
+            parse_size(memory).context("Invalid 'memory' in [vm]")?;
+        }
+
+        if let Some(disk_size) = &self.disk_size {
+            parse_size(disk_size).context("Invalid 'disk_size' in [vm]")?;
+        }
+
+        if self.cpus == Some(0) {
+            bail!("[vm] 'cpus' has to be at least 1");
+        }
+
+        if let Some(disks) = &self.disks {
+            // vda is root disk, vdb..vdz are left for extra disks
+            if disks.len() > 25 {
+                bail!("Too many disks in [vm], at most 25 are supported");
+            }
+
+            let mut roles: Vec<DiskRole> = vec![];
+            for disk in disks {
+                parse_size(&disk.size)
+                    .with_context(|| format!("Invalid disk size '{}' in [vm]", disk.size))?;
+
+                if let Some(role) = disk.role {
+                    if roles.contains(&role) {
+                        bail!(
+                            "Disk role '{}' is used more than once in [vm]",
+                            role.as_str()
+                        );
+                    }
+                    roles.push(role);
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Parse size such as "4096", "512M", "12G" or "1T" into megabytes. Numbers
+/// without suffix are megabytes.
+pub fn parse_size(size: &str) -> Result<u64> {
+    let size = size.trim();
+    let (number, multiplier) = match size.char_indices().last() {
+        Some((index, 'K' | 'k')) => (&size[..index], 0),
+        Some((index, 'M' | 'm')) => (&size[..index], 1),
+        Some((index, 'G' | 'g')) => (&size[..index], 1024),
+        Some((index, 'T' | 't')) => (&size[..index], 1024 * 1024),
+        _ => (size, 1),
+    };
+
+    let number: u64 = number
+        .trim()
+        .parse()
+        .with_context(|| format!("Unable to parse size '{}'", size))?;
+
+    let megabytes = if multiplier == 0 {
+        number / 1024
+    } else {
+        number * multiplier
+    };
+
+    if megabytes == 0 {
+        bail!("Size '{}' is less than 1M", size);
+    }
+
+    Ok(megabytes)
+}
+
 #[derive(Serialize, Deserialize, Default, Clone, Debug)]
 pub struct SystemConfig {
+    pub packages: Option<Vec<String>>,
+    pub qemu: Option<QemuConfig>,
+    pub vm: Option<VmConfig>,
     pub kernel: Option<KernelConfig>,
     pub xfstests: Option<XfstestsConfig>,
     pub xfsprogs: Option<XfsprogsConfig>,
     pub script: Option<ScriptConfig>,
+    pub trace: Option<TraceConfig>,
+    pub share: Option<BTreeMap<String, ShareEntry>>,
 }
 
 impl SystemConfig {
     pub fn merge(&mut self, config: SystemConfig) -> &Self {
+        if let Some(packages) = config.packages {
+            self.packages = Some(packages);
+        }
+
+        if let Some(qemu) = config.qemu {
+            self.qemu = Some(qemu);
+        }
+
+        if let Some(vm) = config.vm {
+            self.vm = Some(vm);
+        }
+
         if let Some(kernel) = config.kernel {
             if self.kernel.is_none() {
                 self.kernel = Some(KernelConfig::default());
@@ -87,6 +410,10 @@ impl SystemConfig {
                     me.prebuild = Some(prebuild);
                 }
 
+                if let Some(source) = kernel.source {
+                    me.source = Some(source);
+                }
+
                 if let Some(version) = kernel.version {
                     me.version = Some(version);
                 }
@@ -106,6 +433,14 @@ impl SystemConfig {
                 if let Some(config) = kernel.config {
                     me.config = Some(config);
                 }
+
+                if let Some(modules) = kernel.modules {
+                    me.modules = Some(modules);
+                }
+
+                if let Some(modules_install) = kernel.modules_install {
+                    me.modules_install = Some(modules_install);
+                }
             }
         }
 
@@ -115,6 +450,10 @@ impl SystemConfig {
             }
 
             if let Some(me) = &mut self.xfstests {
+                if let Some(path) = xfstests.path {
+                    me.path = Some(path);
+                }
+
                 if let Some(repo) = xfstests.repo {
                     me.repo = Some(repo);
                 }
@@ -143,9 +482,17 @@ impl SystemConfig {
                     me.hooks = Some(hooks);
                 }
 
+                if let Some(builtin_hooks) = xfstests.builtin_hooks {
+                    me.builtin_hooks = Some(builtin_hooks);
+                }
+
                 if let Some(kernel_headers) = xfstests.kernel_headers {
                     me.kernel_headers = Some(kernel_headers);
                 }
+
+                if let Some(timeouts) = xfstests.timeouts {
+                    me.timeouts = Some(timeouts);
+                }
             }
         }
 
@@ -155,6 +502,10 @@ impl SystemConfig {
             }
 
             if let Some(me) = &mut self.xfsprogs {
+                if let Some(path) = xfsprogs.path {
+                    me.path = Some(path);
+                }
+
                 if let Some(repo) = xfsprogs.repo {
                     me.repo = Some(repo);
                 }
@@ -173,10 +524,76 @@ impl SystemConfig {
             self.script = Some(script);
         }
 
+        if let Some(trace) = config.trace {
+            self.trace = Some(trace);
+        }
+
+        // Entries are added to the ones of [share], same names replace them
+        if let Some(share) = config.share {
+            self.share.get_or_insert_with(BTreeMap::new).extend(share);
+        }
+
         self
     }
 }
 
+fn validate_source_path(name: &str, path: &str, has_rev: bool) -> Result<()> {
+    let curdir = std::env::current_dir().context("No able to get current working directory")?;
+    let source = absolute(curdir.join(path)).context("Failed to parse source path")?;
+    if !source.is_dir() {
+        bail!("{} source directory doesn't exist: {}", name, path);
+    }
+
+    if has_rev {
+        eprintln!("Note! [{}] 'path' is set, 'repo' and 'rev' are not used.", name);
+    }
+
+    Ok(())
+}
+
+/// Token for the upload backend, `token_env` is tried first
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+pub struct Credentials {
+    pub token_env: Option<String>,
+    pub token_file: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+pub struct GitUpload {
+    pub repo: String,
+    pub branch: Option<String>,
+    #[serde(flatten)]
+    pub credentials: Credentials,
+}
+
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+pub struct DirUpload {
+    pub path: String,
+    /// Put a .tar.gz instead of the directory
+    pub tarball: Option<bool>,
+}
+
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+pub struct HttpUpload {
+    /// Tarball is PUT to <url>/<layout>.tar.gz
+    pub url: String,
+    #[serde(flatten)]
+    pub credentials: Credentials,
+}
+
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+pub struct ResultsConfig {
+    /// Where results go in the backend, e.g. "<host>/<kernel>/<variant>/<date>"
+    pub layout: Option<String>,
+    /// Used as <host>, hostname of the machine by default
+    pub host: Option<String>,
+    /// Upload results after every 'kd run'
+    pub upload: Option<bool>,
+    pub git: Option<GitUpload>,
+    pub dir: Option<DirUpload>,
+    pub http: Option<HttpUpload>,
+}
+
 #[derive(Serialize, Deserialize, Default, Clone, Debug)]
 pub struct DevConfig {
     pub args: Option<Vec<String>>,
@@ -190,8 +607,15 @@ pub struct Config {
     pub xfsprogs: Option<XfsprogsConfig>,
     pub script: Option<ScriptConfig>,
     pub qemu: Option<QemuConfig>,
+    pub vm: Option<VmConfig>,
+    pub trace: Option<TraceConfig>,
+    pub share: Option<BTreeMap<String, ShareEntry>>,
+    /// Kernel config flavors which can be used in 'flavors' along with the
+    /// ones from the flake
+    pub kconfig_flavors: Option<BTreeMap<String, Table>>,
     pub common: Option<SystemConfig>,
     pub named: Option<Table>,
+    pub results: Option<ResultsConfig>,
     pub dev: Option<DevConfig>,
 }
 
@@ -204,17 +628,145 @@ impl Config {
     }
 
     pub fn validate(&self) -> Result<()> {
+        if let Some(subconfig) = &self.vm {
+            subconfig.validate()?;
+        }
+
         if let Some(subconfig) = &self.kernel {
-            let kernel = subconfig.version.is_some()
-                || subconfig.rev.is_some()
-                || subconfig.repo.is_some()
-                || subconfig.config.is_some();
+            let kernel =
+                subconfig.version.is_some() || subconfig.rev.is_some() || subconfig.repo.is_some();
             if subconfig.prebuild.is_some() && kernel {
-                println!("Note! You're using 'prebuild', none of the [kernel] options applies.");
+                eprintln!("Note! You're using 'prebuild', 'version', 'repo' and 'rev' are not used.");
+            }
+
+            if subconfig.prebuild.is_none()
+                && (subconfig.modules.is_some() || subconfig.modules_install.is_some())
+            {
+                bail!("'modules' can only be used with 'prebuild'");
+            }
+
+            if subconfig.prebuild.is_some() && subconfig.source.is_some() {
+                bail!("'prebuild' and 'source' can not be used together");
+            }
+
+            if let Some(source) = &subconfig.source {
+                let curdir =
+                    std::env::current_dir().context("No able to get current working directory")?;
+                let path = absolute(curdir.join(source)).context("Failed to parse source path")?;
+                if !path.join("Makefile").exists() {
+                    bail!("Kernel source doesn't look like a kernel tree: {}", source);
+                }
+
+                if subconfig.repo.is_some() || subconfig.rev.is_some() {
+                    eprintln!("Note! You're using 'source', 'repo' and 'rev' are not used.");
+                }
+            }
+        }
+
+        if let Some(config) = self.kernel.as_ref().and_then(|kernel| kernel.config.as_ref()) {
+            kconfig::requested(config).context("Invalid [kernel.config]")?;
+        }
+
+        if let Some(flavors) = &self.kconfig_flavors {
+            for (name, config) in flavors {
+                kconfig::requested(config)
+                    .with_context(|| format!("Invalid [kconfig_flavors.{}]", name))?;
+            }
+        }
+
+        if let Some(trace) = &self.trace {
+            trace.validate().context("Invalid [trace]")?;
+        }
+
+        let curdir = std::env::current_dir().context("No able to get current working directory")?;
+        if let Some(entries) = &self.share {
+            share::validate(entries, &curdir).context("Invalid [share]")?;
+        }
+
+        if let Some(vm) = self.common.as_ref().and_then(|common| common.vm.as_ref()) {
+            vm.validate().context("Invalid [common.vm]")?;
+        }
+
+        if let Some(entries) = self.common.as_ref().and_then(|common| common.share.as_ref()) {
+            share::validate(entries, &curdir).context("Invalid [common.share]")?;
+        }
+
+        if let Some(trace) = self.common.as_ref().and_then(|common| common.trace.as_ref()) {
+            trace.validate().context("Invalid [common.trace]")?;
+        }
+
+        if let Some(xfstests) = self.common.as_ref().and_then(|common| common.xfstests.as_ref()) {
+            if let Some(timeouts) = &xfstests.timeouts {
+                timeouts
+                    .validate()
+                    .context("Invalid [common.xfstests.timeouts]")?;
+            }
+
+            if let Some(hooks) = &xfstests.hooks {
+                hooks::validate(Path::new(hooks)).context("Invalid [common.xfstests] hooks")?;
+            }
+
+            if let Some(builtin_hooks) = &xfstests.builtin_hooks {
+                hooks::validate_builtin(builtin_hooks).context("Invalid [common.xfstests]")?;
+            }
+        }
+
+        let common = self.common.as_ref().and_then(|common| common.kernel.as_ref());
+        if let Some(config) = common.and_then(|kernel| kernel.config.as_ref()) {
+            kconfig::requested(config).context("Invalid [common.kernel.config]")?;
+        }
+
+        if let Some(named) = &self.named {
+            for (name, run) in named {
+                let run: SystemConfig = run
+                    .clone()
+                    .try_into()
+                    .with_context(|| format!("Invalid [named.{}]", name))?;
+                if let Some(vm) = &run.vm {
+                    vm.validate()
+                        .with_context(|| format!("Invalid [named.{}.vm]", name))?;
+                }
+
+                if let Some(config) = run.kernel.and_then(|kernel| kernel.config) {
+                    kconfig::requested(&config)
+                        .with_context(|| format!("Invalid [named.{}.kernel.config]", name))?;
+                }
+
+                if let Some(trace) = &run.trace {
+                    trace
+                        .validate()
+                        .with_context(|| format!("Invalid [named.{}.trace]", name))?;
+                }
+
+                if let Some(entries) = &run.share {
+                    share::validate(entries, &curdir)
+                        .with_context(|| format!("Invalid [named.{}.share]", name))?;
+                }
+
+                let xfstests = run.xfstests.unwrap_or_default();
+                if let Some(timeouts) = xfstests.timeouts {
+                    timeouts
+                        .validate()
+                        .with_context(|| format!("Invalid [named.{}.xfstests.timeouts]", name))?;
+                }
+
+                if let Some(hooks) = xfstests.hooks {
+                    hooks::validate(Path::new(&hooks))
+                        .with_context(|| format!("Invalid [named.{}.xfstests] hooks", name))?;
+                }
+
+                if let Some(builtin_hooks) = xfstests.builtin_hooks {
+                    hooks::validate_builtin(&builtin_hooks)
+                        .with_context(|| format!("Invalid [named.{}.xfstests]", name))?;
+                }
             }
         }
 
         if let Some(subconfig) = &self.xfsprogs {
+            if let Some(path) = &subconfig.path {
+                validate_source_path("xfsprogs", path, subconfig.rev.is_some())?;
+            }
+
             if let Some(subconfig) = &subconfig.kernel_headers {
                 if subconfig.repo.is_none() {
                     bail!("You are missing 'repo' parameter for kernel headers");
@@ -229,6 +781,10 @@ impl Config {
         }
 
         if let Some(subconfig) = &self.xfstests {
+            if let Some(path) = &subconfig.path {
+                validate_source_path("xfstests", path, subconfig.rev.is_some())?;
+            }
+
             if let Some(subconfig) = &subconfig.kernel_headers {
                 if subconfig.repo.is_none() {
                     bail!("You are missing 'repo' parameter for kernel headers");
@@ -241,13 +797,18 @@ impl Config {
                 }
             }
 
+            if let Some(timeouts) = &subconfig.timeouts {
+                timeouts
+                    .validate()
+                    .context("Invalid [xfstests.timeouts]")?;
+            }
+
             if let Some(hooks) = &subconfig.hooks {
-                let path = PathBuf::from(hooks);
-                if !path.exists() {
-                    let cwd = std::env::current_dir()
-                        .context("Failed to retrieve current working dir")?;
-                    bail!("Failed to find '{:?}' dir (cwd is {:?})", path, cwd);
-                }
+                hooks::validate(Path::new(hooks)).context("Invalid [xfstests] hooks")?;
+            }
+
+            if let Some(builtin_hooks) = &subconfig.builtin_hooks {
+                hooks::validate_builtin(builtin_hooks)?;
             }
         }
 
@@ -256,7 +817,7 @@ impl Config {
                 bail!("While using 'repo' rev/version need to be set");
             }
 
-            if subconfig.rev.is_some() && subconfig.version.is_none() {
+            if subconfig.rev.is_some() && subconfig.version.is_none() && subconfig.source.is_none() {
                 bail!("Revision can not be used without 'version'");
             }
 
@@ -272,6 +833,20 @@ impl Config {
             }
         }
 
-        return Ok(());
+        if let Some(results) = &self.results {
+            if let Some(layout) = &results.layout {
+                upload::validate_layout(layout).context("Invalid [results]")?;
+            }
+
+            if results.upload == Some(true)
+                && results.git.is_none()
+                && results.dir.is_none()
+                && results.http.is_none()
+            {
+                bail!("[results] 'upload' needs [results.git], [results.dir] or [results.http]");
+            }
+        }
+
+        Ok(())
     }
 }
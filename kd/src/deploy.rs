@@ -0,0 +1,357 @@
+use anyhow::{bail, Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::xml_escape;
+
+/// Partitions systemd-repart creates on the first boot of the image and
+/// their maximum sizes in megabytes, see systemd.repart.partitions in lib.nix
+/// and image.nix
+pub const PARTITIONS: &[(&str, u64)] = &[
+    ("test", 10 * 1024),
+    ("scratch", 10 * 1024),
+    ("rt", 5 * 1024),
+    ("log", 1024),
+    ("home", 3 * 1024),
+    ("var", 3 * 1024),
+];
+
+/// Namespace of kd metadata in the domain XML
+const METADATA_NS: &str = "https://github.com/alberand/kd";
+
+/// libvirt domain running kd image
+pub struct Domain {
+    pub name: String,
+    /// Megabytes
+    pub memory: u64,
+    pub cpus: u32,
+    pub disk: PathBuf,
+    /// libvirt network, user networking if not set
+    pub network: Option<String>,
+    /// Checksum of the deployed image
+    pub sha256: String,
+}
+
+/// 'virsh' connected to the libvirt `uri`
+pub struct Virsh {
+    pub uri: String,
+    pub debug: bool,
+}
+
+impl Virsh {
+    pub fn new(uri: &str, debug: bool) -> Self {
+        Self {
+            uri: uri.to_string(),
+            debug,
+        }
+    }
+
+    fn command(&self, args: &[&str]) -> Command {
+        let mut cmd = Command::new("virsh");
+        cmd.arg("--connect").arg(&self.uri).args(args);
+        if self.debug {
+            println!("command: {:?}", cmd);
+        }
+        cmd
+    }
+
+    /// Run virsh command and return its output
+    pub fn run(&self, args: &[&str]) -> Result<String> {
+        let output = self
+            .command(args)
+            .output()
+            .context("Failed to spawn 'virsh', is libvirt installed?")?;
+        if !output.status.success() {
+            bail!(
+                "'virsh {}' failed: {}",
+                args.join(" "),
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    }
+
+    /// Run virsh command which is fine to fail, e.g. to check that something
+    /// exists
+    pub fn check(&self, args: &[&str]) -> bool {
+        self.command(args)
+            .output()
+            .is_ok_and(|output| output.status.success())
+    }
+}
+
+/// Domain names end up in volume names and XML, keep them simple
+pub fn validate_name(name: &str) -> Result<()> {
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+    {
+        bail!(
+            "Invalid domain name '{}', use letters, digits, '-', '_' and '.'",
+            name
+        );
+    }
+
+    Ok(())
+}
+
+/// Volume capacity in megabytes, the image and free space for partitions
+/// systemd-repart creates
+pub fn capacity(image_size: u64) -> u64 {
+    let partitions: u64 = PARTITIONS.iter().map(|(_, size)| size).sum();
+    image_size.div_ceil(1024 * 1024) + partitions
+}
+
+pub fn domain_xml(domain: &Domain) -> String {
+    let interface = match &domain.network {
+        Some(network) => format!(
+            "    <interface type='network'>\n      <source network='{}'/>\n      <model type='virtio'/>\n    </interface>",
+            xml_escape(network)
+        ),
+        None => "    <interface type='user'>\n      <model type='virtio'/>\n    </interface>".to_string(),
+    };
+
+    // Image boots with systemd-boot which isn't signed, and systemd-repart in
+    // initrd expects /dev/sda
+    format!(
+        "<domain type='kvm'>
+  <name>{name}</name>
+  <metadata>
+    <kd:deploy xmlns:kd='{ns}'>
+      <kd:image sha256='{sha256}'/>
+    </kd:deploy>
+  </metadata>
+  <memory unit='MiB'>{memory}</memory>
+  <vcpu>{cpus}</vcpu>
+  <os firmware='efi'>
+    <type arch='x86_64' machine='q35'>hvm</type>
+    <firmware>
+      <feature enabled='no' name='secure-boot'/>
+    </firmware>
+    <boot dev='hd'/>
+  </os>
+  <features>
+    <acpi/>
+    <apic/>
+  </features>
+  <cpu mode='host-passthrough'/>
+  <devices>
+    <disk type='file' device='disk'>
+      <driver name='qemu' type='raw'/>
+      <source file='{disk}'/>
+      <target dev='sda' bus='sata'/>
+    </disk>
+{interface}
+    <serial type='pty'>
+      <target port='0'/>
+    </serial>
+    <console type='pty'>
+      <target type='serial' port='0'/>
+    </console>
+  </devices>
+</domain>
+",
+        name = xml_escape(&domain.name),
+        ns = METADATA_NS,
+        sha256 = xml_escape(&domain.sha256),
+        memory = domain.memory,
+        cpus = domain.cpus,
+        disk = xml_escape(&domain.disk.display().to_string()),
+        interface = interface,
+    )
+}
+
+/// Checksum of the image kd put into the domain XML
+pub fn deployed_sha256(xml: &str) -> Option<String> {
+    let start = xml.find("<kd:image")?;
+    let element = &xml[start..];
+    let element = &element[..element.find('>')?];
+    let value = element.split("sha256=").nth(1)?;
+    let quote = value.chars().next()?;
+    let value = &value[1..];
+
+    Some(value[..value.find(quote)?].to_string())
+}
+
+/// Raw disk image in the #image output
+pub fn find_image(output: &Path) -> Result<PathBuf> {
+    let mut images: Vec<PathBuf> = fs::read_dir(output)
+        .with_context(|| format!("Failed to read {}", output.display()))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "raw"))
+        .collect();
+    images.sort();
+
+    match images.into_iter().next() {
+        Some(image) => Ok(image),
+        None => bail!("There's no raw image in {}", output.display()),
+    }
+}
+
+/// Checksum from <image>.sha256sum made by the #image build, or computed if
+/// there's none
+pub fn image_sha256(image: &Path) -> Result<String> {
+    let mut sumfile = image.as_os_str().to_owned();
+    sumfile.push(".sha256sum");
+    let data = match fs::read_to_string(&sumfile) {
+        Ok(data) => data,
+        Err(_) => {
+            let output = Command::new("sha256sum")
+                .arg(image)
+                .output()
+                .context("Failed to spawn 'sha256sum'")?;
+            if !output.status.success() {
+                bail!("Failed to compute checksum of {}", image.display());
+            }
+            String::from_utf8_lossy(&output.stdout).to_string()
+        }
+    };
+
+    match data.split_whitespace().next() {
+        Some(sha256) => Ok(sha256.to_string()),
+        None => bail!("Invalid checksum of {}", image.display()),
+    }
+}
+
+pub struct Deployment<'a> {
+    pub virsh: &'a Virsh,
+    pub name: String,
+    pub pool: String,
+    pub network: Option<String>,
+    pub memory: u64,
+    pub cpus: u32,
+    /// Where domain XML is written before it's defined
+    pub xml: PathBuf,
+}
+
+/// Stop and undefine the domain, if `volume` is set also delete its volume
+pub fn remove(deployment: &Deployment, volume: bool) -> Result<()> {
+    let virsh = deployment.virsh;
+    let name = deployment.name.as_str();
+
+    if virsh.check(&["dominfo", name]) {
+        if virsh.run(&["domstate", name])? != "shut off" {
+            virsh.run(&["destroy", name])?;
+        }
+        virsh.run(&["undefine", "--nvram", name])?;
+    }
+
+    if volume {
+        for vol in [volume_name(name), upload_volume_name(name)] {
+            if virsh.check(&["vol-info", "--pool", &deployment.pool, &vol]) {
+                virsh.run(&["vol-delete", "--pool", &deployment.pool, &vol])?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Volume the domain boots from
+fn volume_name(name: &str) -> String {
+    format!("{name}.raw")
+}
+
+/// The image is uploaded here first, the running domain is left alone if the
+/// upload fails
+fn upload_volume_name(name: &str) -> String {
+    format!("{name}.new.raw")
+}
+
+fn upload(virsh: &Virsh, pool: &str, vol: &str, image: &Path) -> Result<()> {
+    let size = fs::metadata(image)
+        .with_context(|| format!("Failed to read {}", image.display()))?
+        .len();
+    let capacity = format!("{}M", capacity(size));
+    println!(
+        "Uploading {} to {} ({})",
+        image.display(),
+        virsh.uri,
+        capacity
+    );
+    virsh.run(&["vol-create-as", pool, vol, &capacity, "--format", "raw"])?;
+    virsh.run(&[
+        "vol-upload",
+        "--pool",
+        pool,
+        vol,
+        &image.display().to_string(),
+    ])?;
+
+    Ok(())
+}
+
+/// Upload the image into the pool and (re)start the domain with it. Volume is
+/// kept if the same image was deployed before, the old domain is only stopped
+/// once the new image is uploaded.
+pub fn deploy(deployment: &Deployment, image: &Path) -> Result<()> {
+    let virsh = deployment.virsh;
+    let name = deployment.name.as_str();
+    let pool = deployment.pool.as_str();
+    validate_name(name)?;
+
+    if !virsh.check(&["pool-info", pool]) {
+        bail!(
+            "Storage pool '{}' doesn't exist on {}, create it with 'virsh pool-define-as {} dir --target <dir>' and 'virsh pool-start {}'",
+            pool,
+            virsh.uri,
+            pool,
+            pool
+        );
+    }
+
+    let sha256 = image_sha256(image)?;
+    let deployed = if virsh.check(&["dominfo", name]) {
+        deployed_sha256(&virsh.run(&["dumpxml", name])?)
+    } else {
+        None
+    };
+    let vol = volume_name(name);
+    let unchanged =
+        deployed.as_deref() == Some(&sha256) && virsh.check(&["vol-info", "--pool", pool, &vol]);
+
+    if unchanged {
+        println!("Image unchanged, skipping upload");
+        println!("Stopping '{}' (if running)", name);
+        remove(deployment, false)?;
+    } else {
+        // Left by a failed deploy
+        let new = upload_volume_name(name);
+        if virsh.check(&["vol-info", "--pool", pool, &new]) {
+            virsh.run(&["vol-delete", "--pool", pool, &new])?;
+        }
+        if let Err(err) = upload(virsh, pool, &new, image) {
+            let _ = virsh.run(&["vol-delete", "--pool", pool, &new]);
+            return Err(err.context(format!("'{}' is left as it was", name)));
+        }
+
+        // No vol-rename in libvirt, clone it under the name the domain uses
+        println!("Stopping '{}' (if running)", name);
+        remove(deployment, false)?;
+        if virsh.check(&["vol-info", "--pool", pool, &vol]) {
+            virsh.run(&["vol-delete", "--pool", pool, &vol])?;
+        }
+        virsh.run(&["vol-clone", "--pool", pool, &new, &vol])?;
+        virsh.run(&["vol-delete", "--pool", pool, &new])?;
+    }
+
+    let domain = Domain {
+        name: name.to_string(),
+        memory: deployment.memory,
+        cpus: deployment.cpus,
+        disk: PathBuf::from(virsh.run(&["vol-path", "--pool", pool, &vol])?),
+        network: deployment.network.clone(),
+        sha256,
+    };
+    fs::write(&deployment.xml, domain_xml(&domain))
+        .with_context(|| format!("Failed to write {}", deployment.xml.display()))?;
+
+    println!("Starting '{}'", name);
+    virsh.run(&["define", &deployment.xml.display().to_string()])?;
+    virsh.run(&["start", name])?;
+
+    Ok(())
+}
@@ -0,0 +1,183 @@
+use anyhow::{bail, Context, Result};
+use std::fs;
+use std::path::PathBuf;
+
+use crate::config::{parse_size, VmConfig};
+use crate::qmp::{self, Qmp};
+use crate::State;
+
+/// Root disk of the VM, runner.sh points NIX_DISK_IMAGE here
+pub fn root_image(state: &State) -> PathBuf {
+    state.envdir.join("image.qcow2")
+}
+
+/// Disk 'kd run --image' boots, a copy of the #image with space for the
+/// partitions
+pub fn image_disk(state: &State) -> PathBuf {
+    state.envdir.join("repart-image.qcow2")
+}
+
+pub fn snapshots_dir(state: &State) -> PathBuf {
+    state.envdir.join("snapshots")
+}
+
+pub fn snapshot_path(state: &State, name: &str) -> Result<PathBuf> {
+    if name.is_empty() || name.contains('/') || name.starts_with('.') {
+        bail!("Invalid snapshot name '{}'", name);
+    }
+
+    Ok(snapshots_dir(state).join(format!("{name}.qcow2")))
+}
+
+pub struct DiskInfo {
+    pub name: String,
+    pub path: Option<PathBuf>,
+    pub size: Option<u64>,
+}
+
+fn file_size(path: &PathBuf) -> Option<u64> {
+    fs::metadata(path).ok().map(|metadata| metadata.len())
+}
+
+/// Root disk, disk of 'kd run --image', snapshots and extra disks declared in
+/// [vm]. Extra disks are
+/// created by QEMU in a temporary directory on every boot, so they have no
+/// path on the host.
+pub fn list(state: &State) -> Result<Vec<DiskInfo>> {
+    let mut disks = vec![];
+
+    let root = root_image(state);
+    disks.push(DiskInfo {
+        name: "root".to_string(),
+        size: file_size(&root),
+        path: Some(root),
+    });
+
+    let image = image_disk(state);
+    if image.exists() {
+        disks.push(DiskInfo {
+            name: "image".to_string(),
+            size: file_size(&image),
+            path: Some(image),
+        });
+    }
+
+    let snapshots = snapshots_dir(state);
+    if snapshots.exists() {
+        let mut entries: Vec<PathBuf> = fs::read_dir(&snapshots)
+            .with_context(|| format!("Failed to read {}", snapshots.display()))?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == "qcow2"))
+            .collect();
+        entries.sort();
+
+        for path in entries {
+            let name = path
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().to_string())
+                .unwrap_or_default();
+            disks.push(DiskInfo {
+                name: format!("snapshot:{name}"),
+                size: file_size(&path),
+                path: Some(path),
+            });
+        }
+    }
+
+    if let Some(vm) = &state.config.vm {
+        if let Some(extra) = &vm.disks {
+            for (index, disk) in extra.iter().enumerate() {
+                let role = disk.role.map_or("extra", |role| role.as_str());
+                disks.push(DiskInfo {
+                    name: format!("{} ({})", VmConfig::disk_device(index), role),
+                    path: None,
+                    size: Some(parse_size(&disk.size)? * 1024 * 1024),
+                });
+            }
+        }
+    }
+
+    Ok(disks)
+}
+
+/// Disks are files QEMU of the running VM writes to, they can't be copied or
+/// removed under it
+pub fn check_stopped(state: &State) -> Result<()> {
+    if Qmp::connect(&qmp::socket(&state.envdir)).is_ok() {
+        bail!("VM is running, power it off first");
+    }
+    Ok(())
+}
+
+/// Remove root disk, NixOS creates a new one on the next boot
+pub fn reset(state: &State) -> Result<bool> {
+    check_stopped(state)?;
+    let root = root_image(state);
+    if !root.exists() {
+        return Ok(false);
+    }
+
+    fs::remove_file(&root).with_context(|| format!("Failed to remove {}", root.display()))?;
+    Ok(true)
+}
+
+pub fn reset_image(state: &State) -> Result<bool> {
+    check_stopped(state)?;
+    let disk = image_disk(state);
+    if !disk.exists() {
+        return Ok(false);
+    }
+
+    fs::remove_file(&disk).with_context(|| format!("Failed to remove {}", disk.display()))?;
+    // Checksum of the image the disk was made from
+    let mut sum = disk.into_os_string();
+    sum.push(".sha256sum");
+    let _ = fs::remove_file(sum);
+    Ok(true)
+}
+
+pub fn snapshot(state: &State, name: &str) -> Result<PathBuf> {
+    check_stopped(state)?;
+    let root = root_image(state);
+    if !root.exists() {
+        bail!("There's no root disk at {}, run VM first", root.display());
+    }
+
+    let target = snapshot_path(state, name)?;
+    let dir = snapshots_dir(state);
+    fs::create_dir_all(&dir).with_context(|| format!("Unable to create {}", dir.display()))?;
+    fs::copy(&root, &target)
+        .with_context(|| format!("Failed to copy {} to {}", root.display(), target.display()))?;
+
+    Ok(target)
+}
+
+pub fn restore(state: &State, name: &str) -> Result<PathBuf> {
+    check_stopped(state)?;
+    let source = snapshot_path(state, name)?;
+    if !source.exists() {
+        bail!("Snapshot '{}' doesn't exist", name);
+    }
+
+    let root = root_image(state);
+    fs::copy(&source, &root)
+        .with_context(|| format!("Failed to copy {} to {}", source.display(), root.display()))?;
+
+    Ok(root)
+}
+
+pub fn human_size(size: u64) -> String {
+    let units = ["B", "K", "M", "G", "T"];
+    let mut size = size as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < units.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{}{}", size, units[unit])
+    } else {
+        format!("{:.1}{}", size, units[unit])
+    }
+}
@@ -0,0 +1,262 @@
+use std::fs::{self, OpenOptions};
+use std::path::Path;
+use std::process::Command;
+
+use crate::config::Config;
+
+/// The line `kd init` puts into .envrc
+pub const ENVRC: &str = "use flake path:.kd/flake";
+
+/// Warn if there's less free space than that, kernel builds and disk images
+/// are big
+const MIN_FREE_SPACE: u64 = 20 * 1024 * 1024 * 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Status {
+    Ok,
+    Warning,
+    Error,
+}
+
+pub struct Check {
+    pub name: String,
+    pub status: Status,
+    pub message: String,
+    pub fix: Option<String>,
+}
+
+impl Check {
+    fn ok(name: &str, message: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            status: Status::Ok,
+            message: message.to_string(),
+            fix: None,
+        }
+    }
+
+    fn warning(name: &str, message: &str, fix: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            status: Status::Warning,
+            message: message.to_string(),
+            fix: Some(fix.to_string()),
+        }
+    }
+
+    fn error(name: &str, message: &str, fix: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            status: Status::Error,
+            message: message.to_string(),
+            fix: Some(fix.to_string()),
+        }
+    }
+}
+
+/// Run `tool --version` and return first line of the output
+fn tool_version(tool: &str) -> Option<String> {
+    let output = Command::new(tool).arg("--version").output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    Some(stdout.lines().next().unwrap_or_default().trim().to_string())
+}
+
+pub fn check_tool(tool: &str, required: bool, fix: &str) -> Check {
+    match tool_version(tool) {
+        Some(version) => Check::ok(tool, &version),
+        None if required => Check::error(tool, "not found", fix),
+        None => Check::warning(tool, "not found", fix),
+    }
+}
+
+pub fn check_flakes() -> Check {
+    let name = "nix flakes";
+    let fix = "Add 'experimental-features = nix-command flakes' to ~/.config/nix/nix.conf";
+    let output = Command::new("nix")
+        .arg("config")
+        .arg("show")
+        .arg("experimental-features")
+        .output();
+
+    match output {
+        Ok(output) if output.status.success() => {
+            let features = String::from_utf8_lossy(&output.stdout);
+            let features: Vec<&str> = features.split_whitespace().collect();
+            if features.contains(&"flakes") && features.contains(&"nix-command") {
+                Check::ok(name, "enabled")
+            } else {
+                Check::error(name, "'nix-command' or 'flakes' is not enabled", fix)
+            }
+        }
+        _ => Check::error(name, "unable to read nix configuration", fix),
+    }
+}
+
+pub fn check_kvm(path: &Path) -> Check {
+    let name = "kvm";
+    if !path.exists() {
+        return Check::error(
+            name,
+            &format!("{} doesn't exist", path.display()),
+            "Enable virtualization in BIOS and load kvm_intel or kvm_amd module",
+        );
+    }
+
+    match OpenOptions::new().read(true).write(true).open(path) {
+        Ok(_) => Check::ok(name, &format!("{} is accessible", path.display())),
+        Err(error) => Check::error(
+            name,
+            &format!("{} is not accessible: {}", path.display(), error),
+            "Add yourself to the 'kvm' group: sudo usermod -aG kvm $USER",
+        ),
+    }
+}
+
+pub fn check_flake(curdir: &Path) -> Check {
+    let name = "flake";
+    let flake_dir = curdir.join(".kd/flake");
+    if !flake_dir.exists() {
+        return Check::error(name, ".kd/flake doesn't exist", "Run 'kd init'");
+    }
+
+    let missing: Vec<&str> = ["flake.nix", "flake.lock", "uconfig.nix"]
+        .into_iter()
+        .filter(|file| !flake_dir.join(file).exists())
+        .collect();
+    if !missing.is_empty() {
+        return Check::error(
+            name,
+            &format!("missing {} in .kd/flake", missing.join(", ")),
+            "Run 'kd init' to recreate the flake",
+        );
+    }
+
+    // Flakes made before vmconfig.nix don't pass [vm] and [share] to the VM
+    let flake = fs::read_to_string(flake_dir.join("flake.nix")).unwrap_or_default();
+    if !flake.contains("vmconfig.nix") {
+        return Check::warning(
+            name,
+            "flake.nix doesn't load vmconfig.nix, [vm] and [share] are ignored",
+            "Run 'kd init --upgrade'",
+        );
+    }
+
+    Check::ok(name, &format!("{}", flake_dir.display()))
+}
+
+pub fn check_envrc(curdir: &Path) -> Check {
+    let name = ".envrc";
+    let envrc = curdir.join(".envrc");
+    let fix = format!("Add '{}' to .envrc and run 'direnv allow'", ENVRC);
+    match std::fs::read_to_string(&envrc) {
+        Ok(content) => {
+            if content.lines().any(|line| line.trim() == ENVRC) {
+                Check::ok(name, ".envrc uses kd flake")
+            } else {
+                Check::warning(name, ".envrc doesn't use .kd/flake", &fix)
+            }
+        }
+        Err(_) => Check::warning(name, ".envrc doesn't exist", &fix),
+    }
+}
+
+pub fn check_config(path: &Path) -> Check {
+    let name = "config";
+    if !path.exists() {
+        return Check::error(
+            name,
+            &format!("{} doesn't exist", path.display()),
+            "Run 'kd init' to create it",
+        );
+    }
+
+    let result = Config::load(path).and_then(|config| config.validate());
+    match result {
+        Ok(()) => Check::ok(name, &format!("{} is valid", path.display())),
+        Err(error) => Check::error(
+            name,
+            &format!("{} is invalid: {:#}", path.display(), error),
+            "Fix the config, see README for examples",
+        ),
+    }
+}
+
+/// Free space in bytes on the filesystem containing `path`
+fn free_space(path: &Path) -> Option<u64> {
+    let output = Command::new("df").arg("-Pk").arg(path).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let line = stdout.lines().nth(1)?;
+    let available: u64 = line.split_whitespace().nth(3)?.parse().ok()?;
+    Some(available * 1024)
+}
+
+pub fn check_disk_space(name: &str, path: &Path) -> Check {
+    let fix = "Free some space, e.g. with 'kd clean --all' and 'nix-collect-garbage'";
+    match free_space(path) {
+        Some(free) if free < MIN_FREE_SPACE => Check::warning(
+            name,
+            &format!(
+                "{} free on {}",
+                crate::disks::human_size(free),
+                path.display()
+            ),
+            fix,
+        ),
+        Some(free) => Check::ok(
+            name,
+            &format!(
+                "{} free on {}",
+                crate::disks::human_size(free),
+                path.display()
+            ),
+        ),
+        None => Check::warning(
+            name,
+            &format!("unable to find free space on {}", path.display()),
+            "Check that 'df' is installed",
+        ),
+    }
+}
+
+pub fn run(curdir: &Path, config_path: &Path) -> Vec<Check> {
+    let mut checks = vec![
+        check_tool("nix", true, "Install Nix: https://nixos.org/download"),
+        check_flakes(),
+        // Pinned and local sources don't need it
+        check_tool(
+            "nurl",
+            false,
+            "Install nurl to fetch new remote sources, e.g. 'nix profile install nixpkgs#nurl'",
+        ),
+        check_tool(
+            "direnv",
+            false,
+            "Install direnv, e.g. 'nix profile install nixpkgs#direnv'",
+        ),
+        check_tool(
+            "alejandra",
+            false,
+            "Install alejandra (used by 'kd debug'), e.g. 'nix profile install nixpkgs#alejandra'",
+        ),
+        check_kvm(Path::new("/dev/kvm")),
+        check_flake(curdir),
+        check_envrc(curdir),
+        check_config(config_path),
+    ];
+
+    let store = Path::new("/nix/store");
+    if store.exists() {
+        checks.push(check_disk_space("disk space (store)", store));
+    }
+    checks.push(check_disk_space("disk space (env)", curdir));
+
+    checks
+}
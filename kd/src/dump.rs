@@ -0,0 +1,112 @@
+use anyhow::{bail, Context, Result};
+use std::fs;
+use std::os::unix::fs::symlink;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::plan::Plan;
+use crate::prebuild;
+
+/// Memory dump of the guest, QEMU writes it as ELF core
+pub const VMCORE: &str = "vmcore";
+
+/// Kernel the dump was taken from, next to the vmcore
+pub const VMLINUX: &str = "vmlinux";
+
+pub fn dumps_dir(envdir: &Path) -> PathBuf {
+    envdir.join("dumps")
+}
+
+/// Directory for a new dump named after the current time
+pub fn new_dir(envdir: &Path) -> Result<PathBuf> {
+    let output = Command::new("date")
+        .arg("+%Y-%m-%d_%H-%M-%S")
+        .output()
+        .context("Failed to run 'date'")?;
+    let dir = dumps_dir(envdir).join(String::from_utf8_lossy(&output.stdout).trim());
+    fs::create_dir_all(&dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+
+    Ok(dir)
+}
+
+/// Put vmlinux of the running kernel into the dump directory. The one of the
+/// 'prebuild' kernel is copied from its tree, as the next build replaces it.
+/// Otherwise it's built from the flake and linked, the build result is kept
+/// as GC root.
+pub fn save_vmlinux(plan: &Plan, dir: &Path) -> Result<PathBuf> {
+    let target = dir.join(VMLINUX);
+    let _ = fs::remove_file(&target);
+    let vmlinux = match plan.prebuild()? {
+        Some(image) => {
+            let Some(tree) = prebuild::tree(&image) else {
+                bail!(
+                    "Unable to find kernel tree of {}, there's no vmlinux",
+                    image.display()
+                );
+            };
+            let vmlinux = tree.join(VMLINUX);
+            fs::copy(&vmlinux, &target).with_context(|| {
+                format!("Failed to copy {} to {}", vmlinux.display(), target.display())
+            })?;
+            vmlinux
+        }
+        None => {
+            let vmlinux = plan
+                .build_link("vm-kernel^dev", &dir.join("kernel"))?
+                .join(VMLINUX);
+            if !vmlinux.exists() {
+                bail!("There's no {}", vmlinux.display());
+            }
+            symlink(&vmlinux, &target).with_context(|| {
+                format!("Failed to link {} to {}", vmlinux.display(), target.display())
+            })?;
+            vmlinux
+        }
+    };
+
+    Ok(vmlinux)
+}
+
+/// The most recent dump with vmcore in it
+pub fn latest(envdir: &Path) -> Result<PathBuf> {
+    let dumps = dumps_dir(envdir);
+    let mut dirs: Vec<PathBuf> = match fs::read_dir(&dumps) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|dir| dir.join(VMCORE).exists())
+            .collect(),
+        Err(_) => vec![],
+    };
+    // Named by date
+    dirs.sort();
+
+    match dirs.pop() {
+        Some(dir) => Ok(dir),
+        None => bail!(
+            "There're no memory dumps in {}, take one with 'kd dump'",
+            dumps.display()
+        ),
+    }
+}
+
+/// drgn arguments to open the dump, the script and its arguments go last
+pub fn drgn_args(dump: &Path, script: Option<&Path>, args: &[String]) -> Result<Vec<String>> {
+    for file in [VMCORE, VMLINUX] {
+        if !dump.join(file).exists() {
+            bail!("There's no {} in {}", file, dump.display());
+        }
+    }
+
+    let mut drgn = vec![
+        "--core".to_string(),
+        dump.join(VMCORE).display().to_string(),
+        "--symbols".to_string(),
+        dump.join(VMLINUX).display().to_string(),
+    ];
+    if let Some(script) = script {
+        drgn.push(script.display().to_string());
+    }
+    drgn.extend(args.iter().cloned());
+
+    Ok(drgn)
+}
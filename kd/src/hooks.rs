@@ -0,0 +1,229 @@
+use anyhow::{bail, Context, Result};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use crate::config::{TraceConfig, XfstestsConfig};
+
+/// Hook points of the hook infrastructure patch, hooks in start/ run before
+/// the test, hooks in end/ from its _cleanup()
+pub const POINTS: &[&str] = &["start", "end"];
+
+/// Hooks shipped with kd, enabled with 'builtin_hooks'
+pub const BUILTIN: &[(&str, &str, &str)] = &[
+    (
+        "dmesg",
+        include_str!("../assets/hooks/dmesg-start"),
+        include_str!("../assets/hooks/dmesg-end"),
+    ),
+    (
+        "slabinfo",
+        include_str!("../assets/hooks/slabinfo-start"),
+        include_str!("../assets/hooks/slabinfo-end"),
+    ),
+];
+
+/// Built-in hook which records XFS trace events around every test. It's
+/// [trace] with these events, the VM installs its hooks.
+pub const TRACE: &str = "trace";
+
+/// Events of the 'trace' built-in hook
+pub const TRACE_EVENTS: &[&str] = &["xfs:*"];
+
+/// Hooks directory in the share dir, the VM runs xfstests with HOOK_DIR
+/// pointing to it
+pub const HOOKS: &str = "hooks";
+
+/// Left in the hooks directory kd made, others are not touched
+const MARKER: &str = ".kd";
+
+fn builtin(name: &str) -> Result<&'static (&'static str, &'static str, &'static str)> {
+    match BUILTIN.iter().find(|(builtin, _, _)| *builtin == name) {
+        Some(hook) => Ok(hook),
+        None => {
+            let names: Vec<&str> = BUILTIN
+                .iter()
+                .map(|(name, _, _)| *name)
+                .chain([TRACE])
+                .collect();
+            bail!(
+                "Unknown built-in hook '{}', available: {}",
+                name,
+                names.join(", ")
+            );
+        }
+    }
+}
+
+/// Split "<name>.<n>" hook file name, name is "global" or "<group>-<test>",
+/// e.g. xfs-001 for tests/xfs/001
+fn parse_name(file: &str) -> Option<(&str, usize)> {
+    let (name, number) = file.rsplit_once('.')?;
+    let number = number.parse().ok()?;
+    if name == "global" {
+        return Some((name, number));
+    }
+
+    let (group, test) = name.rsplit_once('-')?;
+    let valid = !group.is_empty()
+        && group
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        && !test.is_empty()
+        && test.chars().all(|c| c.is_ascii_digit());
+    valid.then_some((name, number))
+}
+
+/// Check that every file in `dir` is a hook xfstests runs
+pub fn validate(dir: &Path) -> Result<()> {
+    if !dir.is_dir() {
+        bail!("Hooks directory {} doesn't exist", dir.display());
+    }
+
+    for entry in fs::read_dir(dir).with_context(|| format!("Failed to read {}", dir.display()))? {
+        let path = entry?.path();
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        if name.starts_with('.') {
+            continue;
+        }
+        if !POINTS.contains(&name.as_ref()) || !path.is_dir() {
+            bail!(
+                "{} isn't a hook point, expected {} directories",
+                path.display(),
+                POINTS.join(" and ")
+            );
+        }
+
+        // Hooks of a test run as long as there's the next number
+        let mut numbers: BTreeMap<String, Vec<usize>> = BTreeMap::new();
+        for entry in fs::read_dir(&path)? {
+            let hook = entry?.path();
+            let file = hook.file_name().unwrap_or_default().to_string_lossy();
+            if file.starts_with('.') {
+                continue;
+            }
+            let Some((name, number)) = parse_name(&file) else {
+                bail!(
+                    "Invalid hook name {}, expected global.N or <group>-<test>.N, e.g. xfs-001.0",
+                    hook.display()
+                );
+            };
+            let script = fs::read_to_string(&hook)
+                .with_context(|| format!("Failed to read {}", hook.display()))?;
+            if !script.contains("hook_execute") {
+                bail!("{} doesn't define hook_execute()", hook.display());
+            }
+            numbers.entry(name.to_string()).or_default().push(number);
+        }
+
+        for (name, mut numbers) in numbers {
+            numbers.sort();
+            if let Some(missing) = (0..).zip(&numbers).find(|(n, number)| n != *number) {
+                bail!(
+                    "{}/{}.{} is missing, hooks after it never run",
+                    path.display(),
+                    name,
+                    missing.0
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
+
+pub fn validate_builtin(names: &[String]) -> Result<()> {
+    for name in names.iter().filter(|name| *name != TRACE) {
+        builtin(name)?;
+    }
+
+    Ok(())
+}
+
+/// [trace] of the 'trace' built-in hook, None if it's not enabled
+pub fn trace(config: Option<&XfstestsConfig>) -> Option<TraceConfig> {
+    let builtins = config.and_then(|config| config.builtin_hooks.as_ref())?;
+    builtins.iter().any(|name| name == TRACE).then(|| TraceConfig {
+        events: TRACE_EVENTS.iter().map(|event| event.to_string()).collect(),
+        ..TraceConfig::default()
+    })
+}
+
+/// Next free number of the global hook
+fn next_global(dir: &Path) -> usize {
+    (0..)
+        .find(|n| !dir.join(format!("global.{n}")).exists())
+        .unwrap_or_default()
+}
+
+/// Put hooks from 'hooks' and 'builtin_hooks' of [xfstests] into the share
+/// dir. Built-in hooks run after the global hooks of the directory. The
+/// directory made by kd before is removed if there're no hooks now.
+pub fn install(config: Option<&XfstestsConfig>, curdir: &Path, share: &Path) -> Result<bool> {
+    let hooks = share.join(HOOKS);
+    if hooks.join(MARKER).exists() {
+        fs::remove_dir_all(&hooks)
+            .with_context(|| format!("Failed to remove {}", hooks.display()))?;
+    }
+
+    let source = config.and_then(|config| config.hooks.as_ref());
+    // 'trace' is recorded by the VM as [trace]
+    let builtins: Vec<&String> = config
+        .and_then(|config| config.builtin_hooks.as_ref())
+        .into_iter()
+        .flatten()
+        .filter(|name| *name != TRACE)
+        .collect();
+    if source.is_none() && builtins.is_empty() {
+        return Ok(false);
+    }
+    if hooks.exists() {
+        bail!(
+            "{} is not made by kd, move your hooks to 'hooks' of [xfstests]",
+            hooks.display()
+        );
+    }
+
+    for point in POINTS {
+        fs::create_dir_all(hooks.join(point))
+            .with_context(|| format!("Failed to create {}", hooks.display()))?;
+    }
+    fs::write(hooks.join(MARKER), "")?;
+
+    if let Some(source) = source {
+        let source = curdir.join(source);
+        validate(&source)?;
+        for point in POINTS {
+            let dir = source.join(point);
+            if !dir.exists() {
+                continue;
+            }
+            for entry in fs::read_dir(&dir)? {
+                let path = entry?.path();
+                if path
+                    .file_name()
+                    .is_some_and(|name| name.to_string_lossy().starts_with('.'))
+                {
+                    continue;
+                }
+                // Hooks are often links to common scripts, copy what they point to
+                let target = hooks.join(point).join(path.file_name().unwrap_or_default());
+                fs::copy(&path, &target).with_context(|| {
+                    format!("Failed to copy {} to {}", path.display(), target.display())
+                })?;
+            }
+        }
+    }
+
+    for name in builtins {
+        let (_, start, end) = builtin(name)?;
+        for (point, script) in [("start", start), ("end", end)] {
+            let dir = hooks.join(point);
+            let path = dir.join(format!("global.{}", next_global(&dir)));
+            fs::write(&path, script)
+                .with_context(|| format!("Failed to write {}", path.display()))?;
+        }
+    }
+
+    Ok(true)
+}
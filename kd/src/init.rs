@@ -0,0 +1,135 @@
+use anyhow::{bail, Context, Result};
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+pub const DEFAULT_TEMPLATE: &str = "github:alberand/kd#default";
+
+const CONFIG: &str = include_str!("../assets/config.toml");
+
+/// Files in .kd/flake which belong to the user and are never overwritten
+pub const USER_FILES: [&str; 3] = ["flake.lock", "modules.nix", "overlays.nix"];
+
+pub const PRESETS: [&str; 3] = ["xfs", "ext4", "script"];
+
+/// Content of .kd.toml, optionally starting with one of the presets followed
+/// by commented out reference of all the options
+pub fn config(preset: Option<&str>) -> Result<String> {
+    let preset = match preset {
+        None => "",
+        Some("xfs") => include_str!("../assets/presets/xfs.toml"),
+        Some("ext4") => include_str!("../assets/presets/ext4.toml"),
+        Some("script") => include_str!("../assets/presets/script.toml"),
+        Some(preset) => bail!(
+            "Unknown preset '{}', available: {}",
+            preset,
+            PRESETS.join(", ")
+        ),
+    };
+
+    if preset.is_empty() {
+        return Ok(CONFIG.to_string());
+    }
+
+    Ok(format!("{}\n{}", preset, CONFIG))
+}
+
+/// Flake reference of the template. Local directories are turned into
+/// absolute path: references as 'nix flake init' runs in a different directory.
+pub fn template_ref(template: Option<&str>, curdir: &Path) -> Result<String> {
+    let Some(template) = template else {
+        return Ok(DEFAULT_TEMPLATE.to_string());
+    };
+
+    let (location, output) = match template.split_once('#') {
+        Some((location, output)) => (location, output),
+        None => (template, "default"),
+    };
+
+    let local = location.strip_prefix("path:").unwrap_or(location);
+    let is_path = location.starts_with("path:")
+        || local.starts_with('.')
+        || local.starts_with('/')
+        || curdir.join(local).is_dir();
+    if !is_path {
+        return Ok(format!("{}#{}", location, output));
+    }
+
+    let path = std::path::absolute(curdir.join(local))
+        .with_context(|| format!("Failed to resolve template path {}", local))?;
+    if !path.join("flake.nix").exists() {
+        bail!("{} doesn't contain flake.nix", path.display());
+    }
+
+    Ok(format!("path:{}#{}", path.display(), output))
+}
+
+/// Instantiate template into a temporary directory
+pub fn fetch_template(template: &str, envdir: &Path) -> Result<PathBuf> {
+    let dir = envdir.join("flake-template");
+    if dir.exists() {
+        fs::remove_dir_all(&dir).with_context(|| format!("Unable to remove {}", dir.display()))?;
+    }
+    fs::create_dir_all(&dir).with_context(|| format!("Unable to create {}", dir.display()))?;
+
+    let output = Command::new("nix")
+        .arg("flake")
+        .arg("init")
+        .arg("--template")
+        .arg(template)
+        .current_dir(&dir)
+        .output()
+        .context("Failed to run 'nix flake init' (see 'kd doctor')")?;
+
+    if !output.status.success() {
+        let _ = fs::remove_dir_all(&dir);
+        bail!(
+            "Failed to create Nix Flake from {}:\n{}",
+            template,
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+
+    Ok(dir)
+}
+
+/// Copy template files into the flake directory. Files from [USER_FILES]
+/// which already exist are kept. Returns list of the files which were kept.
+pub fn install_template(template_dir: &Path, flake_dir: &Path) -> Result<Vec<String>> {
+    fs::create_dir_all(flake_dir)
+        .with_context(|| format!("Unable to create {}", flake_dir.display()))?;
+
+    let mut kept = vec![];
+    for entry in fs::read_dir(template_dir)
+        .with_context(|| format!("Failed to read {}", template_dir.display()))?
+    {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        let target = flake_dir.join(&name);
+
+        if target.exists() && USER_FILES.contains(&name.as_str()) {
+            kept.push(name);
+            continue;
+        }
+
+        fs::copy(entry.path(), &target)
+            .with_context(|| format!("Failed to copy {} to {}", name, flake_dir.display()))?;
+        // Files coming from the Nix store are read-only
+        fs::set_permissions(&target, fs::Permissions::from_mode(0o644))
+            .with_context(|| format!("Failed to set permissions on {}", target.display()))?;
+    }
+
+    // uconfig.nix and vmconfig.nix are regenerated on every build, they only
+    // need to exist
+    for name in ["uconfig.nix", "vmconfig.nix"] {
+        let config = flake_dir.join(name);
+        if !config.exists() {
+            fs::write(&config, "{pkgs, kd, ...}: {}\n")
+                .with_context(|| format!("Unable to create {}", config.display()))?;
+        }
+    }
+
+    kept.sort();
+    Ok(kept)
+}
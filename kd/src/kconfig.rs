@@ -0,0 +1,383 @@
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use toml::Table;
+
+use crate::plan::Plan;
+
+/// Kernel options as they are in .config, without CONFIG_ prefix. Options
+/// which are "not set" have "n" value.
+pub type Kconfig = BTreeMap<String, String>;
+
+/// Options as they are set in Nix (or [kernel.config]), without CONFIG_ prefix
+pub type Options = BTreeMap<String, Expected>;
+
+/// Flavor name (e.g. "debug") to the options it sets
+pub type Flavors = BTreeMap<String, Options>;
+
+/// The flavor which kd always applies
+pub const DEFAULT_FLAVOR: &str = "default";
+
+/// Value of an option as produced by lib.kernel (yes, no, module, freeform)
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct Expected {
+    pub tristate: Option<String>,
+    pub freeform: Option<serde_json::Value>,
+    #[serde(default)]
+    pub optional: bool,
+}
+
+impl Expected {
+    /// Value in .config notation
+    pub fn value(&self) -> String {
+        if let Some(tristate) = &self.tristate {
+            return tristate.clone();
+        }
+
+        match &self.freeform {
+            Some(serde_json::Value::String(value)) => value.clone(),
+            Some(value) => value.to_string(),
+            None => String::new(),
+        }
+    }
+
+    /// Value in lib.kernel notation as used in uconfig.nix
+    pub fn nix(&self) -> String {
+        match self.tristate.as_deref() {
+            Some("y") => "yes".to_string(),
+            Some("n") => "no".to_string(),
+            Some("m") => "module".to_string(),
+            _ => format!("freeform \"{}\"", self.value()),
+        }
+    }
+
+    /// Is `actual` value from .config good enough. Builtin is fine if module
+    /// was requested.
+    pub fn matches(&self, actual: Option<&str>) -> bool {
+        let expected = self.value();
+        match (expected.as_str(), actual) {
+            (_, None) if self.optional => true,
+            ("n", None) | ("n", Some("n")) => true,
+            ("m", Some("m")) | ("m", Some("y")) => true,
+            (expected, Some(actual)) => expected == actual.trim_matches('"'),
+            (_, None) => false,
+        }
+    }
+}
+
+/// Parse value from [kernel.config] such as "yes", "module" or
+/// 'freeform "64"'
+pub fn parse_value(value: &str) -> Result<Expected> {
+    let value = value.trim();
+    let tristate = |tristate: &str| Expected {
+        tristate: Some(tristate.to_string()),
+        freeform: None,
+        optional: false,
+    };
+
+    Ok(match value {
+        "yes" => tristate("y"),
+        "no" => tristate("n"),
+        "module" => tristate("m"),
+        _ => match value.strip_prefix("freeform") {
+            Some(freeform) if !freeform.trim().is_empty() => Expected {
+                tristate: None,
+                freeform: Some(serde_json::Value::String(
+                    freeform.trim().trim_matches('"').to_string(),
+                )),
+                optional: false,
+            },
+            _ => bail!("Unknown value '{}'", value),
+        },
+    })
+}
+
+/// Options from [kernel.config] without CONFIG_ prefix
+pub fn requested(config: &Table) -> Result<Options> {
+    let mut requested = Options::new();
+    for (key, value) in config {
+        let Some(name) = key.strip_prefix("CONFIG_") else {
+            bail!("Option {} doesn't start with CONFIG_", key);
+        };
+        let Some(value) = value.as_str() else {
+            bail!("Value of {} has to be a string", key);
+        };
+        let value = parse_value(value).with_context(|| format!("Invalid value of {}", key))?;
+        requested.insert(name.to_string(), value);
+    }
+
+    Ok(requested)
+}
+
+/// Parse kernel .config
+pub fn parse(data: &str) -> Kconfig {
+    let mut config = Kconfig::new();
+    for line in data.lines() {
+        let line = line.trim();
+        if let Some(name) = line
+            .strip_prefix("# CONFIG_")
+            .and_then(|line| line.strip_suffix(" is not set"))
+        {
+            config.insert(name.to_string(), "n".to_string());
+        } else if let Some((name, value)) = line
+            .strip_prefix("CONFIG_")
+            .and_then(|line| line.split_once('='))
+        {
+            config.insert(name.to_string(), value.to_string());
+        }
+    }
+
+    config
+}
+
+pub fn load(path: &Path) -> Result<Kconfig> {
+    let data =
+        fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    Ok(parse(&data))
+}
+
+pub fn parse_flavors(data: &str) -> Result<Flavors> {
+    serde_json::from_str(data).context("Failed to parse kernel config flavors")
+}
+
+/// Build #kconfig-flavors of the kd flake, it's kconfigs/default.nix in JSON,
+/// and add flavors defined in .kd.toml
+pub fn flavors(plan: &Plan) -> Result<Flavors> {
+    let path = plan.build_output("kconfig-flavors")?;
+    let data =
+        fs::read_to_string(&path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let mut flavors = parse_flavors(&data)?;
+
+    // Flavors from .kd.toml replace the ones with the same name
+    if let Some(user) = &plan.kconfig_flavors {
+        for (name, config) in user {
+            let options =
+                requested(config).with_context(|| format!("Invalid [kconfig_flavors.{}]", name))?;
+            flavors.insert(name.clone(), options);
+        }
+    }
+
+    Ok(flavors)
+}
+
+/// Symbols from Kconfig files, one per line
+pub fn parse_symbols(data: &str) -> BTreeSet<String> {
+    data.lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty())
+        .map(|line| line.to_string())
+        .collect()
+}
+
+/// Build #kconfig-symbols of the kd flake, all the symbols defined by Kconfig
+/// files of the kernel source used in the config
+pub fn symbols(plan: &Plan) -> Result<BTreeSet<String>> {
+    let path = plan.build_output("kconfig-symbols")?;
+    let data =
+        fs::read_to_string(&path).with_context(|| format!("Failed to read {}", path.display()))?;
+    Ok(parse_symbols(&data))
+}
+
+/// Build #kconfig of the kd flake, the .config the kernel is built with,
+/// flavors included
+pub fn final_config(plan: &Plan) -> Result<Kconfig> {
+    let path = plan.build_output("kconfig")?;
+    load(&path)
+}
+
+/// Requested options which are not defined in any Kconfig
+pub fn unknown(requested: &Options, symbols: &BTreeSet<String>) -> Vec<String> {
+    requested
+        .keys()
+        .filter(|name| !symbols.contains(*name))
+        .cloned()
+        .collect()
+}
+
+pub struct Conflict {
+    pub name: String,
+    pub value: String,
+    pub flavor: String,
+    pub flavor_value: String,
+}
+
+/// Requested options which are set to a different value by the default or
+/// one of the `selected` flavors
+pub fn conflicts(requested: &Options, flavors: &Flavors, selected: &[String]) -> Vec<Conflict> {
+    let names = std::iter::once(DEFAULT_FLAVOR).chain(selected.iter().map(|name| name.as_str()));
+    let mut conflicts = vec![];
+    for flavor in names {
+        let Some(options) = flavors.get(flavor) else {
+            continue;
+        };
+
+        for (name, value) in requested {
+            match options.get(name) {
+                Some(flavor_value) if flavor_value.value() != value.value() => {
+                    conflicts.push(Conflict {
+                        name: name.clone(),
+                        value: value.value(),
+                        flavor: flavor.to_string(),
+                        flavor_value: flavor_value.value(),
+                    })
+                }
+                _ => continue,
+            }
+        }
+    }
+
+    conflicts
+}
+
+/// Options required by the `selected` flavors and the default one. Later
+/// flavors override earlier ones, same as in Nix.
+pub fn required(flavors: &Flavors, selected: &[String]) -> Result<Options> {
+    let mut required = Options::new();
+    let names = std::iter::once(DEFAULT_FLAVOR).chain(selected.iter().map(|name| name.as_str()));
+    for name in names {
+        let Some(flavor) = flavors.get(name) else {
+            bail!("Unknown kernel config flavor '{}'", name);
+        };
+        required.extend(flavor.clone());
+    }
+
+    Ok(required)
+}
+
+pub struct Mismatch {
+    pub name: String,
+    pub expected: String,
+    pub actual: Option<String>,
+}
+
+/// Options from `expected` which are not set as requested in `config`
+pub fn check(expected: &Options, config: &Kconfig) -> Vec<Mismatch> {
+    expected
+        .iter()
+        .filter(|(name, value)| !value.matches(config.get(*name).map(|value| value.as_str())))
+        .map(|(name, value)| Mismatch {
+            name: name.clone(),
+            expected: value.value(),
+            actual: config.get(name).cloned(),
+        })
+        .collect()
+}
+
+/// Change between two configs, printed the same way as scripts/diffconfig
+/// does
+#[derive(Debug, PartialEq)]
+pub enum Change {
+    Removed(String, String),
+    Changed(String, String, String),
+    Added(String, String),
+}
+
+impl std::fmt::Display for Change {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Change::Removed(name, old) => write!(f, "-{} {}", name, old),
+            Change::Changed(name, old, new) => write!(f, " {} {} -> {}", name, old, new),
+            Change::Added(name, new) => write!(f, "+{} {}", name, new),
+        }
+    }
+}
+
+/// Differences between `old` and `new` configs. Same as scripts/diffconfig,
+/// removed options go first, then changed and then added ones.
+pub fn diff(old: &Kconfig, new: &Kconfig) -> Vec<Change> {
+    let mut changes = vec![];
+    for (name, value) in old {
+        if !new.contains_key(name) {
+            changes.push(Change::Removed(name.clone(), value.clone()));
+        }
+    }
+
+    for (name, value) in old {
+        match new.get(name) {
+            Some(new) if new != value => {
+                changes.push(Change::Changed(name.clone(), value.clone(), new.clone()))
+            }
+            _ => continue,
+        }
+    }
+
+    for (name, value) in new {
+        if !old.contains_key(name) {
+            changes.push(Change::Added(name.clone(), value.clone()));
+        }
+    }
+
+    changes
+}
+
+/// .config line for the option
+fn config_line(name: &str, value: &Expected) -> String {
+    match value.tristate.as_deref() {
+        Some("n") => format!("# CONFIG_{name} is not set"),
+        Some(tristate) => format!("CONFIG_{name}={tristate}"),
+        None => {
+            let value = value.value();
+            let number = value.parse::<i64>().is_ok() || value.starts_with("0x");
+            if number {
+                format!("CONFIG_{name}={value}")
+            } else {
+                format!("CONFIG_{name}=\"{value}\"")
+            }
+        }
+    }
+}
+
+/// Apply `options` onto the existing .config `data`, same as
+/// scripts/kconfig/merge_config.sh does with a fragment. The result needs
+/// 'make olddefconfig' to resolve dependencies.
+pub fn merge(data: &str, options: &Options) -> String {
+    let mut merged: Vec<String> = vec![];
+    for line in data.lines() {
+        let name = line
+            .strip_prefix("# CONFIG_")
+            .and_then(|line| line.strip_suffix(" is not set"))
+            .or_else(|| {
+                line.strip_prefix("CONFIG_")
+                    .and_then(|line| line.split_once('='))
+                    .map(|(name, _)| name)
+            });
+
+        match name {
+            Some(name) if options.contains_key(name) => continue,
+            _ => merged.push(line.to_string()),
+        }
+    }
+
+    for (name, value) in options {
+        merged.push(config_line(name, value));
+    }
+
+    merged.push(String::new());
+    merged.join("\n")
+}
+
+/// Run 'make olddefconfig' in the kernel `tree` on the `config` file
+pub fn olddefconfig(plan: &Plan, tree: &Path, config: &Path) -> Result<()> {
+    let mut cmd = Command::new("make");
+    cmd.arg("-C")
+        .arg(tree)
+        .arg("-s")
+        .arg(format!("KCONFIG_CONFIG={}", config.display()))
+        .arg("olddefconfig");
+
+    if plan.debug {
+        println!("command: {:?}", cmd);
+    }
+
+    let status = cmd
+        .status()
+        .context("Failed to spawn 'make olddefconfig'")?;
+    if !status.success() {
+        bail!("'make olddefconfig' failed in {}", tree.display());
+    }
+
+    Ok(())
+}
@@ -1,36 +1,41 @@
 use anyhow::{bail, Context, Result};
-use std::collections::HashMap;
-use std::path::{self, PathBuf};
-use std::process::Command;
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use toml::Table;
 
+pub mod clean;
 pub mod config;
+pub mod deploy;
+pub mod disks;
+pub mod doctor;
+pub mod dump;
+pub mod hooks;
+pub mod init;
+pub mod kconfig;
+pub mod plan;
+pub mod prebuild;
+pub mod progress;
+pub mod qmp;
+pub mod report;
+pub mod results;
+pub mod share;
+pub mod sources;
+pub mod targets;
+pub mod upload;
+pub mod watchdog;
 use config::{
-    Config, KernelConfig, KernelConfigOption, SystemConfig, XfsprogsConfig, XfstestsConfig,
+    parse_size, Config, KernelConfig, ShareEntry, SystemConfig, TraceConfig, VmConfig,
+    XfsprogsConfig, XfstestsConfig, XfstestsDevices,
 };
+use sources::Sources;
 
-fn nurl(repo: &str, rev: &str) -> Result<String> {
-    println!("Fetching source for {} at {}", repo, rev);
-    let output = Command::new("nurl")
-        .arg("--fetcher")
-        .arg("builtins.fetchGit")
-        .arg("--arg")
-        .arg("allRefs")
-        .arg("true")
-        .arg(repo)
-        .arg(rev)
-        .output()
-        .context("Failed to fetch source with nurl")?;
-
-    if !output.status.success() {
-        bail!("{}", String::from_utf8_lossy(&output.stderr));
-    }
-
-    String::from_utf8(output.stdout).context("Failed to parse nurl output")
-}
+/// Used when xfstests 'rev' is set without 'repo'
+pub const XFSTESTS_REPO: &str = "https://kernel.googlesource.com/pub/scm/fs/xfs/xfstests-dev.git";
 
 // Agh, so ugly
 // TODO fix nrix to parse nix from rust
 
+#[derive(Default)]
 pub struct State {
     pub debug: bool,
     pub curdir: PathBuf,
@@ -38,25 +43,11 @@ pub struct State {
     pub flake_dir: PathBuf,
     pub config: Config,
     pub user_config: PathBuf,
+    pub vm_config: PathBuf,
     pub args: Vec<String>,
     pub envs: HashMap<String, String>,
     pub name: String,
-}
-
-impl Default for State {
-    fn default() -> Self {
-        Self {
-            debug: false,
-            curdir: PathBuf::default(),
-            envdir: PathBuf::default(),
-            flake_dir: PathBuf::default(),
-            config: Config::default(),
-            user_config: PathBuf::default(),
-            args: Vec::<String>::default(),
-            envs: HashMap::<String, String>::default(),
-            name: String::default(),
-        }
-    }
+    pub offline: bool,
 }
 
 impl State {
@@ -72,6 +63,7 @@ impl State {
         let envdir = curdir.clone().join(".kd");
         let flake_dir = envdir.clone().join("flake");
         let user_config = flake_dir.clone().join("uconfig.nix");
+        let vm_config = flake_dir.clone().join("vmconfig.nix");
 
         Ok(Self {
             debug: false,
@@ -80,9 +72,11 @@ impl State {
             flake_dir,
             config,
             user_config,
+            vm_config,
             args: vec![],
             envs: HashMap::new(),
             name: String::default(),
+            offline: false,
         })
     }
 }
@@ -95,11 +89,16 @@ pub fn uconfig_set_value_str(name: &str, value: &str) -> String {
     uconfig_set_value(name, &format!("\"{}\"", &value))
 }
 
-pub fn uconfig_xfsprogs(config: &XfsprogsConfig) -> String {
+pub fn uconfig_xfsprogs(config: &XfsprogsConfig, sources: &mut Sources) -> Result<String> {
     let mut options: Vec<String> = vec![];
-    if let Some(rev) = &config.rev {
+    if let Some(path) = &config.path {
+        let src = sources.path(path, "xfsprogs", &[".git"])?;
+        options.push(uconfig_set_value("src", &src));
+    } else if let Some(rev) = &config.rev {
         if let Some(repo) = &config.repo {
-            let src = nurl(&repo, &rev).expect("Failed to fetch source");
+            let src = sources
+                .fetch(repo, rev)
+                .context("Failed to fetch xfsprogs source")?;
             options.push(uconfig_set_value("src", &src));
         }
     };
@@ -108,67 +107,76 @@ pub fn uconfig_xfsprogs(config: &XfsprogsConfig) -> String {
         if let (Some(version), Some(rev), Some(repo)) =
             (&headers.version, &headers.rev, &headers.repo)
         {
-            let src = nurl(&repo, &rev)
-                .expect("Failed to fetch kernel source for xfsprogs headers");
+            let src = sources
+                .fetch(repo, rev)
+                .context("Failed to fetch kernel source for xfsprogs headers")?;
             let value =
                 format!("pkgs.kd.lib.buildKernelHeaders {{ src = {src}; version = \"{version}\"; }}");
             options.push(uconfig_set_value("kernelHeaders", &value));
         };
     }
 
-    format!("services.xfsprogs = {{ {} }};", &options.join("\n"))
+    Ok(format!("services.xfsprogs = {{ {} }};", &options.join("\n")))
+}
+
+/// xfstests devices, `default` ones are set with mkDefault so the config of
+/// the system can override them
+pub fn uconfig_devices(devices: &XfstestsDevices, default: bool) -> String {
+    let mut options: Vec<String> = vec![];
+
+    for (name, device) in [
+        ("test.main", &devices.test),
+        ("test.rtdev", &devices.test_rtdev),
+        ("test.logdev", &devices.test_logdev),
+        ("scratch.main", &devices.scratch),
+        ("scratch.rtdev", &devices.scratch_rtdev),
+        ("scratch.logdev", &devices.scratch_logdev),
+    ] {
+        let Some(device) = device else {
+            continue;
+        };
+        if default {
+            options.push(uconfig_set_value(
+                name,
+                &format!("pkgs.lib.mkDefault \"{}\"", device),
+            ));
+        } else {
+            options.push(uconfig_set_value_str(name, device));
+        }
+    }
+
+    format!("dev = {{ {} }};", options.join("\n"))
 }
 
-pub fn uconfig_xfstests(config: &XfstestsConfig) -> String {
+pub fn uconfig_xfstests(config: &XfstestsConfig, sources: &mut Sources) -> Result<String> {
     let mut options: Vec<String> = vec![];
 
-    if let Some(rev) = &config.rev {
+    if let Some(path) = &config.path {
+        let src = sources.path(path, "xfstests", &[".git"])?;
+        options.push(uconfig_set_value("src", &src));
+    } else if let Some(rev) = &config.rev {
         let repo = if let Some(repo) = &config.repo {
             repo
         } else {
-            &XfstestsConfig::default().repo.unwrap()
+            XFSTESTS_REPO
         };
 
-        let src = nurl(&repo, &rev).expect("Failed to fetch xfstests");
+        let src = sources
+            .fetch(repo, rev)
+            .context("Failed to fetch xfstests source")?;
         options.push(uconfig_set_value("src", &src));
     };
 
     if let Some(args) = &config.args {
-        options.push(uconfig_set_value_str("arguments", &args));
+        options.push(uconfig_set_value_str("arguments", args));
     };
 
     if let Some(devices) = &config.devices {
-        let mut dev_options: Vec<String> = vec![];
-
-        if let Some(test_dev) = &devices.test {
-            dev_options.push(uconfig_set_value_str("test.main", &test_dev));
-        };
-
-        if let Some(rtdev) = &devices.test_rtdev {
-            dev_options.push(uconfig_set_value_str("test.rtdev", &rtdev));
-        };
-
-        if let Some(logdev) = &devices.test_logdev {
-            dev_options.push(uconfig_set_value_str("test.logdev", &logdev));
-        };
-
-        if let Some(scratch_dev) = &devices.scratch {
-            dev_options.push(uconfig_set_value_str("scratch.main", &scratch_dev));
-        };
-
-        if let Some(rtdev) = &devices.scratch_rtdev {
-            dev_options.push(uconfig_set_value_str("scratch.rtdev", &rtdev));
-        };
-
-        if let Some(logdev) = &devices.scratch_logdev {
-            dev_options.push(uconfig_set_value_str("scratch.logdev", &logdev));
-        };
-
-        options.push(format!("dev = {{ {} }};", &dev_options.join("\n")));
+        options.push(uconfig_devices(devices, false));
     };
 
     if let Some(filesystem) = &config.filesystem {
-        options.push(uconfig_set_value_str("filesystem", &filesystem));
+        options.push(uconfig_set_value_str("filesystem", filesystem));
     };
 
     if let Some(extra_env) = &config.extra_env {
@@ -178,157 +186,367 @@ pub fn uconfig_xfstests(config: &XfstestsConfig) -> String {
         ));
     };
 
-    if let Some(hooks) = &config.hooks {
-        let path = PathBuf::from(hooks);
-        let path = path.to_str().expect("Failed to retrieve hooks path");
-        options.push(uconfig_set_value("hooks", path));
-    };
-
     if let Some(headers) = &config.kernel_headers {
         if let (Some(version), Some(rev), Some(repo)) =
             (&headers.version, &headers.rev, &headers.repo)
         {
-            let src = nurl(&repo, &rev)
-                .expect("Failed to fetch kernel source for xfstests headers");
+            let src = sources
+                .fetch(repo, rev)
+                .context("Failed to fetch kernel source for xfstests headers")?;
             let value =
                 format!("pkgs.kd.lib.buildKernelHeaders {{ src = {src}; version = \"{version}\"; }}");
             options.push(uconfig_set_value("kernelHeaders", &value));
         };
     }
 
-    format!("services.xfstests = {{ {} }};", &options.join("\n"))
+    Ok(format!("services.xfstests = {{ {} }};", &options.join("\n")))
+}
+
+/// Build artefacts which are not copied into the Nix store when kernel is
+/// built from the local worktree. Generated headers of an in-tree build make
+/// the O= build refuse the tree as not clean.
+pub const KERNEL_EXCLUDE: [&str; 25] = [
+    ".git",
+    ".kd",
+    "result",
+    ".config",
+    ".config.old",
+    "*.bup",
+    ".version",
+    "vmlinux",
+    "vmlinux.a",
+    "vmlinux.o",
+    "vmlinux.symvers",
+    "bzImage",
+    "System.map",
+    "Module.symvers",
+    "modules.order",
+    "include/config",
+    "include/generated",
+    "arch/*/include/generated",
+    "*.o",
+    "*.a",
+    "*.ko",
+    "*.cmd",
+    "*.mod",
+    "*.mod.c",
+    "*.tmp",
+];
+
+/// Escape text for XML content and attributes
+pub fn xml_escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('\'', "&apos;")
+        .replace('"', "&quot;")
+}
+
+/// Copy `path` to the first free `<path>.<N>.bup`, older backups are never
+/// overwritten
+pub fn backup(path: &Path) -> Result<PathBuf> {
+    let name = path
+        .file_name()
+        .with_context(|| format!("Invalid path {}", path.display()))?
+        .to_string_lossy()
+        .to_string();
+
+    let mut number = 1;
+    let backup = loop {
+        let backup = path.with_file_name(format!("{name}.{number}.bup"));
+        if !backup.exists() {
+            break backup;
+        }
+        number += 1;
+    };
+
+    std::fs::copy(path, &backup).with_context(|| {
+        format!(
+            "Failed to copy {} to {}",
+            path.display(),
+            backup.display()
+        )
+    })?;
+
+    Ok(backup)
+}
+
+/// Kernel version such as "v7.0-rc1" from the kernel Makefile
+pub fn kernel_version(source: &Path) -> Result<String> {
+    let makefile = source.join("Makefile");
+    let data = std::fs::read_to_string(&makefile)
+        .with_context(|| format!("Failed to read {}", makefile.display()))?;
+
+    let variable = |name: &str| -> Option<String> {
+        data.lines().find_map(|line| {
+            let (key, value) = line.split_once('=')?;
+            if key.trim() == name {
+                Some(value.trim().to_string())
+            } else {
+                None
+            }
+        })
+    };
+
+    let (Some(version), Some(patchlevel)) = (variable("VERSION"), variable("PATCHLEVEL")) else {
+        bail!("{} is not a kernel Makefile", makefile.display());
+    };
+    let sublevel = variable("SUBLEVEL").unwrap_or_default();
+    let extraversion = variable("EXTRAVERSION").unwrap_or_default();
+
+    let sublevel = if sublevel.is_empty() || sublevel == "0" {
+        String::new()
+    } else {
+        format!(".{sublevel}")
+    };
+
+    Ok(format!("v{version}.{patchlevel}{sublevel}{extraversion}"))
 }
 
-pub fn uconfig_kernel(config: &KernelConfig) -> String {
+/// Kernel options from [kernel.config] or [kconfig_flavors.<name>] as
+/// lib.kernel attribute set
+pub fn uconfig_kconfig(config: &Table) -> Result<String> {
+    let requested = kconfig::requested(config)?;
+    Ok(format!(
+        "with pkgs.lib.kernel; {{ {} }}",
+        requested
+            .iter()
+            .map(|(name, value)| {
+                // Nix names can't start with a digit, e.g. 64BIT
+                let name = if name.starts_with(|c: char| c.is_ascii_digit()) {
+                    format!("\"{name}\"")
+                } else {
+                    name.clone()
+                };
+                format!("{name} = {value};", value = value.nix())
+            })
+            .collect::<Vec<String>>()
+            .join("\n")
+    ))
+}
+
+pub fn uconfig_kernel(
+    config: &KernelConfig,
+    kconfig_flavors: Option<&BTreeMap<String, Table>>,
+    sources: &mut Sources,
+) -> Result<String> {
     let mut options: Vec<String> = vec![];
 
-    if let Some(rev) = &config.rev {
+    if let Some(source) = &config.source {
+        let version = match &config.version {
+            Some(version) => version.clone(),
+            None => kernel_version(&sources.curdir.join(source))?,
+        };
+        let src = sources.path(source, "linux", &KERNEL_EXCLUDE)?;
+        options.push(uconfig_set_value_str("version", &version));
+        options.push(uconfig_set_value("src", &src));
+    } else if let Some(rev) = &config.rev {
         if let Some(version) = &config.version {
             let repo = if let Some(repo) = &config.repo {
                 repo
             } else {
                 "git@github.com:torvalds/linux.git"
             };
-            let src = nurl(&repo, &rev).expect("Failed to parse kernel source repo");
+            let src = sources
+                .fetch(repo, rev)
+                .context("Failed to fetch kernel source")?;
             options.push(uconfig_set_value_str("version", version));
             options.push(uconfig_set_value("src", &src));
         }
     };
 
     if let Some(flavors) = &config.flavors {
-        let value = format!(r#"with pkgs.kconfigs; [{}]"#, flavors.join(" "));
+        // Flavors defined in .kd.toml are inlined, the rest come from the flake
+        let mut list: Vec<String> = vec![];
+        for flavor in flavors {
+            match kconfig_flavors.and_then(|user| user.get(flavor)) {
+                Some(user) => {
+                    let kconfig = uconfig_kconfig(user)
+                        .with_context(|| format!("Invalid [kconfig_flavors.{}]", flavor))?;
+                    list.push(format!("({kconfig})"));
+                }
+                None => list.push(flavor.clone()),
+            }
+        }
+        let value = format!(r#"with pkgs.kconfigs; [{}]"#, list.join(" "));
         options.push(uconfig_set_value("flavors", &value));
     };
 
-    format!("kernel = {{ {} }};", options.join("\n"))
+    Ok(format!("kernel = {{ {} }};", options.join("\n")))
 }
 
-/// TODO all this parsing should be just done nrix
-pub fn generate_uconfig(state: &mut State) -> Result<String> {
-    let mut options = vec![];
+/// [vm] settings, they exist only in the VM and go to vmconfig.nix
+pub fn uconfig_vm(config: &VmConfig) -> Result<String> {
+    let mut options: Vec<String> = vec![];
+
+    if let Some(memory) = &config.memory {
+        let memory = parse_size(memory).context("Invalid VM memory size")?;
+        options.push(uconfig_set_value("memorySize", &memory.to_string()));
+    };
 
-    if let Some(packages) = &state.config.packages {
-        let mut list = String::from("with pkgs; [");
-        for package in packages {
-            list.push_str(package);
-            list.push_str("\n");
+    if let Some(cpus) = &config.cpus {
+        options.push(uconfig_set_value("cores", &cpus.to_string()));
+    };
+
+    if let Some(disk_size) = &config.disk_size {
+        let disk_size = parse_size(disk_size).context("Invalid VM root disk size")?;
+        options.push(uconfig_set_value("diskSize", &disk_size.to_string()));
+    };
+
+    if let Some(disks) = &config.disks {
+        let mut list = String::from("[");
+        for disk in disks {
+            let size = parse_size(&disk.size).context("Invalid VM disk size")?;
+            list.push_str(&size.to_string());
+            list.push('\n');
         }
-        list.push_str("]");
+        list.push(']');
+
+        options.push(uconfig_set_value("emptyDiskImages", &list));
+    };
+
+    let mut vm = vec![format!("virtualisation = {{ {} }};", options.join("\n"))];
 
-        options.push(uconfig_set_value("environment.systemPackages", &list));
+    // Disks with a role are xfstests devices unless [xfstests.devices] sets
+    // them. Only the VM has these disks, the image uses its partitions.
+    if let Some(devices) = config.devices() {
+        vm.push(format!("services.xfstests.{}", uconfig_devices(&devices, true)));
     }
 
-    let merged: SystemConfig = if state.name != "" {
-        if let Some(named) = &state.config.named {
-            if !named.contains_key(&state.name) {
-                bail!("Config doesn't define requested run: {}", &state.name);
-            }
-            let mut result = if let Some(common) = &state.config.common {
-                common.clone()
-            } else {
-                SystemConfig::default()
-            };
+    Ok(vm.join("\n"))
+}
 
-            let run_config: SystemConfig =
-                named.get(&state.name).unwrap().clone().try_into().unwrap();
-            result = result.merge(run_config).clone();
-            result
-        } else {
-            SystemConfig {
-                xfstests: state.config.xfstests.clone(),
-                xfsprogs: state.config.xfsprogs.clone(),
-                kernel: state.config.kernel.clone(),
-                ..SystemConfig::default()
+pub fn uconfig_trace(config: &TraceConfig) -> Result<String> {
+    let mut options: Vec<String> = vec![uconfig_set_value("enable", "true")];
+
+    let events: Vec<String> = config
+        .events
+        .iter()
+        .map(|event| format!("\"{}\"", event))
+        .collect();
+    options.push(uconfig_set_value(
+        "events",
+        &format!("[{}]", events.join(" ")),
+    ));
+
+    if let Some(buffer) = config.buffer_kb()? {
+        options.push(uconfig_set_value("bufferSize", &buffer.to_string()));
+    }
+
+    if config.mode.as_deref() == Some("failed") {
+        options.push(uconfig_set_value("rerunFailed", "true"));
+    }
+
+    Ok(format!("services.xfstests.trace = {{ {} }};", options.join("\n")))
+}
+
+/// Directories are mounted over 9p, read-only ones with 'ro'. Files are
+/// linked to their copies in the share dir. Only the VM has the share dir, it
+/// goes to vmconfig.nix.
+pub fn uconfig_share(entries: &BTreeMap<String, ShareEntry>, curdir: &Path) -> Result<String> {
+    let mut mounts: Vec<String> = vec![];
+    let mut options: Vec<String> = vec![];
+    let mut links: Vec<String> = vec![];
+
+    for (name, entry) in entries {
+        let source = std::path::absolute(curdir.join(&entry.source))
+            .context("Failed to parse share source path")?;
+        let target = entry.target(name);
+        if source.is_dir() {
+            mounts.push(format!(
+                "{} = {{ source = \"{}\"; target = \"{}\"; }};",
+                share::tag(name),
+                source.display(),
+                target
+            ));
+            if entry.readonly() {
+                options.push(format!("\"{}\".options = [\"ro\"];", target));
             }
+        } else {
+            links.push(format!(
+                "\"L+ {} - - - - {}/{}/{}\"",
+                target,
+                share::GUEST_DIR,
+                share::FILES,
+                name
+            ));
         }
-    } else {
-        SystemConfig {
+    }
+
+    let mut config: Vec<String> = vec![];
+    if !mounts.is_empty() {
+        config.push(format!(
+            "virtualisation.sharedDirectories = {{ {} }};",
+            mounts.join("\n")
+        ));
+    }
+    if !options.is_empty() {
+        config.push(format!(
+            "virtualisation.fileSystems = {{ {} }};",
+            options.join("\n")
+        ));
+    }
+    if !links.is_empty() {
+        config.push(uconfig_set_value(
+            "systemd.tmpfiles.rules",
+            &format!("[{}]", links.join(" ")),
+        ));
+    }
+
+    Ok(config.join("\n"))
+}
+
+/// System configuration of the run: [common] merged with the named run, or
+/// top level sections if no run is requested. Top level packages, [qemu],
+/// [vm] and [share] are defaults which [common] and named runs can override.
+pub fn system_config(state: &State) -> Result<SystemConfig> {
+    let defaults = SystemConfig {
+        packages: state.config.packages.clone(),
+        qemu: state.config.qemu.clone(),
+        vm: state.config.vm.clone(),
+        share: state.config.share.clone(),
+        ..SystemConfig::default()
+    };
+
+    if state.name.is_empty() {
+        return Ok(SystemConfig {
             xfstests: state.config.xfstests.clone(),
             xfsprogs: state.config.xfsprogs.clone(),
             kernel: state.config.kernel.clone(),
-            ..SystemConfig::default()
-        }
-    };
+            script: state.config.script.clone(),
+            trace: state.config.trace.clone(),
+            ..defaults
+        });
+    }
 
-    if let Some(config) = &merged.xfstests {
-        options.push(uconfig_xfstests(&config));
+    let Some(named) = &state.config.named else {
+        bail!("Config doesn't define any named runs, requested: {}", &state.name);
     };
-
-    if let Some(subconfig) = &merged.xfsprogs {
-        options.push(uconfig_xfsprogs(&subconfig));
+    let Some(run) = named.get(&state.name) else {
+        bail!("Config doesn't define requested run: {}", &state.name);
     };
 
-    if let Some(subconfig) = &merged.kernel {
-        if let Some(kernel) = &subconfig.prebuild {
-            let path = path::absolute(&state.curdir.join(kernel))
-                .context("Failed to parse kernel path")?;
+    let mut merged = defaults;
+    if let Some(common) = &state.config.common {
+        merged.merge(common.clone());
+    }
 
-            state.envs.insert(
-                format!("NIXPKGS_QEMU_KERNEL_kd"),
-                path.display().to_string(),
-            );
-        } else {
-            options.push(uconfig_kernel(&subconfig));
-            if let Some(config) = &subconfig.config {
-                let mut config_options: Vec<KernelConfigOption> = vec![];
-                for (key, value) in config.iter() {
-                    config_options.push(KernelConfigOption {
-                        name: key
-                            .strip_prefix("CONFIG_")
-                            .expect("Option doesn't start with CONFIG_")
-                            .to_string(),
-                        value: value.to_string().replace("\"", ""),
-                    });
-                }
-                let kernel_config_options = format!(
-                    "with pkgs.lib.kernel; {{ {} }}",
-                    config_options
-                        .into_iter()
-                        .map(|x| format!("{name} = {value};", name = x.name, value = x.value))
-                        .collect::<Vec<String>>()
-                        .join("\n")
-                );
-                options.push(uconfig_set_value("kernel.kconfig", &kernel_config_options))
-            }
-        };
-    };
+    let run: SystemConfig = run
+        .clone()
+        .try_into()
+        .with_context(|| format!("Invalid [named.{}]", &state.name))?;
+    merged.merge(run);
 
-    if let Some(subconfig) = &state.config.qemu {
-        if let Some(qemu_options) = &subconfig.options {
-            let mut list = String::from("[");
-            for option in qemu_options {
-                list.push_str("\"");
-                list.push_str(option);
-                list.push_str("\"\n");
-            }
-            list.push_str("]");
+    Ok(merged)
+}
 
-            options.push(uconfig_set_value("virtualisation.qemu.options", &list));
-        };
-    };
+/// Nix module with the system configuration. Same as [plan::Plan::new] but
+/// also puts required Nix arguments and environment into the `state`.
+pub fn generate_uconfig(state: &mut State) -> Result<String> {
+    let plan = plan::Plan::new(state)?;
+    state.args = plan.args;
+    state.envs = plan.envs;
 
-    Ok(format!(
-        include_str!("uconfig.tmpl"),
-        s_options = options.join("\n")
-    ))
+    Ok(plan.uconfig)
 }
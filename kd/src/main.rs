@@ -1,76 +1,87 @@
 use anyhow::{bail, Context, Result};
 use clap::Parser;
 use std::fs::File;
-use std::io::Write;
+use std::io::{IsTerminal, Write};
 use std::os::unix::fs::PermissionsExt;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
+use std::time::Instant;
 
+use kd::config::{parse_size, KernelConfig};
+use kd::plan::Plan;
 use kd::*;
 mod cli;
-use cli::{Cli, Commands};
+use cli::{Cli, Commands, DisksCommands, VmCommands};
 
-const CONFIG: &str = include_str!("../assets/config.kdl");
-
-fn cmd_init(_: &State) -> Result<()> {
+fn cmd_init(
+    force: bool,
+    upgrade: bool,
+    template: &Option<String>,
+    preset: &Option<String>,
+) -> Result<()> {
     let curdir = std::env::current_dir().context("No able to get current working directory")?;
-    let config_path = curdir.clone().join(".kd.toml");
+    let envdir = curdir.join(".kd");
+    let flake_dir = envdir.join("flake");
+    let config_path = curdir.join(".kd.toml");
 
-    if !config_path.exists() {
-        match &mut File::create(&config_path) {
-            Ok(target) => {
-                writeln!(target, "{}", CONFIG).context("Failed to write config to .kd.toml")?
-            }
-            Err(error) => {
-                bail!("Unable to create {}: {}", config_path.display(), error);
-            }
-        };
+    let exists = flake_dir.join("flake.nix").exists();
+    if exists && !(force || upgrade) {
+        println!("kd environment already exists in {}", flake_dir.display());
+        println!("Use --upgrade to update it from the template or --force to reinitialize");
+        return Ok(());
     }
 
-    let flake_dir = curdir.clone().join(".kd/flake");
-    if flake_dir.exists() {
-        println!("Reinitializing flake (.kd/flake exists)");
-        std::fs::remove_file(flake_dir.join("flake.nix"))?;
-        std::fs::remove_file(flake_dir.join("flake.lock"))?;
-        std::fs::remove_file(flake_dir.join("uconfig.nix"))?;
+    if upgrade && !exists {
+        bail!("Nothing to upgrade, run 'kd init' first");
     }
 
-    std::fs::create_dir_all(&flake_dir)
-        .with_context(|| format!("Unable to create {}", flake_dir.display()))?;
+    if !config_path.exists() || force {
+        if config_path.exists() {
+            let backup = backup(&config_path).context("Failed to make backup of .kd.toml")?;
+            println!("Old config saved to {}", backup.display());
+        }
 
-    let mut cmd = Command::new("nix");
-    cmd.arg("flake")
-        .arg("init")
-        .arg("--template")
-        .arg("github:alberand/kd#default")
-        .current_dir(&flake_dir);
+        let config = init::config(preset.as_deref())?;
+        std::fs::write(&config_path, config).context("Failed to write config to .kd.toml")?;
+    } else if preset.is_some() {
+        println!("Preset is not applied as .kd.toml already exists, use --force to overwrite it");
+    }
 
-    let output = cmd.output().expect("Failed to execute command");
+    let template = init::template_ref(template.as_deref(), &curdir)?;
+    if exists {
+        println!("Updating flake from {}", template);
+    } else {
+        println!("Creating flake from {}", template);
+    }
 
-    if !output.status.success() {
-        println!("Failed to create Nix Flake:");
-        println!("{}", String::from_utf8_lossy(&output.stderr));
-        std::process::exit(1);
+    let template_dir = init::fetch_template(&template, &envdir)?;
+    let kept = init::install_template(&template_dir, &flake_dir);
+    std::fs::remove_dir_all(&template_dir)
+        .with_context(|| format!("Unable to remove {}", template_dir.display()))?;
+    for file in kept? {
+        println!("Kept existing {}", file);
     }
 
-    let user_config = PathBuf::from(flake_dir.clone()).join("uconfig.nix");
-    File::create(&user_config)
-        .with_context(|| format!("Unable to create {}", user_config.display()))?;
+    if upgrade {
+        return Ok(());
+    }
 
     let direnv = curdir.join(".envrc");
-    if direnv.exists() {
+    let content = std::fs::read_to_string(&direnv).unwrap_or_default();
+    let uses_flake = content.lines().any(|line| line.trim() == doctor::ENVRC);
+    if direnv.exists() && !uses_flake {
         let mut backup = direnv.clone();
         backup.set_extension("bup");
         std::fs::copy(&direnv, backup).context("Failed to make backup of .envrc")?;
         println!("You already have .envrc. Update with:");
         println!(
-            "\tmv .envrc .envrc.bup && echo \"use flake path:.kd/flake\" > .envrc"
+            "\tmv .envrc .envrc.bup && echo \"{}\" > .envrc",
+            doctor::ENVRC
         );
-    } else {
+    } else if !direnv.exists() {
         match &mut File::create(&direnv) {
             Ok(target) => {
-                writeln!(target, "use flake path:.kd/flake")
-                    .context("Failed to overwrite .envrc")?;
+                writeln!(target, "{}", doctor::ENVRC).context("Failed to overwrite .envrc")?;
             }
             Err(error) => {
                 bail!("Unable to create {}: {}", direnv.display(), error);
@@ -84,70 +95,473 @@ fn cmd_init(_: &State) -> Result<()> {
     Ok(())
 }
 
-fn cmd_build(state: &mut State, target: &Option<String>) -> Result<()> {
-    match generate_uconfig(state) {
-        Ok(content) => {
-            let mut file = std::fs::File::create(&state.user_config)
-                .context("Failed to create user config uconfig.nix")?;
-            file.write_all(content.as_bytes())
-                .context("Failed to write out uconfig.nix data")?;
-        }
-        Err(error) => {
-            bail!("Failed to generate nix config: {error}");
+/// [kernel.config] of the kernel kd builds, prebuild kernels are checked in
+/// prepare_prebuild()
+fn requested_kconfig(plan: &Plan) -> Result<Option<(KernelConfig, kconfig::Options)>> {
+    let Some(kernel) = plan.kernel() else {
+        return Ok(None);
+    };
+    if kernel.prebuild.is_some() {
+        return Ok(None);
+    }
+    let Some(config) = &kernel.config else {
+        return Ok(None);
+    };
+
+    let requested = kconfig::requested(config).context("Invalid [kernel.config]")?;
+    Ok(Some((kernel.clone(), requested)))
+}
+
+/// Check that options in [kernel.config] exist in the kernel source and warn
+/// if flavors set them differently
+fn check_kconfig(plan: &Plan) -> Result<()> {
+    let Some((kernel, requested)) = requested_kconfig(plan)? else {
+        return Ok(());
+    };
+
+    let unknown = kconfig::unknown(&requested, &kconfig::symbols(plan)?);
+    if !unknown.is_empty() {
+        let names: Vec<String> = unknown.iter().map(|name| format!("CONFIG_{name}")).collect();
+        bail!(
+            "Kernel source doesn't have these options from [kernel.config]: {}",
+            names.join(", ")
+        );
+    }
+
+    let flavors = kconfig::flavors(plan)?;
+    let selected = kernel.flavors.unwrap_or_default();
+    for conflict in kconfig::conflicts(&requested, &flavors, &selected) {
+        // Default flavor is applied on top of everything, others are
+        // overridden by [kernel.config]
+        let used = if conflict.flavor == kconfig::DEFAULT_FLAVOR {
+            &conflict.flavor_value
+        } else {
+            &conflict.value
+        };
+        println!(
+            "Warning: CONFIG_{} = {} conflicts with '{}' flavor ({}), {} is used",
+            conflict.name, conflict.value, conflict.flavor, conflict.flavor_value, used
+        );
+    }
+
+    Ok(())
+}
+
+/// Warn about options from [kernel.config] which didn't end up in the final
+/// .config, e.g. due to unmet dependencies
+fn report_kconfig(plan: &Plan, config: Option<&kconfig::Kconfig>) -> Result<()> {
+    let Some((_, requested)) = requested_kconfig(plan)? else {
+        return Ok(());
+    };
+
+    let config = match config {
+        Some(config) => config.clone(),
+        None => kconfig::final_config(plan)?,
+    };
+
+    for mismatch in kconfig::check(&requested, &config) {
+        println!(
+            "Warning: CONFIG_{} = {} was requested, but it's {} in the final .config (check its dependencies)",
+            mismatch.name,
+            mismatch.expected,
+            mismatch.actual.as_deref().unwrap_or("not set")
+        );
+    }
+
+    Ok(())
+}
+
+fn cmd_targets(plan: &Plan) -> Result<()> {
+    for target in targets::available(plan)? {
+        match targets::describe(&target) {
+            Some(description) => println!("{:<16}{}", target, description),
+            None => println!("{}", target),
         }
     }
 
-    let target: &str = if let Some(t) = target { t } else { "image" };
-    let package = format!("path:{}#{}", state.flake_dir.display(), target);
+    Ok(())
+}
 
-    let mut cmd = Command::new("nix");
-    cmd.arg("build").args(&state.args).arg(&package);
+fn cmd_build(plan: &Plan, target: &Option<Option<String>>) -> Result<()> {
+    let target = match target {
+        Some(None) => return cmd_targets(plan),
+        Some(Some(target)) => Some(target.as_str()),
+        None => None,
+    };
 
-    if state.debug {
+    let prebuild = plan.prebuild()?.is_some();
+    let target: &str = match target {
+        // With prebuild kernel the VM is the only thing which uses it
+        None if prebuild => plan.vm_target(),
+        None => "image",
+        Some("vm") => plan.vm_target(),
+        Some(target) => target,
+    };
+    // Well-known ones are always there, don't wait for flake evaluation
+    if targets::describe(target).is_none() {
+        targets::validate(target, &targets::available(plan)?)?;
+    }
+    if prebuild && target != plan.vm_target() {
+        println!(
+            "Note! 'prebuild' kernel is used only by the VM, {} is built with the kernel from the config",
+            target
+        );
+    }
+
+    check_kconfig(plan)?;
+
+    for artifact in build_target(plan, target)? {
+        println!(
+            "{}\t{}",
+            artifact.path.display(),
+            disks::human_size(artifact.size)
+        );
+    }
+
+    report_kconfig(plan, None)
+}
+
+/// 'nix build' the `target` with progress shown to the user
+fn build_target(plan: &Plan, target: &str) -> Result<Vec<targets::Artifact>> {
+    let mut cmd = plan.nix("build");
+    cmd.arg("--print-out-paths")
+        .arg(plan.package(target))
+        .stdout(Stdio::piped());
+
+    if plan.debug {
         println!("command: {:?}", cmd);
     }
 
-    cmd.spawn()
-        .context("Failed to spawn 'nix build'")?
-        .wait()
+    let output = cmd
+        .spawn()
+        .context("Failed to spawn 'nix build' (see 'kd doctor')")?
+        .wait_with_output()
         .context("'nix build' wasn't running")?;
+    if !output.status.success() {
+        bail!("'nix build' failed");
+    }
 
-    Ok(())
+    let artifacts = targets::artifacts(&String::from_utf8_lossy(&output.stdout))?;
+    let outputs: Vec<PathBuf> = artifacts.iter().map(|a| a.path.clone()).collect();
+    clean::record_gc_roots(&plan.curdir, &plan.envdir, &outputs)?;
+
+    Ok(artifacts)
 }
 
-fn cmd_run(state: &mut State) -> Result<()> {
-    match generate_uconfig(state) {
-        Ok(content) => {
-            let mut file = std::fs::File::create(&state.user_config)
-                .context("Failed to create user config uconfig.nix")?;
-            file.write_all(content.as_bytes())
-                .context("Failed to write out uconfig.nix data")?;
+fn cmd_deploy(
+    plan: &Plan,
+    uri: &str,
+    domain: &Option<String>,
+    pool: &str,
+    network: &Option<String>,
+) -> Result<()> {
+    let name = match domain {
+        Some(domain) => domain.clone(),
+        None => {
+            let dir = plan
+                .curdir
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_default();
+            if plan.name.is_empty() {
+                format!("kd-{}", dir)
+            } else {
+                format!("kd-{}-{}", dir, plan.name)
+            }
         }
-        Err(error) => {
-            bail!("Failed to generate nix config: {error}")
+    };
+    deploy::validate_name(&name)?;
+    if plan.prebuild()?.is_some() {
+        println!("Note! 'prebuild' kernel is used only by the VM, image is built with the kernel from the config");
+    }
+
+    check_kconfig(plan)?;
+    let Some(output) = build_target(plan, "image")?.into_iter().next() else {
+        bail!("'nix build' didn't output the image");
+    };
+    let image = deploy::find_image(&output.path)?;
+
+    let vm = plan.system.vm.clone().unwrap_or_default();
+    let memory = match &vm.memory {
+        Some(memory) => parse_size(memory).context("Invalid VM memory size")?,
+        None => 4096,
+    };
+
+    let virsh = deploy::Virsh::new(uri, plan.debug);
+    let deployment = deploy::Deployment {
+        virsh: &virsh,
+        xml: plan.envdir.join(format!("{}.xml", name)),
+        name,
+        pool: pool.to_string(),
+        network: network.clone(),
+        memory,
+        cpus: vm.cpus.unwrap_or(4),
+    };
+    deploy::deploy(&deployment, &image)?;
+
+    println!("Done. Console:");
+    println!("    virsh --connect {} console {}", uri, deployment.name);
+    Ok(())
+}
+
+/// Options required by the flavors and [kernel.config]
+fn required_kconfig(plan: &Plan, kernel: &KernelConfig) -> Result<kconfig::Options> {
+    let flavors = kconfig::flavors(plan)?;
+    let mut required = kconfig::required(&flavors, kernel.flavors.as_deref().unwrap_or(&[]))?;
+    if let Some(requested) = &kernel.config {
+        required.extend(kconfig::requested(requested).context("Invalid [kernel.config]")?);
+    }
+
+    Ok(required)
+}
+
+/// Share modules of the prebuild kernel with the VM and check that its .config
+/// has everything kd needs
+fn prepare_prebuild(plan: &Plan) -> Result<()> {
+    let Some(image) = plan.prebuild()? else {
+        return Ok(());
+    };
+    let kernel = plan.kernel().cloned().unwrap_or_default();
+    let tree = prebuild::tree(&image);
+
+    if let Some(modules) = &kernel.modules {
+        let staging = std::path::absolute(plan.curdir.join(modules))
+            .context("Failed to parse modules path")?;
+        if kernel.modules_install.unwrap_or(true) {
+            let Some(tree) = &tree else {
+                bail!(
+                    "Unable to find kernel tree of {}, install modules by hand and set 'modules_install = false'",
+                    image.display()
+                );
+            };
+            println!("Installing modules into {}", staging.display());
+            prebuild::modules_install(plan, tree, &staging)?;
         }
+
+        let releases = prebuild::stage_modules(&staging, &plan.envdir.join("share"))?;
+        println!("Modules of {} are shared with the VM", releases.join(", "));
     }
 
-    state
-        .args
-        .push(format!("path:{}#vm", state.flake_dir.display()));
-    let mut cmd = Command::new("nix");
-    cmd.arg("run")
-        .args(state.args.clone())
-        .envs(state.envs.clone());
+    let Some(tree) = tree else {
+        println!(
+            "Warning: unable to find kernel tree of {}, .config is not checked",
+            image.display()
+        );
+        return Ok(());
+    };
 
-    if state.debug {
+    let config = kconfig::load(&tree.join(".config"))?;
+    let expected = required_kconfig(plan, &kernel)?;
+
+    for mismatch in kconfig::check(&expected, &config) {
+        println!(
+            "Warning: CONFIG_{} is {} in the prebuild kernel, expected {}",
+            mismatch.name,
+            mismatch.actual.as_deref().unwrap_or("not set"),
+            mismatch.expected
+        );
+    }
+
+    Ok(())
+}
+
+/// Start the VM. If xfstests runs, on a terminal the console is followed and
+/// only its progress is shown, the runner still writes all of it to
+/// execution_*.log. Results are uploaded after the run with
+/// `[results] upload = true`.
+fn run_vm(plan: &Plan, mut cmd: Command, plain: bool) -> Result<()> {
+    let share = share::dir(&plan.envdir);
+    // Results of the previous run are removed from the share dir
+    progress::save_history(&plan.results_dir(), &plan.envdir)?;
+    share::prepare(&plan.system, &plan.curdir, &share)?;
+    results::save_variant(&plan.results_dir(), &plan.variant())?;
+    // Tests to skip are only for the VM reset by the watchdog
+    watchdog::clean(&share)?;
+
+    if hooks::install(plan.system.xfstests.as_ref(), &plan.curdir, &share)? {
+        println!("xfstests hooks are in {}", share.join(hooks::HOOKS).display());
+    }
+
+    // Left by QEMU which didn't exit cleanly
+    let socket = qmp::socket(&plan.envdir);
+    let _ = std::fs::remove_file(&socket);
+
+    let mut watchdog = plan
+        .system
+        .xfstests
+        .as_ref()
+        .and_then(|xfstests| xfstests.timeouts.clone())
+        .map(|timeouts| watchdog::Watchdog::new(timeouts, &socket, &share));
+
+    // Without [xfstests] the VM is an interactive shell, keep the console
+    let xfstests = plan.system.xfstests.is_some();
+    let output = if xfstests && !plain && std::io::stdout().is_terminal() {
+        println!(
+            "Console goes to {}, use --plain to see it here",
+            share.join("execution_*.log").display()
+        );
+        Some(progress::Output::Status)
+    } else if watchdog.is_some() {
+        Some(progress::Output::Raw)
+    } else {
+        None
+    };
+    if output.is_some() {
+        cmd.stdout(Stdio::piped());
+    }
+
+    if plan.debug {
         println!("command: {:?}", cmd);
     }
 
-    cmd.spawn()
-        .context("Failed to spawn 'nix run'")?
-        .wait()
-        .context("'nix run' wasn't running")?;
+    let mut child = cmd
+        .spawn()
+        .context("Failed to spawn 'nix run' (see 'kd doctor')")?;
+    if let (Some(stdout), Some(output)) = (child.stdout.take(), output) {
+        let history = progress::load_history(&plan.envdir);
+        let progress = progress::Progress::new(history, Instant::now());
+        progress::follow(
+            stdout,
+            progress,
+            &mut std::io::stdout(),
+            output,
+            watchdog.as_mut(),
+        )?;
+    }
+    child.wait().context("'nix run' wasn't running")?;
+
+    for path in share::sync(&plan.system, &plan.curdir, &share)? {
+        println!("{} was changed in the VM, copied it back", path.display());
+    }
+
+    watchdog::clean(&share)?;
+    progress::save_history(&plan.results_dir(), &plan.envdir)?;
+
+    if plan
+        .results
+        .as_ref()
+        .is_some_and(|results| results.upload == Some(true))
+    {
+        upload_results(plan)?;
+    }
+
+    Ok(())
+}
+
+/// Boot #image the same way it boots on the real hardware or libvirt
+fn cmd_run_image(state: &State, plan: &Plan, fresh: bool, plain: bool) -> Result<()> {
+    if plan.prebuild()?.is_some() {
+        println!("Note! 'prebuild' kernel is used only by the VM, image is built with the kernel from the config");
+    }
+    if plan.system.share.as_ref().is_some_and(|entries| !entries.is_empty()) {
+        bail!("[share] is mounted only into the VM, remove it to run the image");
+    }
+    check_kconfig(plan)?;
+
+    if fresh && disks::reset_image(state)? {
+        println!("Removed image disk, it will be created from the image again");
+    }
+
+    let mut cmd = plan.nix("run");
+    cmd.arg(plan.package("run-image"))
+        .env("KD_IMAGE_DISK", disks::image_disk(state));
+
+    run_vm(plan, cmd, plain)
+}
+
+fn cmd_run(state: &State, plan: &Plan, fresh: bool, image: bool, plain: bool) -> Result<()> {
+    if image {
+        return cmd_run_image(state, plan, fresh, plain);
+    }
+
+    prepare_prebuild(plan)?;
+    check_kconfig(plan)?;
+
+    if fresh && disks::reset(state)? {
+        println!("Removed root disk, VM will start with a new one");
+    }
+
+    let mut cmd = plan.nix("run");
+    cmd.arg(plan.package(plan.vm_target()));
+    run_vm(plan, cmd, plain)
+}
+
+fn upload_results(plan: &Plan) -> Result<()> {
+    let Some(config) = &plan.results else {
+        bail!("There's no [results] in the config, nowhere to upload");
+    };
+
+    let run = upload::RunInfo::new(plan);
+    let log = upload::latest_log(&share::dir(&plan.envdir));
+    let results = plan.results_dir();
+    for destination in upload::upload(config, &run, &results, log.as_deref(), &plan.envdir)? {
+        println!("Results uploaded to {}", destination);
+    }
+
+    Ok(())
+}
+
+fn cmd_results(
+    plan: &Plan,
+    upload: bool,
+    format: &Option<String>,
+    input: &[String],
+    output: &Option<String>,
+) -> Result<()> {
+    if upload {
+        return upload_results(plan);
+    }
+
+    if let Some(format) = format {
+        let format = results::Format::parse(format)?;
+        let mut suites = vec![];
+        if input.is_empty() {
+            suites = results::load(&plan.results_dir())?;
+        }
+        // Runs of several variants go into one report
+        for input in input {
+            suites.extend(results::load(&plan.curdir.join(input))?);
+        }
+        let report = results::render(&suites, format)?;
+
+        match output {
+            Some(output) => std::fs::write(output, report)
+                .with_context(|| format!("Failed to write report to {}", output))?,
+            None => print!("{report}"),
+        }
+        return Ok(());
+    }
+
+    let run = upload::RunInfo::new(plan);
+    println!("results: {}", plan.results_dir().display());
+    println!("host:    {}", run.host);
+    println!("kernel:  {}", run.kernel);
+    println!("variant: {}", run.variant);
+    println!("date:    {}", run.date);
 
     Ok(())
 }
 
+fn cmd_report(plan: &Plan, html: &str, input: &[String]) -> Result<()> {
+    let reports = if input.is_empty() {
+        // The flake is built only to compare [kernel.config] with the flavors
+        let flavors = match plan.kernel().and_then(|kernel| kernel.config.as_ref()) {
+            Some(_) => kconfig::flavors(plan)?,
+            None => kconfig::Flavors::new(),
+        };
+        vec![report::Report::new(plan, &flavors)?]
+    } else {
+        input
+            .iter()
+            .map(|dir| report::Report::from_dir(&plan.curdir.join(dir)))
+            .collect::<Result<Vec<report::Report>>>()?
+    };
+
+    let index = report::write(&plan.curdir.join(html), &reports)?;
+    println!("Report written to {}", index.display());
+    Ok(())
+}
+
 fn cmd_update(state: &State) -> Result<()> {
     let package = format!("path:{}", state.flake_dir.display());
     let mut cmd = Command::new("nix");
@@ -162,92 +576,428 @@ fn cmd_update(state: &State) -> Result<()> {
     }
 
     cmd.spawn()
-        .context("Failed to spawn 'nix flake update'")?
+        .context("Failed to spawn 'nix flake update' (see 'kd doctor')")?
         .wait()
         .context("'nix flake update' wasn't running")?;
 
     Ok(())
 }
 
-fn cmd_config(state: &mut State, output: Option<String>) -> Result<()> {
-    match generate_uconfig(state) {
-        Ok(content) => {
-            let mut file = std::fs::File::create(&state.user_config)
-                .context("Failed to create user config uconfig.nix")?;
-            file.write_all(content.as_bytes())
-                .context("Failed to write out uconfig.nix data")?;
-        }
-        Err(error) => {
-            bail!("Failed to generate nix config: {error}")
-        }
+/// Merge options required by kd into the existing `output` config and let
+/// 'make olddefconfig' resolve dependencies. Returns path to the merged config.
+fn merge_config(plan: &Plan, output: &Path) -> Result<PathBuf> {
+    if !output.exists() {
+        bail!(
+            "Nothing to merge into, {} doesn't exist",
+            output.display()
+        );
     }
 
-    let package = format!("path:{}#kconfig", state.flake_dir.display());
-    let mut cmd = Command::new("nix");
-    cmd.arg("build").arg(&package).current_dir(&state.envdir);
+    let kernel = plan.kernel().cloned().unwrap_or_default();
+    let tree = match (&kernel.source, plan.prebuild()?) {
+        (Some(source), _) => std::path::absolute(plan.curdir.join(source))
+            .context("Failed to parse source path")?,
+        (None, Some(image)) => prebuild::tree(&image).unwrap_or(plan.curdir.clone()),
+        (None, None) => plan.curdir.clone(),
+    };
+    if !tree.join("Makefile").exists() {
+        bail!(
+            "{} is not a kernel tree, --merge needs one to run 'make olddefconfig'",
+            tree.display()
+        );
+    }
 
-    if state.debug {
-        println!("command: {:?}", cmd);
+    let required = required_kconfig(plan, &kernel)?;
+    let data = std::fs::read_to_string(output)
+        .with_context(|| format!("Failed to read {}", output.display()))?;
+    let merged = plan.envdir.join("config.merged");
+    std::fs::write(&merged, kconfig::merge(&data, &required))
+        .with_context(|| format!("Failed to write {}", merged.display()))?;
+    kconfig::olddefconfig(plan, &tree, &merged)?;
+
+    for mismatch in kconfig::check(&required, &kconfig::load(&merged)?) {
+        println!(
+            "Warning: CONFIG_{} = {} was requested, but it's {} after olddefconfig (check its dependencies)",
+            mismatch.name,
+            mismatch.expected,
+            mismatch.actual.as_deref().unwrap_or("not set")
+        );
     }
 
-    cmd.spawn()
-        .context("Failed to spawn 'nix build .#kconfig'")?
-        .wait()
-        .context("'nix build .#kconfig' wasn't running")?;
+    Ok(merged)
+}
 
+fn cmd_config(plan: &Plan, output: Option<String>, diff: bool, merge: bool) -> Result<()> {
     let output = if let Some(output) = output {
-        PathBuf::from(output)
+        plan.curdir.join(output)
+    } else {
+        plan.curdir.clone().join(".config")
+    };
+
+    let source = if merge {
+        merge_config(plan, &output)?
+    } else {
+        if plan.prebuild()?.is_some() {
+            bail!("kd doesn't build 'prebuild' kernel, use --merge to add required options to its .config");
+        }
+
+        check_kconfig(plan)?;
+
+        let mut cmd = plan.nix("build");
+        cmd.arg(plan.package("kconfig")).current_dir(&plan.envdir);
+
+        if plan.debug {
+            println!("command: {:?}", cmd);
+        }
+
+        let status = cmd
+            .spawn()
+            .context("Failed to spawn 'nix build .#kconfig' (see 'kd doctor')")?
+            .wait()
+            .context("'nix build .#kconfig' wasn't running")?;
+        if !status.success() {
+            bail!("'nix build .#kconfig' failed");
+        }
+
+        let source = plan.envdir.clone().join("result");
+        report_kconfig(plan, Some(&kconfig::load(&source)?))?;
+        source
+    };
+
+    let result = if diff {
+        diff_config(&output, &source)
+    } else {
+        install_config(&output, &source)
+    };
+
+    if merge {
+        // olddefconfig leaves the previous version next to the config
+        for path in [source.clone(), source.with_extension("merged.old")] {
+            if path.exists() {
+                std::fs::remove_file(&path)
+                    .with_context(|| format!("Failed to remove {}", path.display()))?;
+            }
+        }
+    }
+
+    result
+}
+
+fn diff_config(output: &Path, source: &Path) -> Result<()> {
+    let old = if output.exists() {
+        kconfig::load(output)?
     } else {
-        state.curdir.clone().join(".config")
+        kconfig::Kconfig::new()
     };
 
+    for change in kconfig::diff(&old, &kconfig::load(source)?) {
+        println!("{}", change);
+    }
+
+    Ok(())
+}
+
+fn install_config(output: &Path, source: &Path) -> Result<()> {
     if output.exists() {
-        let mut backup = output.clone();
-        backup.set_extension("bup");
-        std::fs::copy(&output, backup).context("Failed to create config backup")?;
+        let backup = backup(output).context("Failed to create config backup")?;
+        println!("Old config saved to {}", backup.display());
     }
 
-    let source = state.envdir.clone().join("result");
-    std::fs::copy(source, &output).context("Failed to copy config to .config")?;
+    std::fs::copy(source, output).context("Failed to copy config to .config")?;
     std::fs::set_permissions(output, std::fs::Permissions::from_mode(0o644))
         .context("Failed to set 644 permission on config")
 }
 
-fn cmd_debug(state: &mut State, output: &bool) -> Result<()> {
-    match generate_uconfig(state) {
-        Ok(content) => {
-            if *output {
-                let mut cmd = Command::new("alejandra")
-                    .stdin(Stdio::piped())
-                    .arg("--quiet")
-                    .spawn()
-                    .context("alejandra (nix code formatter) failed to run")?;
-                write!(
-                    cmd.stdin
-                        .as_mut()
-                        .context("No input content for alejandra")?,
-                    "{}",
-                    content
-                )
-                .unwrap();
-                cmd.wait().context("'alejandra' failed to run")?;
+fn cmd_debug(plan: &Plan, output: &bool) -> Result<()> {
+    let name = if plan.name.is_empty() {
+        "-"
+    } else {
+        &plan.name
+    };
+    println!("run:    {}", name);
+    println!("vm:     {}", plan.package(plan.vm_target()));
+    println!("args:   {}", plan.args.join(" "));
+    for (key, value) in &plan.envs {
+        println!("env:    {}={}", key, value);
+    }
+
+    if *output {
+        // uconfig.nix, then vmconfig.nix
+        for config in [&plan.uconfig, &plan.vmconfig] {
+            let mut cmd = Command::new("alejandra")
+                .stdin(Stdio::piped())
+                .arg("--quiet")
+                .spawn()
+                .context("alejandra (nix code formatter) failed to run")?;
+            write!(
+                cmd.stdin
+                    .as_mut()
+                    .context("No input content for alejandra")?,
+                "{}",
+                config
+            )
+            .unwrap();
+            cmd.wait().context("'alejandra' failed to run")?;
+        }
+    }
+
+    Ok(())
+}
+
+fn cmd_disks(state: &State, command: &DisksCommands) -> Result<()> {
+    match command {
+        DisksCommands::List {} => {
+            for disk in disks::list(state)? {
+                let size = match disk.size {
+                    Some(size) => disks::human_size(size),
+                    None => "-".to_string(),
+                };
+                let path = match &disk.path {
+                    Some(path) if path.exists() => path.display().to_string(),
+                    Some(path) => format!("{} (not created yet)", path.display()),
+                    None => "created on boot".to_string(),
+                };
+                println!("{:<24} {:>8}  {}", disk.name, size, path);
+            }
+        }
+        DisksCommands::Reset {} => {
+            if disks::reset(state)? {
+                println!("Root disk removed, VM will start with a new one");
+            } else {
+                println!("There's no root disk, nothing to reset");
             }
-            Ok(())
         }
-        Err(error) => {
-            bail!("Failed to generate nix config: {error}")
+        DisksCommands::Snapshot { name } => {
+            let path = disks::snapshot(state, name)?;
+            println!("Snapshot saved to {}", path.display());
+        }
+        DisksCommands::Restore { name } => {
+            disks::restore(state, name)?;
+            println!("Root disk restored from '{}'", name);
+        }
+    }
+
+    Ok(())
+}
+
+fn cmd_vm(state: &State, command: &VmCommands) -> Result<()> {
+    let socket = qmp::socket(&state.envdir);
+    if !socket.exists() {
+        if let VmCommands::Status {} = command {
+            println!("VM isn't running");
+            return Ok(());
+        }
+        bail!("VM isn't running, there's no {}", socket.display());
+    }
+    let mut qmp = qmp::Qmp::connect(&socket)?;
+
+    match command {
+        VmCommands::Status {} => println!("VM is {}", qmp.status()?),
+        VmCommands::Stop { force: false } => {
+            qmp.powerdown()?;
+            println!("Asked the guest to power off");
+        }
+        VmCommands::Stop { force: true } => {
+            qmp.quit()?;
+            println!("QEMU stopped");
+        }
+        VmCommands::Reset {} => {
+            qmp.reset()?;
+            println!("VM reset");
+        }
+        VmCommands::Sysrq { key } => {
+            qmp.sysrq(*key)?;
+            println!("Sent sysrq-{}, output is on the console", key);
+        }
+        VmCommands::Dump { output } => {
+            let output = match output {
+                Some(output) => std::path::absolute(output)
+                    .with_context(|| format!("Failed to parse {}", output.display()))?,
+                None => state.envdir.join("vmcore"),
+            };
+            println!("Dumping guest memory to {}", output.display());
+            let size = qmp.dump(&output)?;
+            println!("Done, {} written", disks::human_size(size));
+        }
+    }
+
+    Ok(())
+}
+
+fn cmd_dump(plan: &Plan) -> Result<()> {
+    let socket = qmp::socket(&plan.envdir);
+    if !socket.exists() {
+        bail!("VM isn't running, there's no {}", socket.display());
+    }
+    let mut qmp = qmp::Qmp::connect(&socket)?;
+    let status = qmp.status()?;
+
+    let dir = dump::new_dir(&plan.envdir)?;
+    // Before the dump, the VM may go away while it's written
+    let vmlinux = dump::save_vmlinux(plan, &dir)?;
+    println!("Kernel is {}", vmlinux.display());
+
+    let vmcore = dir.join(dump::VMCORE);
+    println!("Dumping guest memory ({}) to {}", status, vmcore.display());
+    let size = qmp.dump(&vmcore)?;
+    println!("Done, {} written. Open it with 'kd drgn'", disks::human_size(size));
+
+    Ok(())
+}
+
+fn cmd_drgn(
+    plan: &Plan,
+    dump: &Option<PathBuf>,
+    script: &Option<PathBuf>,
+    args: &[String],
+) -> Result<()> {
+    let dir = match dump {
+        Some(dir) => plan.curdir.join(dir),
+        None => dump::latest(&plan.envdir)?,
+    };
+    let script = script.as_ref().map(|script| plan.curdir.join(script));
+    let drgn = dump::drgn_args(&dir, script.as_deref(), args)?;
+
+    // uconfig.nix is the one of the last run, it can use local sources
+    let mut cmd = plan.nix("run");
+    cmd.arg("--impure")
+        .arg(plan.package("drgn"))
+        .arg("--")
+        .args(drgn);
+
+    if plan.debug {
+        println!("command: {:?}", cmd);
+    }
+
+    let status = cmd
+        .status()
+        .context("Failed to spawn 'nix run' (see 'kd doctor')")?;
+    if !status.success() {
+        bail!("drgn failed");
+    }
+
+    Ok(())
+}
+
+fn cmd_clean(
+    state: &State,
+    selection: &clean::Selection,
+    older_than: &Option<String>,
+    dry_run: bool,
+) -> Result<()> {
+    if selection.is_empty() {
+        bail!("Nothing to clean, use --logs, --images, --results, --backups, --gc-roots or --all");
+    }
+
+    let age = match older_than {
+        Some(age) => Some(clean::parse_age(age)?),
+        None => None,
+    };
+
+    let mut artifacts: Vec<clean::Artifact> = clean::artifacts(state)?
+        .into_iter()
+        .filter(|artifact| selection.includes(artifact.kind))
+        .collect();
+    if selection.gc_roots {
+        artifacts.extend(clean::gc_roots(state)?);
+    }
+    artifacts.retain(|artifact| age.is_none_or(|age| artifact.is_older_than(age)));
+
+    let images = artifacts
+        .iter()
+        .any(|artifact| artifact.kind == clean::ArtifactKind::Image);
+    if images && !dry_run {
+        disks::check_stopped(state)?;
+    }
+
+    if artifacts.is_empty() {
+        println!("Nothing to clean");
+        return Ok(());
+    }
+
+    let action = if dry_run { "would remove" } else { "removing" };
+    let mut total = 0;
+    for artifact in &artifacts {
+        println!(
+            "{} {:<8} {:>8}  {}",
+            action,
+            artifact.kind.as_str(),
+            disks::human_size(artifact.size),
+            artifact.path.display()
+        );
+
+        if dry_run {
+            continue;
+        }
+
+        clean::remove(artifact)?;
+        total += artifact.size;
+    }
+
+    if dry_run {
+        let total: u64 = artifacts.iter().map(|artifact| artifact.size).sum();
+        println!("Would free {}", disks::human_size(total));
+    } else {
+        println!("Freed {}", disks::human_size(total));
+        if selection.includes(clean::ArtifactKind::Result) || selection.gc_roots {
+            println!("Run 'nix-collect-garbage' to reclaim store paths of old builds");
+        }
+    }
+
+    Ok(())
+}
+
+fn cmd_doctor(config: &Option<PathBuf>) -> Result<()> {
+    let curdir = std::env::current_dir().context("No able to get current working directory")?;
+    let config_path = if let Some(config) = config {
+        config.clone()
+    } else {
+        curdir.join(".kd.toml")
+    };
+
+    let mut failed = false;
+    for check in doctor::run(&curdir, &config_path) {
+        let status = match check.status {
+            doctor::Status::Ok => "ok",
+            doctor::Status::Warning => "warn",
+            doctor::Status::Error => {
+                failed = true;
+                "FAIL"
+            }
+        };
+        println!("[{:>4}] {:<20} {}", status, check.name, check.message);
+        if let Some(fix) = &check.fix {
+            println!("{:>7} fix: {}", "", fix);
         }
     }
+
+    if failed {
+        bail!("Some checks failed");
+    }
+
+    Ok(())
+}
+
+/// Resolve the run plan for the named run and write out uconfig.nix for it
+fn plan(state: &mut State, name: &Option<String>) -> Result<Plan> {
+    if let Some(name) = &name {
+        state.name = name.clone();
+    }
+
+    let plan = Plan::new(state).context("Failed to generate nix config")?;
+    plan.write_uconfig()?;
+
+    Ok(plan)
 }
 
 fn main() -> Result<()> {
     let cli = Cli::parse();
 
     // All the command require .kd.toml. Only init can go without the config as it creates it
-    let mut state = if let Some(Commands::Init {}) = &cli.command {
-        State::default()
-    } else {
-        State::new(cli.config)?
+    // and doctor checks the config itself
+    let mut state = match &cli.command {
+        Some(Commands::Init { .. }) | Some(Commands::Doctor {}) => State::default(),
+        _ => State::new(cli.config.clone())?,
     };
 
     state.debug = cli.debug;
@@ -260,6 +1010,11 @@ fn main() -> Result<()> {
         state.args.push("--show-trace".to_string());
     }
 
+    state.offline = cli.offline;
+    if state.offline {
+        state.args.push("--offline".to_string());
+    }
+
     if let Some(config) = &state.config.dev {
         if let Some(args) = &config.args {
             for arg in args {
@@ -269,32 +1024,111 @@ fn main() -> Result<()> {
     }
 
     match &cli.command {
-        Some(Commands::Init {}) => cmd_init(&state).context("Initialization failed"),
+        Some(Commands::Init {
+            force,
+            upgrade,
+            template,
+            preset,
+        }) => cmd_init(*force, *upgrade, template, preset).context("Initialization failed"),
 
         Some(Commands::Build { name, target }) => {
+            let plan = plan(&mut state, name)?;
+            cmd_build(&plan, target)
+        }
+
+        Some(Commands::Run {
+            name,
+            fresh,
+            image,
+            plain,
+        }) => {
+            let plan = plan(&mut state, name)?;
+            cmd_run(&state, &plan, *fresh, *image, *plain)
+        }
+
+        Some(Commands::Deploy {
+            name,
+            uri,
+            domain,
+            pool,
+            network,
+        }) => {
+            let plan = plan(&mut state, name)?;
+            cmd_deploy(&plan, uri, domain, pool, network)
+        }
+
+        Some(Commands::Results {
+            name,
+            upload,
+            format,
+            input,
+            output,
+        }) => {
             if let Some(name) = &name {
                 state.name = name.clone();
             }
 
-            cmd_build(&mut state, target)
+            let plan = Plan::unresolved(&state)?;
+            cmd_results(&plan, *upload, format, input, output)
         }
 
-        Some(Commands::Run { name }) => {
+        Some(Commands::Report { name, html, input }) => {
             if let Some(name) = &name {
                 state.name = name.clone();
             }
 
-            cmd_run(&mut state)
+            let plan = Plan::unresolved(&state)?;
+            cmd_report(&plan, html, input)
         }
 
-        Some(Commands::Update {}) => cmd_update(&state),
+        Some(Commands::Disks { command }) => cmd_disks(&state, command),
 
-        Some(Commands::Config { output, name }) => {
-            if let Some(name) = &name {
-                state.name = name.clone();
-            }
+        Some(Commands::Vm { command }) => cmd_vm(&state, command),
+
+        Some(Commands::Dump { name }) => {
+            let plan = plan(&mut state, name)?;
+            cmd_dump(&plan)
+        }
+
+        Some(Commands::Drgn { dump, script, args }) => {
+            let plan = Plan::unresolved(&state)?;
+            cmd_drgn(&plan, dump, script, args)
+        }
+
+        Some(Commands::Clean {
+            logs,
+            images,
+            results,
+            backups,
+            all,
+            older_than,
+            dry_run,
+            gc_roots,
+        }) => {
+            let selection = clean::Selection {
+                logs: *logs,
+                images: *images,
+                results: *results,
+                backups: *backups,
+                all: *all,
+                gc_roots: *gc_roots,
+            };
+
+            cmd_clean(&state, &selection, older_than, *dry_run)
+        }
+
+        Some(Commands::Doctor {}) => cmd_doctor(&cli.config),
+
+        Some(Commands::Update {}) => cmd_update(&state),
 
-            cmd_config(&mut state, output.clone())
+        Some(Commands::Config {
+            output,
+            name,
+            diff,
+            merge,
+        }) => {
+            let plan = plan(&mut state, name)?;
+            cmd_config(&plan, output.clone(), *diff, *merge)
         }
 
         Some(Commands::Debug { config, name }) => {
@@ -302,7 +1136,8 @@ fn main() -> Result<()> {
                 state.name = name.clone();
             }
 
-            cmd_debug(&mut state, config)
+            let plan = Plan::new(&state).context("Failed to generate nix config")?;
+            cmd_debug(&plan, config)
         }
 
         None => Ok(()),
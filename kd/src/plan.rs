@@ -0,0 +1,271 @@
+use anyhow::{bail, Context, Result};
+use std::collections::{BTreeMap, HashMap};
+use std::path::{self, Path, PathBuf};
+use std::process::Command;
+use toml::Table;
+
+use crate::config::{KernelConfig, ResultsConfig, SystemConfig};
+use crate::sources::Sources;
+use crate::{
+    hooks, share, system_config, uconfig_kconfig, uconfig_kernel, uconfig_set_value, uconfig_share,
+    uconfig_trace, uconfig_vm, uconfig_xfsprogs, uconfig_xfstests, State,
+};
+
+/// Variable the NixOS VM runner takes kernel image from instead of the one in
+/// the system closure
+pub const PREBUILD_KERNEL_ENV: &str = "NIXPKGS_QEMU_KERNEL_kd";
+
+/// Everything commands need to know about the run, resolved once from the
+/// config and the command line: system configuration of the (named) run,
+/// generated uconfig.nix and vmconfig.nix and how to call Nix with it.
+pub struct Plan {
+    pub debug: bool,
+    pub curdir: PathBuf,
+    pub envdir: PathBuf,
+    pub flake_dir: PathBuf,
+    pub user_config: PathBuf,
+    pub vm_config: PathBuf,
+    pub name: String,
+    pub system: SystemConfig,
+    pub kconfig_flavors: Option<BTreeMap<String, Table>>,
+    pub results: Option<ResultsConfig>,
+    pub uconfig: String,
+    /// Settings only the VM has, the image, iso and qcow2 don't load them
+    pub vmconfig: String,
+    /// Arguments for every nix command
+    pub args: Vec<String>,
+    /// Environment for every nix command
+    pub envs: HashMap<String, String>,
+}
+
+impl Plan {
+    pub fn new(state: &State) -> Result<Self> {
+        let mut plan = Self::unresolved(state)?;
+        plan.uconfig = plan.generate(state.offline)?;
+        Ok(plan)
+    }
+
+    /// Same as [Plan::new], but sources are not resolved and uconfig and
+    /// vmconfig are left empty. For commands which only read results or dumps, they don't
+    /// touch the network or .kd/sources.toml.
+    pub fn unresolved(state: &State) -> Result<Self> {
+        Ok(Self {
+            debug: state.debug,
+            curdir: state.curdir.clone(),
+            envdir: state.envdir.clone(),
+            flake_dir: state.flake_dir.clone(),
+            user_config: state.user_config.clone(),
+            vm_config: state.vm_config.clone(),
+            name: state.name.clone(),
+            system: system_config(state)?,
+            kconfig_flavors: state.config.kconfig_flavors.clone(),
+            results: state.config.results.clone(),
+            uconfig: String::new(),
+            vmconfig: String::new(),
+            args: state.args.clone(),
+            envs: state.envs.clone(),
+        })
+    }
+
+    pub fn kernel(&self) -> Option<&KernelConfig> {
+        self.system.kernel.as_ref()
+    }
+
+    /// Absolute path to the prebuild kernel image
+    pub fn prebuild(&self) -> Result<Option<PathBuf>> {
+        let Some(prebuild) = self.kernel().and_then(|kernel| kernel.prebuild.as_ref()) else {
+            return Ok(None);
+        };
+
+        let path =
+            path::absolute(self.curdir.join(prebuild)).context("Failed to parse kernel path")?;
+        Ok(Some(path))
+    }
+
+    /// Flake output with the VM, the 'prebuild' one doesn't build the kernel
+    pub fn vm_target(&self) -> &'static str {
+        if self
+            .kernel()
+            .is_some_and(|kernel| kernel.prebuild.is_some())
+        {
+            "prebuild"
+        } else {
+            "vm"
+        }
+    }
+
+    /// Name of the configuration, `--name` or "default"
+    pub fn variant(&self) -> String {
+        if self.name.is_empty() {
+            "default".to_string()
+        } else {
+            self.name.clone()
+        }
+    }
+
+    /// Where xfstests put results of the last run, shared with the VM
+    pub fn results_dir(&self) -> PathBuf {
+        share::dir(&self.envdir).join(share::RESULTS)
+    }
+
+    pub fn package(&self, target: &str) -> String {
+        format!("path:{}#{}", self.flake_dir.display(), target)
+    }
+
+    /// 'nix <subcommand>' with arguments and environment of the plan, e.g.
+    /// "build" or "flake show". The package is not added, as some commands
+    /// need options after it.
+    pub fn nix(&self, subcommand: &str) -> Command {
+        let mut cmd = Command::new("nix");
+        cmd.args(subcommand.split_whitespace())
+            .args(&self.args)
+            .envs(&self.envs);
+        cmd
+    }
+
+    /// Build `target` of the kd flake and return its store path
+    pub fn build_output(&self, target: &str) -> Result<PathBuf> {
+        self.build(target, None)
+    }
+
+    /// Same as build_output(), but `link` is GC root of the result
+    pub fn build_link(&self, target: &str, link: &Path) -> Result<PathBuf> {
+        self.build(target, Some(link))
+    }
+
+    fn build(&self, target: &str, link: Option<&Path>) -> Result<PathBuf> {
+        let mut cmd = self.nix("build");
+        match link {
+            Some(link) => cmd.arg("--out-link").arg(link),
+            None => cmd.arg("--no-link"),
+        };
+        cmd.arg("--print-out-paths").arg(self.package(target));
+
+        if self.debug {
+            println!("command: {:?}", cmd);
+        }
+
+        let output = cmd
+            .output()
+            .context("Failed to spawn 'nix build' (see 'kd doctor')")?;
+        if !output.status.success() {
+            bail!(
+                "Failed to build {}: {}",
+                target,
+                String::from_utf8_lossy(&output.stderr)
+            );
+        }
+
+        Ok(PathBuf::from(
+            String::from_utf8_lossy(&output.stdout).trim(),
+        ))
+    }
+
+    pub fn write_uconfig(&self) -> Result<()> {
+        std::fs::write(&self.user_config, &self.uconfig)
+            .context("Failed to write out uconfig.nix data")?;
+        std::fs::write(&self.vm_config, &self.vmconfig)
+            .context("Failed to write out vmconfig.nix data")
+    }
+
+    /// TODO all this parsing should be just done nrix
+    fn generate(&mut self, offline: bool) -> Result<String> {
+        let mut options = vec![];
+        let mut vm_options = vec![];
+        let pins = if self.envdir.as_os_str().is_empty() {
+            None
+        } else {
+            Some(self.envdir.join("sources.toml"))
+        };
+        let mut sources = Sources::new(offline, self.curdir.clone(), pins)?;
+        let system = &self.system;
+
+        if let Some(packages) = &system.packages {
+            let mut list = String::from("with pkgs; [");
+            for package in packages {
+                list.push_str(package);
+                list.push('\n');
+            }
+            list.push(']');
+
+            options.push(uconfig_set_value("environment.systemPackages", &list));
+        }
+
+        if let Some(subconfig) = &system.vm {
+            vm_options.push(uconfig_vm(subconfig)?);
+        };
+
+        if let Some(config) = &system.xfstests {
+            options.push(uconfig_xfstests(config, &mut sources)?);
+        };
+
+        // [trace] wins over the 'trace' built-in hook
+        let trace = system
+            .trace
+            .clone()
+            .or_else(|| hooks::trace(system.xfstests.as_ref()));
+        if let Some(config) = &trace {
+            options.push(uconfig_trace(config)?);
+        };
+
+        if let Some(entries) = system.share.as_ref().filter(|entries| !entries.is_empty()) {
+            vm_options.push(uconfig_share(entries, &self.curdir)?);
+        };
+
+        if let Some(subconfig) = &system.xfsprogs {
+            options.push(uconfig_xfsprogs(subconfig, &mut sources)?);
+        };
+
+        if let Some(subconfig) = &system.kernel {
+            if let Some(kernel) = &subconfig.prebuild {
+                let path = path::absolute(self.curdir.join(kernel))
+                    .context("Failed to parse kernel path")?;
+
+                self.envs
+                    .insert(PREBUILD_KERNEL_ENV.to_string(), path.display().to_string());
+
+                if subconfig.modules.is_some() {
+                    options.push(uconfig_set_value("kernel.prebuildModules", "true"));
+                }
+            } else {
+                options.push(uconfig_kernel(
+                    subconfig,
+                    self.kconfig_flavors.as_ref(),
+                    &mut sources,
+                )?);
+                if let Some(config) = &subconfig.config {
+                    let kconfig = uconfig_kconfig(config).context("Invalid [kernel.config]")?;
+                    options.push(uconfig_set_value("kernel.kconfig", &kconfig))
+                }
+            };
+        };
+
+        if let Some(subconfig) = &system.qemu {
+            if let Some(qemu_options) = &subconfig.options {
+                let mut list = String::from("[");
+                for option in qemu_options {
+                    list.push('"');
+                    list.push_str(option);
+                    list.push_str("\"\n");
+                }
+                list.push(']');
+
+                options.push(uconfig_set_value("virtualisation.qemu.options", &list));
+            };
+        };
+
+        sources.save()?;
+        if sources.impure && !self.args.contains(&"--impure".to_string()) {
+            self.args.push("--impure".to_string());
+        }
+
+        self.vmconfig = format!(
+            include_str!("uconfig.tmpl"),
+            s_options = vm_options.join("\n")
+        );
+        Ok(format!(
+            include_str!("uconfig.tmpl"),
+            s_options = options.join("\n")
+        ))
+    }
+}
@@ -0,0 +1,107 @@
+use anyhow::{bail, Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::plan::Plan;
+
+/// Where modules are put in the share dir, the guest points MODULE_DIR here
+pub const SHARE_MODULES: &str = "modules";
+
+/// Kernel tree the prebuild kernel image comes from, i.e. the closest parent
+/// of arch/x86/boot/bzImage with Kconfig and Makefile
+pub fn tree(kernel: &Path) -> Option<PathBuf> {
+    kernel
+        .ancestors()
+        .skip(1)
+        .find(|dir| dir.join("Kconfig").is_file() && dir.join("Makefile").is_file())
+        .map(Path::to_path_buf)
+}
+
+/// Run 'make modules_install' in the kernel tree to install modules into the
+/// `staging` directory
+pub fn modules_install(plan: &Plan, tree: &Path, staging: &Path) -> Result<()> {
+    fs::create_dir_all(staging)
+        .with_context(|| format!("Unable to create {}", staging.display()))?;
+
+    let mut cmd = Command::new("make");
+    cmd.arg("-C")
+        .arg(tree)
+        .arg("-s")
+        .arg(format!("INSTALL_MOD_PATH={}", staging.display()))
+        .arg("modules_install");
+
+    if plan.debug {
+        println!("command: {:?}", cmd);
+    }
+
+    let status = cmd
+        .status()
+        .context("Failed to spawn 'make modules_install'")?;
+    if !status.success() {
+        bail!("'make modules_install' failed in {}", tree.display());
+    }
+
+    Ok(())
+}
+
+fn copy_dir(source: &Path, target: &Path) -> Result<()> {
+    fs::create_dir_all(target).with_context(|| format!("Unable to create {}", target.display()))?;
+
+    for entry in
+        fs::read_dir(source).with_context(|| format!("Failed to read {}", source.display()))?
+    {
+        let entry = entry.with_context(|| format!("Failed to read {}", source.display()))?;
+        let path = entry.path();
+        let destination = target.join(entry.file_name());
+        let file_type = entry.file_type()?;
+
+        if file_type.is_dir() {
+            copy_dir(&path, &destination)?;
+        } else if file_type.is_symlink() {
+            // 'build' and 'source' links point back to the kernel tree, they
+            // are useless in the guest
+            continue;
+        } else {
+            fs::copy(&path, &destination).with_context(|| {
+                format!(
+                    "Failed to copy {} to {}",
+                    path.display(),
+                    destination.display()
+                )
+            })?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Copy lib/modules from the `staging` directory into the share dir, so the
+/// guest can load them over 9p. Returns kernel releases which were copied.
+pub fn stage_modules(staging: &Path, share: &Path) -> Result<Vec<String>> {
+    let modules = staging.join("lib/modules");
+    if !modules.is_dir() {
+        bail!(
+            "There's no lib/modules in {}, run 'make modules_install INSTALL_MOD_PATH={}'",
+            staging.display(),
+            staging.display()
+        );
+    }
+
+    let target = share.join(SHARE_MODULES);
+    if target.exists() {
+        fs::remove_dir_all(&target)
+            .with_context(|| format!("Failed to remove {}", target.display()))?;
+    }
+    copy_dir(&modules, &target)?;
+
+    let mut releases: Vec<String> = fs::read_dir(&target)
+        .with_context(|| format!("Failed to read {}", target.display()))?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path().is_dir())
+        .map(|entry| entry.file_name().to_string_lossy().to_string())
+        .collect();
+    releases.sort();
+
+    Ok(releases)
+}
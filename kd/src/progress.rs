@@ -0,0 +1,555 @@
+use anyhow::{Context, Result};
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::fmt;
+use std::fs;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::report::SPLAT_MARKERS;
+use crate::results::{Status, TestCase};
+use crate::watchdog::Watchdog;
+
+/// Runtimes of the previous runs, 'test seconds' per line as in xfstests
+/// check.time. Results are removed on every run, so kd keeps them here.
+pub const HISTORY: &str = "check.time";
+
+/// Kernel warnings kept for the status
+const WARNINGS: usize = 3;
+
+/// Status line is redrawn this often while a test runs
+const REDRAW: Duration = Duration::from_secs(1);
+
+const STATUS_WIDTH: usize = 100;
+
+/// The guest prints it before running failed tests again with [trace]
+const RERUN: &str = "Re-running failed tests with tracing:";
+
+#[derive(Debug, PartialEq)]
+pub enum Event {
+    Section(String),
+    Start(String),
+    Pass(String),
+    Fail(String, String),
+    NotRun(String, String),
+    Warning(String),
+    /// Summary xfstests prints at the end of a section
+    Done(String),
+    /// Failed tests run again to record their traces
+    Rerun(String),
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Event::Section(section) => write!(f, "section {section}"),
+            Event::Start(test) => write!(f, "{test}"),
+            Event::Pass(test) => write!(f, "{test} pass"),
+            Event::Fail(test, reason) => write!(f, "{test} FAIL {reason}"),
+            Event::NotRun(test, reason) => write!(f, "{test} not run {reason}"),
+            Event::Warning(warning) => write!(f, "kernel: {warning}"),
+            Event::Done(summary) => write!(f, "{summary}"),
+            Event::Rerun(tests) => write!(f, "re-running with tracing: {tests}"),
+        }
+    }
+}
+
+/// Message of a console line printed by the kernel, '[   12.345678] ...'
+fn kernel_message(line: &str) -> Option<&str> {
+    let rest = line.trim_start().strip_prefix('[')?;
+    let (time, message) = rest.split_once(']')?;
+    time.trim()
+        .parse::<f64>()
+        .ok()
+        .map(|_| message.trim_start())
+}
+
+/// Test names are '<group>/<number>', e.g. generic/001
+fn is_test(name: &str) -> bool {
+    let Some((group, number)) = name.split_once('/') else {
+        return false;
+    };
+
+    group.starts_with(|c: char| c.is_ascii_alphabetic())
+        && group
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        && !number.is_empty()
+        && number.chars().all(|c| c.is_ascii_digit())
+}
+
+fn is_runtime(token: &str) -> bool {
+    token
+        .strip_suffix('s')
+        .is_some_and(|time| !time.is_empty() && time.chars().all(|c| c.is_ascii_digit()))
+}
+
+/// Result of the test from what xfstests prints after its name
+fn outcome(test: &str, rest: &str) -> Option<Event> {
+    let rest = rest.trim();
+    if let Some((_, reason)) = rest.split_once("[not run]") {
+        return Some(Event::NotRun(test.to_string(), reason.trim().to_string()));
+    }
+    if rest.contains("[expunged]") {
+        return Some(Event::NotRun(test.to_string(), "expunged".to_string()));
+    }
+    if rest.contains("output mismatch") || rest.contains("[failed") || rest.contains("_check_") {
+        let reason = rest
+            .trim_start_matches(|c: char| c.is_ascii_digit() || c == 's' || c == '.' || c == ' ');
+        return Some(Event::Fail(
+            test.to_string(),
+            reason.trim_start_matches("- ").to_string(),
+        ));
+    }
+    if rest.split_whitespace().last().is_some_and(is_runtime) {
+        return Some(Event::Pass(test.to_string()));
+    }
+
+    None
+}
+
+/// Split the console line into what was printed before a kernel message
+/// and the message. xfstests prints test name before running it, so kernel
+/// messages often continue the line. Messages have no timestamp if the
+/// kernel is built without PRINTK_TIME, then they are found by the text.
+fn split_kernel(line: &str) -> (&str, Option<&str>) {
+    let stamped = line
+        .match_indices('[')
+        .map(|(index, _)| index)
+        .find(|index| kernel_message(&line[*index..]).is_some());
+    let marked = std::iter::once(&"run fstests ")
+        .chain(SPLAT_MARKERS)
+        .filter_map(|marker| line.find(marker))
+        .min();
+
+    match stamped.into_iter().chain(marked).min() {
+        Some(index) => {
+            let message = kernel_message(&line[index..]).unwrap_or(&line[index..]);
+            (&line[..index], Some(message))
+        }
+        None => (line, None),
+    }
+}
+
+fn kernel_event(message: &str) -> Option<Event> {
+    if let Some((_, rest)) = message.split_once("run fstests ") {
+        let test = rest.split_whitespace().next()?;
+        return Some(Event::Start(test.to_string()));
+    }
+    if SPLAT_MARKERS.iter().any(|marker| message.contains(marker)) {
+        return Some(Event::Warning(message.to_string()));
+    }
+
+    None
+}
+
+/// Parse a console line. `current` is the test which is running, results
+/// split from the test name by kernel messages are attributed to it.
+pub fn parse(line: &str, current: Option<&str>) -> Option<Event> {
+    let (line, kernel) = split_kernel(line.trim_end_matches(['\r', '\n']));
+    if let Some(event) = kernel.and_then(kernel_event) {
+        return Some(event);
+    }
+
+    let trimmed = line.trim();
+    if trimmed.is_empty() {
+        return None;
+    }
+    if let Some(section) = trimmed
+        .strip_prefix("SECTION")
+        .and_then(|rest| rest.trim_start().strip_prefix("--"))
+    {
+        return Some(Event::Section(section.trim().to_string()));
+    }
+    if let Some(tests) = trimmed.strip_prefix(RERUN) {
+        return Some(Event::Rerun(tests.trim().to_string()));
+    }
+    if trimmed.starts_with("Passed all ")
+        || (trimmed.starts_with("Failed ") && trimmed.ends_with(" tests"))
+    {
+        return Some(Event::Done(trimmed.to_string()));
+    }
+
+    let (first, rest) = trimmed.split_once(' ').unwrap_or((trimmed, ""));
+    if is_test(first) {
+        return outcome(first, rest).or_else(|| Some(Event::Start(first.to_string())));
+    }
+
+    // Only the result, e.g. ' 5s ...  4s' after kernel messages
+    let test = current?;
+    let only_runtime = trimmed
+        .split_whitespace()
+        .all(|token| token == "..." || is_runtime(token));
+    if only_runtime
+        || trimmed.starts_with("[not run]")
+        || trimmed.starts_with("- output mismatch")
+        || trimmed.starts_with("[failed")
+    {
+        return outcome(test, trimmed);
+    }
+
+    None
+}
+
+pub fn parse_history(data: &str) -> BTreeMap<String, f64> {
+    data.lines()
+        .filter_map(|line| {
+            let (test, time) = line.trim().split_once(' ')?;
+            Some((test.to_string(), time.trim().parse().ok()?))
+        })
+        .collect()
+}
+
+pub fn load_history(envdir: &Path) -> BTreeMap<String, f64> {
+    fs::read_to_string(envdir.join(HISTORY))
+        .map(|data| parse_history(&data))
+        .unwrap_or_default()
+}
+
+fn find_runtimes(dir: &Path, files: &mut Vec<PathBuf>) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+
+    for path in entries.filter_map(|entry| entry.ok().map(|entry| entry.path())) {
+        if path.is_dir() && !path.is_symlink() {
+            find_runtimes(&path, files);
+        } else if path.file_name().is_some_and(|name| name == "check.time") {
+            files.push(path);
+        }
+    }
+}
+
+/// Merge check.time of all sections in `results` into the history
+pub fn save_history(results: &Path, envdir: &Path) -> Result<()> {
+    let mut files = vec![];
+    find_runtimes(results, &mut files);
+    if files.is_empty() {
+        return Ok(());
+    }
+
+    let mut history = load_history(envdir);
+    files.sort();
+    for file in files {
+        let data = fs::read_to_string(&file)
+            .with_context(|| format!("Failed to read {}", file.display()))?;
+        history.extend(parse_history(&data));
+    }
+
+    let data: String = history
+        .iter()
+        .map(|(test, time)| format!("{test} {time}\n"))
+        .collect();
+    let path = envdir.join(HISTORY);
+    fs::write(&path, data).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// 1:02:03 or 2:03
+pub fn duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    if seconds >= 3600 {
+        format!(
+            "{}:{:02}:{:02}",
+            seconds / 3600,
+            seconds / 60 % 60,
+            seconds % 60
+        )
+    } else {
+        format!("{}:{:02}", seconds / 60, seconds % 60)
+    }
+}
+
+/// State of the xfstests run built from the console
+pub struct Progress {
+    started: Instant,
+    history: BTreeMap<String, f64>,
+    pub section: Option<String>,
+    pub current: Option<(String, Instant)>,
+    pub done: BTreeSet<String>,
+    /// Outcomes of the tests of the section, the watchdog saves them before
+    /// the VM reset as xfstests doesn't write the report
+    pub results: Vec<TestCase>,
+    /// Sections xfstests ran to the end
+    pub finished: Vec<String>,
+    pub passed: usize,
+    pub failed: usize,
+    pub notrun: usize,
+    pub warnings: VecDeque<String>,
+    /// Failed tests are running again with tracing
+    pub rerun: bool,
+}
+
+impl Progress {
+    pub fn new(history: BTreeMap<String, f64>, now: Instant) -> Self {
+        Self {
+            started: now,
+            history,
+            section: None,
+            current: None,
+            done: BTreeSet::new(),
+            results: vec![],
+            finished: vec![],
+            passed: 0,
+            failed: 0,
+            notrun: 0,
+            warnings: VecDeque::new(),
+            rerun: false,
+        }
+    }
+
+    /// Update with a console line, returns what happened
+    pub fn feed(&mut self, line: &str, now: Instant) -> Option<Event> {
+        let current = self.current.as_ref().map(|(test, _)| test.as_str());
+        let event = parse(line, current)?;
+
+        match &event {
+            Event::Section(section) => {
+                // The hung section starts over after the VM reset
+                if self.section.as_ref().is_some_and(|current| current != section) {
+                    self.finish_section();
+                }
+                self.section = Some(section.clone());
+                self.done.clear();
+                self.results.clear();
+            }
+            Event::Start(test) => {
+                if current == Some(test.as_str()) {
+                    return None;
+                }
+                self.current = Some((test.clone(), now));
+            }
+            Event::Pass(test) | Event::Fail(test, _) | Event::NotRun(test, _) => {
+                // Results of the re-run are already counted
+                match event {
+                    _ if self.rerun => (),
+                    Event::Pass(_) => self.passed += 1,
+                    Event::Fail(..) => self.failed += 1,
+                    _ => self.notrun += 1,
+                }
+                if let Some(case) = self.case(&event, now).filter(|_| !self.rerun) {
+                    self.results.push(case);
+                }
+                if current == Some(test.as_str()) {
+                    self.current = None;
+                }
+                self.done.insert(test.clone());
+            }
+            Event::Warning(warning) => {
+                self.warnings.push_back(warning.clone());
+                if self.warnings.len() > WARNINGS {
+                    self.warnings.pop_front();
+                }
+            }
+            Event::Done(_) => {
+                self.current = None;
+                self.finish_section();
+            }
+            Event::Rerun(_) => {
+                self.rerun = true;
+                self.current = None;
+            }
+        }
+
+        Some(event)
+    }
+
+    fn finish_section(&mut self) {
+        if let Some(section) = &self.section {
+            if !self.finished.contains(section) {
+                self.finished.push(section.clone());
+            }
+        }
+    }
+
+    /// Test case of the test result, as xfstests would report it
+    fn case(&self, event: &Event, now: Instant) -> Option<TestCase> {
+        let (test, status, message) = match event {
+            Event::Pass(test) => (test, Status::Pass, None),
+            Event::Fail(test, reason) => (test, Status::Fail, Some(reason.clone())),
+            Event::NotRun(test, reason) => (test, Status::Skip, Some(reason.clone())),
+            _ => return None,
+        };
+        let time = match &self.current {
+            Some((current, started)) if current == test => {
+                now.duration_since(*started).as_secs_f64().round()
+            }
+            _ => 0.0,
+        };
+
+        Some(TestCase {
+            name: test.clone(),
+            time,
+            status,
+            message,
+            diff: None,
+            dmesg: None,
+        })
+    }
+
+    /// Time left if the section runs the same tests as the previous runs
+    pub fn eta(&self, now: Instant) -> Option<Duration> {
+        if self.history.is_empty() {
+            return None;
+        }
+
+        let mut left = 0.0;
+        for (test, time) in &self.history {
+            if self.done.contains(test) {
+                continue;
+            }
+            match &self.current {
+                Some((current, started)) if current == test => {
+                    left += (time - now.duration_since(*started).as_secs_f64()).max(0.0)
+                }
+                _ => left += time,
+            }
+        }
+
+        Some(Duration::from_secs_f64(left))
+    }
+
+    pub fn status(&self, now: Instant) -> String {
+        let mut status = vec![];
+        if let Some(section) = &self.section {
+            status.push(section.clone());
+        }
+        match &self.current {
+            Some((test, started)) => status.push(format!(
+                "{} {}",
+                test,
+                duration(now.duration_since(*started))
+            )),
+            None => status.push("waiting".to_string()),
+        }
+        status.push(format!(
+            "{} pass {} fail {} notrun",
+            self.passed, self.failed, self.notrun
+        ));
+        status.push(format!(
+            "elapsed {}",
+            duration(now.duration_since(self.started))
+        ));
+        if let Some(eta) = self.eta(now) {
+            status.push(format!("ETA {}", duration(eta)));
+        }
+        if let Some(warning) = self.warnings.back() {
+            status.push(format!("last warning: {warning}"));
+        }
+
+        let status = status.join(" | ");
+        status.chars().take(STATUS_WIDTH).collect()
+    }
+}
+
+/// What `follow` shows
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Output {
+    /// Console as it is
+    Raw,
+    /// Test results and kernel warnings
+    Events,
+    /// Events and the status line at the bottom of the terminal
+    Status,
+}
+
+/// Follow the console in `reader` till it's closed and show it in `out`.
+/// Watchdog, if any, is checked on every line and every REDRAW.
+pub fn follow<R, W>(
+    reader: R,
+    mut progress: Progress,
+    out: &mut W,
+    output: Output,
+    mut watchdog: Option<&mut Watchdog>,
+) -> Result<Progress>
+where
+    R: Read + Send + 'static,
+    W: Write,
+{
+    // Chunks and not lines, prompts in the raw console don't end with newline
+    let (sender, receiver) = mpsc::channel::<Vec<u8>>();
+    thread::spawn(move || {
+        let mut reader = reader;
+        let mut buffer = [0; 4096];
+        while let Ok(read) = reader.read(&mut buffer) {
+            if read == 0 || sender.send(buffer[..read].to_vec()).is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut pending: Vec<u8> = vec![];
+    let mut closed = false;
+    while !closed {
+        let chunk = match receiver.recv_timeout(REDRAW) {
+            Ok(chunk) => chunk,
+            Err(mpsc::RecvTimeoutError::Timeout) => vec![],
+            Err(mpsc::RecvTimeoutError::Disconnected) => {
+                closed = true;
+                // Last line without newline
+                vec![b'\n']
+            }
+        };
+
+        let now = Instant::now();
+        if output == Output::Raw && !closed {
+            out.write_all(&chunk)?;
+        }
+        pending.extend_from_slice(&chunk);
+
+        let mut messages = vec![];
+        while let Some(end) = pending.iter().position(|byte| *byte == b'\n') {
+            let line: Vec<u8> = pending.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line);
+            if let Some(watchdog) = watchdog.as_deref_mut() {
+                watchdog.feed(&line, now);
+            }
+            match progress.feed(&line, now) {
+                Some(Event::Start(_)) | None => (),
+                Some(event) => {
+                    if output != Output::Raw {
+                        messages.push(event.to_string());
+                    }
+                }
+            }
+        }
+        if let Some(watchdog) = watchdog.as_deref_mut() {
+            for message in watchdog.check(&mut progress, now)? {
+                if output == Output::Raw {
+                    messages.push(format!("kd: {message}"));
+                } else {
+                    messages.push(message);
+                }
+            }
+        }
+
+        for message in messages {
+            if output == Output::Status {
+                write!(out, "\r\x1b[K")?;
+            }
+            writeln!(out, "{message}")?;
+        }
+        if output == Output::Status && !closed {
+            write!(out, "\r\x1b[K{}", progress.status(now))?;
+        }
+        out.flush()?;
+    }
+
+    if output == Output::Status {
+        write!(out, "\r\x1b[K")?;
+    }
+    if output != Output::Raw {
+        writeln!(
+            out,
+            "{} passed, {} failed, {} not run in {}",
+            progress.passed,
+            progress.failed,
+            progress.notrun,
+            duration(progress.started.elapsed())
+        )?;
+    }
+    out.flush()?;
+
+    Ok(progress)
+}
@@ -0,0 +1,144 @@
+use anyhow::{bail, Context, Result};
+use serde_json::{json, Value};
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+/// QEMU doesn't answer in time only if it's stuck itself
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Interval of polling a running memory dump
+const POLL: Duration = Duration::from_millis(500);
+
+/// QMP socket of the running VM, both runners start QEMU with it
+pub fn socket(envdir: &Path) -> PathBuf {
+    envdir.join("qmp.sock")
+}
+
+/// Client of QEMU Machine Protocol
+pub struct Qmp {
+    stream: BufReader<UnixStream>,
+}
+
+impl Qmp {
+    /// Connect and leave capabilities negotiation mode
+    pub fn connect(socket: &Path) -> Result<Self> {
+        let stream = UnixStream::connect(socket).with_context(|| {
+            format!(
+                "Failed to connect to QMP socket {}, is VM running?",
+                socket.display()
+            )
+        })?;
+        stream
+            .set_read_timeout(Some(TIMEOUT))
+            .context("Failed to set QMP timeout")?;
+
+        let mut qmp = Self {
+            stream: BufReader::new(stream),
+        };
+        let greeting = qmp.read()?;
+        if greeting.get("QMP").is_none() {
+            bail!("Unexpected QMP greeting: {}", greeting);
+        }
+        qmp.execute("qmp_capabilities", None)?;
+
+        Ok(qmp)
+    }
+
+    fn read(&mut self) -> Result<Value> {
+        let mut line = String::new();
+        let read = self
+            .stream
+            .read_line(&mut line)
+            .context("Failed to read from QMP socket")?;
+        if read == 0 {
+            bail!("QMP connection closed");
+        }
+
+        serde_json::from_str(&line).with_context(|| format!("Invalid QMP message: {}", line.trim()))
+    }
+
+    /// Run the command and return its result, events are skipped
+    pub fn execute(&mut self, command: &str, arguments: Option<Value>) -> Result<Value> {
+        let mut request = json!({ "execute": command });
+        if let Some(arguments) = arguments {
+            request["arguments"] = arguments;
+        }
+        writeln!(self.stream.get_mut(), "{}", request).context("Failed to write to QMP socket")?;
+
+        loop {
+            let response = self.read()?;
+            if let Some(value) = response.get("return") {
+                return Ok(value.clone());
+            }
+            if let Some(error) = response.get("error") {
+                bail!(
+                    "QMP command '{}' failed: {}",
+                    command,
+                    error["desc"].as_str().unwrap_or("unknown error")
+                );
+            }
+        }
+    }
+
+    /// Run state of the VM, e.g. "running", "paused" or "guest-panicked"
+    pub fn status(&mut self) -> Result<String> {
+        let status = self.execute("query-status", None)?;
+        match status["status"].as_str() {
+            Some(status) => Ok(status.to_string()),
+            None => bail!("Unexpected QMP status: {}", status),
+        }
+    }
+
+    /// Press Alt+SysRq+<key> on the VM keyboard
+    pub fn sysrq(&mut self, key: char) -> Result<()> {
+        if !key.is_ascii_lowercase() && !key.is_ascii_digit() {
+            bail!("Invalid sysrq key '{}', expected a-z or 0-9", key);
+        }
+        let keys: Vec<Value> = ["alt", "sysrq", &key.to_string()]
+            .iter()
+            .map(|key| json!({ "type": "qcode", "data": key }))
+            .collect();
+        self.execute("send-key", Some(json!({ "keys": keys })))?;
+        Ok(())
+    }
+
+    pub fn reset(&mut self) -> Result<()> {
+        self.execute("system_reset", None)?;
+        Ok(())
+    }
+
+    /// Ask the guest to shut down (ACPI power button)
+    pub fn powerdown(&mut self) -> Result<()> {
+        self.execute("system_powerdown", None)?;
+        Ok(())
+    }
+
+    /// Stop QEMU right away
+    pub fn quit(&mut self) -> Result<()> {
+        self.execute("quit", None)?;
+        Ok(())
+    }
+
+    /// Write guest memory as ELF core to `path`, returns its size. QEMU writes
+    /// the file itself, so the path has to be absolute.
+    pub fn dump(&mut self, path: &Path) -> Result<u64> {
+        let arguments = json!({
+            "paging": false,
+            "protocol": format!("file:{}", path.display()),
+            "detach": true,
+        });
+        self.execute("dump-guest-memory", Some(arguments))?;
+
+        loop {
+            let dump = self.execute("query-dump", None)?;
+            match dump["status"].as_str() {
+                Some("completed") => return Ok(dump["total"].as_u64().unwrap_or(0)),
+                Some("failed") => bail!("QEMU failed to dump memory to {}", path.display()),
+                _ => thread::sleep(POLL),
+            }
+        }
+    }
+}
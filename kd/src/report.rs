@@ -0,0 +1,481 @@
+use anyhow::{bail, Context, Result};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::kconfig::{self, Change, Flavors, Options, DEFAULT_FLAVOR};
+use crate::plan::Plan;
+use crate::results::{self, Status, Suite};
+use crate::upload::{self, RunInfo};
+use crate::xml_escape as escape;
+
+/// Lines of a splat kept if there's no end of trace marker
+const SPLAT_LINES: usize = 60;
+
+/// Slowest tests shown in the runtime chart
+const CHART_TESTS: usize = 25;
+
+/// Console lines starting a kernel splat
+pub const SPLAT_MARKERS: &[&str] = &[
+    "BUG:",
+    "WARNING:",
+    "Oops:",
+    "kernel BUG at",
+    "general protection fault",
+    "Unable to handle kernel",
+    "Kernel panic",
+    "INFO: task",
+    "INFO: rcu_",
+    "unreferenced object",
+];
+
+const STYLE: &str = r#"
+body { font-family: sans-serif; margin: 2em; color: #222; }
+table { border-collapse: collapse; margin-bottom: 1em; }
+td, th { border: 1px solid #ccc; padding: 0.2em 0.6em; text-align: left; vertical-align: top; }
+pre { background: #f6f6f6; padding: 0.5em; overflow-x: auto; }
+.pass { color: #1a7f37; }
+.fail { color: #cf222e; font-weight: bold; }
+.skip { color: #777; }
+td.pass, td.fail, td.skip { text-align: center; }
+.filters { margin-bottom: 0.5em; }
+.filters label { margin-right: 1em; }
+"#;
+
+const SCRIPT: &str = r##"
+function filter() {
+    const text = document.getElementById("filter").value;
+    const shown = new Set(Array.from(document.querySelectorAll(".filters input[type=checkbox]"))
+        .filter(box => box.checked)
+        .map(box => box.dataset.status));
+    for (const row of document.querySelectorAll("#tests tbody tr")) {
+        row.hidden = !shown.has(row.dataset.status) || !row.dataset.name.includes(text);
+    }
+}
+document.querySelectorAll(".filters input").forEach(input => input.addEventListener("input", filter));
+"##;
+
+/// One run on the HTML page
+pub struct Report {
+    pub title: String,
+    /// Configuration of the run, name and value
+    pub summary: Vec<(String, String)>,
+    /// [kernel.config] options on top of the flavors
+    pub kconfig: Vec<Change>,
+    pub suites: Vec<Suite>,
+    pub splats: Vec<String>,
+}
+
+impl Report {
+    /// The last run, results and console log from .kd/share. `flavors` are
+    /// from kconfig::flavors()
+    pub fn new(plan: &Plan, flavors: &Flavors) -> Result<Self> {
+        let run = RunInfo::new(plan);
+        let suites = results::load(&plan.results_dir())?;
+        let console = upload::latest_log(&plan.envdir.join("share"))
+            .and_then(|log| fs::read_to_string(log).ok())
+            .unwrap_or_default();
+
+        Ok(Self {
+            title: format!("{} {} {}", run.variant, run.kernel, run.date),
+            summary: summary(plan, &run),
+            kconfig: kconfig_changes(plan, flavors)?,
+            suites,
+            splats: splats(&console),
+        })
+    }
+
+    /// Results of another run, e.g. uploaded with [results.dir]. Only the
+    /// console log in the directory describes it.
+    pub fn from_dir(dir: &Path) -> Result<Self> {
+        let suites = results::load(dir)?;
+        let log = upload::latest_log(dir);
+        let console = log
+            .as_ref()
+            .and_then(|log| fs::read_to_string(log).ok())
+            .unwrap_or_default();
+
+        let mut summary = vec![("Results".to_string(), dir.display().to_string())];
+        if let Some(kernel) = upload::kernel_release(&console) {
+            summary.push(("Kernel".to_string(), kernel));
+        }
+        if let Some(date) = log.as_deref().and_then(upload::log_date) {
+            summary.push(("Date".to_string(), date));
+        }
+
+        Ok(Self {
+            title: dir.display().to_string(),
+            summary,
+            kconfig: vec![],
+            suites,
+            splats: splats(&console),
+        })
+    }
+
+    pub fn count(&self, status: Status) -> usize {
+        self.suites.iter().map(|suite| suite.count(status)).sum()
+    }
+
+    pub fn tests(&self) -> usize {
+        self.suites.iter().map(|suite| suite.tests.len()).sum()
+    }
+}
+
+/// Where a package comes from: local path, repo and revision or the
+/// default of the kd flake
+fn source(path: &Option<String>, repo: &Option<String>, rev: &Option<String>) -> String {
+    if let Some(path) = path {
+        return path.clone();
+    }
+
+    match (repo, rev) {
+        (Some(repo), Some(rev)) => format!("{repo} {rev}"),
+        (Some(repo), None) => repo.clone(),
+        (None, Some(rev)) => rev.clone(),
+        (None, None) => "default".to_string(),
+    }
+}
+
+fn summary(plan: &Plan, run: &RunInfo) -> Vec<(String, String)> {
+    let mut summary = vec![
+        ("Variant".to_string(), run.variant.clone()),
+        ("Host".to_string(), run.host.clone()),
+        ("Date".to_string(), run.date.clone()),
+        ("Kernel".to_string(), run.kernel.clone()),
+    ];
+
+    if let Some(kernel) = plan.kernel() {
+        let tree = kernel
+            .prebuild
+            .clone()
+            .unwrap_or_else(|| source(&kernel.source, &kernel.repo, &kernel.rev));
+        summary.push(("Kernel source".to_string(), tree));
+
+        let mut flavors = vec![DEFAULT_FLAVOR.to_string()];
+        flavors.extend(kernel.flavors.clone().unwrap_or_default());
+        summary.push(("Kconfig flavors".to_string(), flavors.join(", ")));
+    }
+
+    let xfstests = plan.system.xfstests.clone().unwrap_or_default();
+    summary.push((
+        "xfstests".to_string(),
+        source(&xfstests.path, &xfstests.repo, &xfstests.rev),
+    ));
+    if let Some(args) = &xfstests.args {
+        summary.push(("xfstests args".to_string(), args.clone()));
+    }
+
+    let xfsprogs = plan.system.xfsprogs.clone().unwrap_or_default();
+    summary.push((
+        "xfsprogs".to_string(),
+        source(&xfsprogs.path, &xfsprogs.repo, &xfsprogs.rev),
+    ));
+
+    summary
+}
+
+/// Options of [kernel.config] which are not set by the selected flavors
+pub fn kconfig_changes(plan: &Plan, flavors: &Flavors) -> Result<Vec<Change>> {
+    let Some(kernel) = plan.kernel() else {
+        return Ok(vec![]);
+    };
+    let Some(config) = &kernel.config else {
+        return Ok(vec![]);
+    };
+    let requested = kconfig::requested(config).context("Invalid [kernel.config]")?;
+
+    let mut selected = vec![DEFAULT_FLAVOR.to_string()];
+    selected.extend(kernel.flavors.clone().unwrap_or_default());
+    let mut options = Options::new();
+    for name in &selected {
+        if let Some(flavor) = flavors.get(name) {
+            options.extend(flavor.clone());
+        }
+    }
+
+    let mut changes = vec![];
+    for (name, value) in requested {
+        match options.get(&name) {
+            Some(old) if old.value() == value.value() => continue,
+            Some(old) => changes.push(Change::Changed(name, old.value(), value.value())),
+            None => changes.push(Change::Added(name, value.value())),
+        }
+    }
+
+    Ok(changes)
+}
+
+/// Warnings, BUGs, oopses and hung tasks from the console log. A splat ends
+/// with the end of trace marker, or after SPLAT_LINES lines.
+pub fn splats(console: &str) -> Vec<String> {
+    let lines: Vec<&str> = console.lines().collect();
+    let mut splats = vec![];
+    let mut index = 0;
+
+    while index < lines.len() {
+        if !SPLAT_MARKERS
+            .iter()
+            .any(|marker| lines[index].contains(marker))
+        {
+            index += 1;
+            continue;
+        }
+
+        let end = lines[index..]
+            .iter()
+            .take(SPLAT_LINES)
+            .position(|line| line.contains("---[ end "))
+            .map_or((index + SPLAT_LINES).min(lines.len()), |offset| {
+                index + offset + 1
+            });
+        splats.push(lines[index..end].join("\n"));
+        index = end;
+    }
+
+    splats
+}
+
+fn status_class(status: Status) -> &'static str {
+    match status {
+        Status::Pass => "pass",
+        Status::Fail => "fail",
+        Status::Skip => "skip",
+    }
+}
+
+/// Horizontal bars of the slowest tests
+fn chart(report: &Report) -> String {
+    let mut tests: Vec<(String, f64)> = report
+        .suites
+        .iter()
+        .flat_map(|suite| {
+            suite
+                .tests
+                .iter()
+                .map(move |test| (format!("{} {}", suite.name, test.name), test.time))
+        })
+        .collect();
+    tests.sort_by(|a, b| b.1.total_cmp(&a.1));
+    tests.truncate(CHART_TESTS);
+
+    let max = tests.first().map_or(0.0, |test| test.1);
+    if max <= 0.0 {
+        return "<p>No runtime recorded</p>\n".to_string();
+    }
+
+    let (label, width, row) = (300.0, 400.0, 20.0);
+    let mut svg = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" font-size=\"12\">\n",
+        label + width + 60.0,
+        row * tests.len() as f64
+    );
+    for (index, (name, time)) in tests.iter().enumerate() {
+        let y = row * index as f64;
+        let bar = width * time / max;
+        svg.push_str(&format!(
+            "<text x=\"0\" y=\"{}\">{}</text><rect x=\"{}\" y=\"{}\" width=\"{:.1}\" height=\"{}\" fill=\"#4c8bf5\"/><text x=\"{:.1}\" y=\"{}\">{}s</text>\n",
+            y + 14.0,
+            escape(name),
+            label,
+            y + 3.0,
+            bar,
+            row - 6.0,
+            label + bar + 4.0,
+            y + 14.0,
+            time
+        ));
+    }
+    svg.push_str("</svg>\n");
+    svg
+}
+
+fn page(title: &str, body: &str) -> String {
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n<style>{}</style>\n</head>\n<body>\n{}</body>\n</html>\n",
+        escape(title),
+        STYLE,
+        body
+    )
+}
+
+/// Self-contained page of the run
+pub fn html(report: &Report) -> String {
+    let mut body = format!("<h1>{}</h1>\n", escape(&report.title));
+    body.push_str(&format!(
+        "<p>{} tests, <span class=\"fail\">{} failed</span>, <span class=\"skip\">{} skipped</span></p>\n",
+        report.tests(),
+        report.count(Status::Fail),
+        report.count(Status::Skip)
+    ));
+
+    body.push_str("<h2>Configuration</h2>\n<table>\n");
+    for (name, value) in &report.summary {
+        body.push_str(&format!(
+            "<tr><th>{}</th><td>{}</td></tr>\n",
+            escape(name),
+            escape(value)
+        ));
+    }
+    body.push_str("</table>\n");
+
+    if !report.kconfig.is_empty() {
+        body.push_str("<h2>Kernel config on top of flavors</h2>\n<pre>");
+        for change in &report.kconfig {
+            body.push_str(&format!("{}\n", escape(&change.to_string())));
+        }
+        body.push_str("</pre>\n");
+    }
+
+    body.push_str("<h2>Kernel splats</h2>\n");
+    if report.splats.is_empty() {
+        body.push_str("<p>None detected in the console log</p>\n");
+    }
+    for splat in &report.splats {
+        let first = splat.lines().next().unwrap_or_default();
+        body.push_str(&format!(
+            "<details><summary>{}</summary><pre>{}</pre></details>\n",
+            escape(first),
+            escape(splat)
+        ));
+    }
+
+    body.push_str("<h2>Runtime</h2>\n");
+    body.push_str(&chart(report));
+
+    body.push_str(
+        "<h2>Tests</h2>\n<div class=\"filters\">\n\
+         <label><input type=\"checkbox\" data-status=\"fail\" checked> failed</label>\n\
+         <label><input type=\"checkbox\" data-status=\"skip\" checked> skipped</label>\n\
+         <label><input type=\"checkbox\" data-status=\"pass\" checked> passed</label>\n\
+         <input type=\"search\" id=\"filter\" placeholder=\"Filter tests\">\n</div>\n\
+         <table id=\"tests\">\n<thead><tr><th>Suite</th><th>Test</th><th>Status</th><th>Time</th></tr></thead>\n<tbody>\n",
+    );
+    for suite in &report.suites {
+        for test in &suite.tests {
+            let class = status_class(test.status);
+            let mut name = escape(&test.name);
+            if test.status == Status::Fail {
+                name.push_str(&format!(
+                    "<details><summary>{}</summary>",
+                    escape(test.message.as_deref().unwrap_or("details"))
+                ));
+                if let Some(diff) = &test.diff {
+                    name.push_str(&format!("<pre>{}</pre>", escape(diff)));
+                }
+                if let Some(dmesg) = &test.dmesg {
+                    name.push_str(&format!("<pre>{}</pre>", escape(dmesg)));
+                }
+                name.push_str("</details>");
+            } else if let Some(message) = &test.message {
+                name.push_str(&format!(" <span class=\"skip\">{}</span>", escape(message)));
+            }
+
+            body.push_str(&format!(
+                "<tr data-status=\"{}\" data-name=\"{}\"><td>{}</td><td>{}</td><td class=\"{}\">{}</td><td>{}s</td></tr>\n",
+                class,
+                escape(&format!("{} {}", suite.name, test.name)),
+                escape(&suite.name),
+                name,
+                class,
+                class,
+                test.time
+            ));
+        }
+    }
+    body.push_str("</tbody>\n</table>\n");
+    body.push_str(&format!("<script>{}</script>\n", SCRIPT));
+
+    page(&report.title, &body)
+}
+
+/// Index of several runs: totals and status of every test in every run
+pub fn matrix(reports: &[Report], pages: &[String]) -> String {
+    let mut body = "<h1>Test matrix</h1>\n<table>\n<tr><th>Run</th><th>Tests</th><th>Failed</th><th>Skipped</th></tr>\n".to_string();
+    for (report, page) in reports.iter().zip(pages) {
+        body.push_str(&format!(
+            "<tr><td><a href=\"{}\">{}</a></td><td>{}</td><td class=\"fail\">{}</td><td>{}</td></tr>\n",
+            escape(page),
+            escape(&report.title),
+            report.tests(),
+            report.count(Status::Fail),
+            report.count(Status::Skip)
+        ));
+    }
+    body.push_str("</table>\n");
+
+    let mut statuses: BTreeMap<String, Vec<Option<Status>>> = BTreeMap::new();
+    let names: BTreeSet<String> = reports
+        .iter()
+        .flat_map(|report| &report.suites)
+        .flat_map(|suite| {
+            suite
+                .tests
+                .iter()
+                .map(move |test| format!("{} {}", suite.name, test.name))
+        })
+        .collect();
+    for name in names {
+        statuses.insert(name, vec![None; reports.len()]);
+    }
+    for (index, report) in reports.iter().enumerate() {
+        for suite in &report.suites {
+            for test in &suite.tests {
+                if let Some(row) = statuses.get_mut(&format!("{} {}", suite.name, test.name)) {
+                    row[index] = Some(test.status);
+                }
+            }
+        }
+    }
+
+    body.push_str("<table>\n<tr><th>Test</th>");
+    for index in 1..=reports.len() {
+        body.push_str(&format!("<th>{index}</th>"));
+    }
+    body.push_str("</tr>\n");
+    for (name, row) in statuses {
+        body.push_str(&format!("<tr><td>{}</td>", escape(&name)));
+        for status in row {
+            match status {
+                Some(status) => {
+                    let class = status_class(status);
+                    body.push_str(&format!("<td class=\"{class}\">{class}</td>"));
+                }
+                None => body.push_str("<td></td>"),
+            }
+        }
+        body.push_str("</tr>\n");
+    }
+    body.push_str("</table>\n");
+
+    page("Test matrix", &body)
+}
+
+/// Write index.html of the run to `out`. Several runs get a page each and
+/// the matrix as index.html.
+pub fn write(out: &Path, reports: &[Report]) -> Result<PathBuf> {
+    if reports.is_empty() {
+        bail!("There're no runs to report");
+    }
+
+    fs::create_dir_all(out).with_context(|| format!("Failed to create {}", out.display()))?;
+    let index = out.join("index.html");
+
+    if let [report] = reports {
+        fs::write(&index, html(report))
+            .with_context(|| format!("Failed to write {}", index.display()))?;
+        return Ok(index);
+    }
+
+    let pages: Vec<String> = (1..=reports.len())
+        .map(|index| format!("run-{index}.html"))
+        .collect();
+    for (report, page) in reports.iter().zip(&pages) {
+        let path = out.join(page);
+        fs::write(&path, html(report))
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+    }
+    fs::write(&index, matrix(reports, &pages))
+        .with_context(|| format!("Failed to write {}", index.display()))?;
+
+    Ok(index)
+}
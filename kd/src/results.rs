@@ -0,0 +1,547 @@
+use anyhow::{bail, Context, Result};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::xml_escape;
+
+/// Lines of .out.bad diff and dmesg kept in reports
+const EXCERPT_LINES: usize = 100;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Junit,
+    Json,
+    Tap,
+    Markdown,
+}
+
+impl Format {
+    pub fn parse(format: &str) -> Result<Self> {
+        match format {
+            "junit" => Ok(Format::Junit),
+            "json" => Ok(Format::Json),
+            "tap" => Ok(Format::Tap),
+            "markdown" => Ok(Format::Markdown),
+            _ => bail!(
+                "Unknown format '{}', use junit, json, tap or markdown",
+                format
+            ),
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Pass,
+    Fail,
+    Skip,
+}
+
+#[derive(Serialize, Debug)]
+pub struct TestCase {
+    pub name: String,
+    /// Seconds
+    pub time: f64,
+    pub status: Status,
+    pub message: Option<String>,
+    /// Diff of the golden output and .out.bad
+    pub diff: Option<String>,
+    pub dmesg: Option<String>,
+}
+
+/// Tests of one xfstests section, `name` is <variant>/<section>
+#[derive(Serialize, Debug)]
+pub struct Suite {
+    pub name: String,
+    pub tests: Vec<TestCase>,
+}
+
+impl Suite {
+    pub fn count(&self, status: Status) -> usize {
+        self.tests
+            .iter()
+            .filter(|test| test.status == status)
+            .count()
+    }
+
+    pub fn time(&self) -> f64 {
+        self.tests.iter().map(|test| test.time).sum()
+    }
+}
+
+/// First EXCERPT_LINES lines of the text
+fn excerpt(text: &str) -> Option<String> {
+    let text = text.trim_matches('\n');
+    if text.trim().is_empty() {
+        return None;
+    }
+
+    let lines: Vec<&str> = text.lines().collect();
+    if lines.len() <= EXCERPT_LINES {
+        return Some(text.to_string());
+    }
+
+    Some(format!(
+        "{}\n... {} more lines",
+        lines[..EXCERPT_LINES].join("\n"),
+        lines.len() - EXCERPT_LINES
+    ))
+}
+
+fn child_text(node: roxmltree::Node, name: &str) -> Option<String> {
+    node.children()
+        .find(|child| child.tag_name().name() == name)
+        .map(|child| {
+            child
+                .descendants()
+                .filter(|node| node.is_text())
+                .filter_map(|node| node.text())
+                .collect::<String>()
+        })
+}
+
+/// .out.bad diff and dmesg files xfstests leaves next to result.xml, used if
+/// they are not in the xunit report
+fn failure_files(dir: &Path, test: &str) -> (Option<String>, Option<String>) {
+    let base = dir.join(test);
+    let with_suffix = |suffix: &str| {
+        let mut path = base.clone().into_os_string();
+        path.push(suffix);
+        PathBuf::from(path)
+    };
+
+    let bad = with_suffix(".out.bad");
+    let golden = with_suffix(".out");
+    let diff = if bad.exists() && golden.exists() {
+        Command::new("diff")
+            .arg("-u")
+            .arg(&golden)
+            .arg(&bad)
+            .output()
+            .ok()
+            .map(|output| String::from_utf8_lossy(&output.stdout).to_string())
+    } else {
+        fs::read_to_string(&bad).ok()
+    };
+
+    let dmesg = fs::read_to_string(with_suffix(".dmesg")).ok();
+
+    (
+        diff.as_deref().and_then(excerpt),
+        dmesg.as_deref().and_then(excerpt),
+    )
+}
+
+/// Parse xunit report xfstests writes with '-R xunit'. `dir` is the section
+/// directory, it's where failure details are looked up.
+pub fn parse_xunit(data: &str, name: &str, dir: &Path) -> Result<Suite> {
+    let document = roxmltree::Document::parse(data).context("Invalid xunit report")?;
+    let root = document.root_element();
+    if root.tag_name().name() != "testsuite" {
+        bail!(
+            "Invalid xunit report, root element is <{}>",
+            root.tag_name().name()
+        );
+    }
+
+    let mut tests = vec![];
+    for case in root
+        .children()
+        .filter(|node| node.tag_name().name() == "testcase")
+    {
+        let test = case.attribute("name").unwrap_or_default().to_string();
+        let time = case
+            .attribute("time")
+            .and_then(|time| time.parse().ok())
+            .unwrap_or(0.0);
+
+        let failure = case
+            .children()
+            .find(|node| matches!(node.tag_name().name(), "failure" | "error"));
+        let skipped = case
+            .children()
+            .find(|node| node.tag_name().name() == "skipped");
+
+        let (status, message) = match (failure, skipped) {
+            (Some(node), _) => (Status::Fail, node.attribute("message")),
+            (None, Some(node)) => (Status::Skip, node.attribute("message")),
+            (None, None) => (Status::Pass, None),
+        };
+
+        let (mut diff, mut dmesg) = (None, None);
+        if status == Status::Fail {
+            let (file_diff, file_dmesg) = failure_files(dir, &test);
+            diff = child_text(case, "system-err")
+                .as_deref()
+                .and_then(excerpt)
+                .or(file_diff);
+            dmesg = child_text(case, "kernel-log")
+                .as_deref()
+                .and_then(excerpt)
+                .or(file_dmesg);
+        }
+
+        tests.push(TestCase {
+            name: test,
+            time,
+            status,
+            message: message.map(|message| message.trim().to_string()),
+            diff,
+            dmesg,
+        });
+    }
+
+    Ok(Suite {
+        name: name.to_string(),
+        tests,
+    })
+}
+
+/// Files under `dir` with the name `matches` accepts
+fn find(dir: &Path, matches: fn(&Path) -> bool, files: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in fs::read_dir(dir).with_context(|| format!("Failed to read {}", dir.display()))? {
+        let path = entry
+            .with_context(|| format!("Failed to read {}", dir.display()))?
+            .path();
+        if path.is_dir() && !path.is_symlink() {
+            find(&path, matches, files)?;
+        } else if matches(&path) {
+            files.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+/// Mark tests the watchdog saved <group>/<test>.hang for as failed, they
+/// aren't in the xunit report or it wasn't written at all
+fn add_hangs(suite: &mut Suite, hangs: &[PathBuf]) {
+    for hang in hangs {
+        let (Some(group), Some(number)) = (
+            hang.parent().and_then(|dir| dir.file_name()),
+            hang.file_stem(),
+        ) else {
+            continue;
+        };
+        let test = format!("{}/{}", group.to_string_lossy(), number.to_string_lossy());
+        let dmesg = fs::read_to_string(hang).ok().as_deref().and_then(excerpt);
+        let message = Some(format!("hung, see {}", hang.display()));
+
+        match suite.tests.iter_mut().find(|case| case.name == test) {
+            Some(case) => {
+                case.status = Status::Fail;
+                case.message = message;
+                case.dmesg = dmesg;
+            }
+            None => suite.tests.push(TestCase {
+                name: test,
+                time: 0.0,
+                status: Status::Fail,
+                message,
+                diff: None,
+                dmesg,
+            }),
+        }
+    }
+}
+
+/// Variant (--name) of the run, kd writes it into the results before the VM
+/// starts, so it goes with them when they are uploaded
+pub const VARIANT: &str = "kd-variant";
+
+pub fn save_variant(results: &Path, variant: &str) -> Result<()> {
+    let path = results.join(VARIANT);
+    fs::write(&path, format!("{variant}\n"))
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// Variant the results were recorded with, None for results of older kd
+pub fn variant(results: &Path) -> Option<String> {
+    fs::read_to_string(results.join(VARIANT))
+        .ok()
+        .map(|variant| variant.trim().to_string())
+        .filter(|variant| !variant.is_empty())
+}
+
+/// Report of the tests which ran in the section before the n-th VM reset by
+/// the watchdog, xfstests writes result.xml only for the tests after it
+pub fn reset_report(dir: &Path, n: usize) -> PathBuf {
+    dir.join(format!("result.reset-{n}.xml"))
+}
+
+/// Order of the reports of a section, result.xml is the last one
+fn report_order(path: &Path) -> Option<usize> {
+    let name = path.file_name()?.to_str()?;
+    if name == "result.xml" {
+        return Some(usize::MAX);
+    }
+
+    name.strip_prefix("result.reset-")?
+        .strip_suffix(".xml")?
+        .parse()
+        .ok()
+}
+
+/// Load every result.xml under `results`, suites are named after their
+/// directory, prefixed with the variant of the run if it's recorded. Reports
+/// of the section saved before VM resets are merged into it.
+pub fn load(results: &Path) -> Result<Vec<Suite>> {
+    if !results.is_dir() {
+        bail!(
+            "There're no results in {}, run tests first",
+            results.display()
+        );
+    }
+
+    let mut reports = vec![];
+    find(
+        results,
+        |path| report_order(path).is_some(),
+        &mut reports,
+    )?;
+    let mut hangs = vec![];
+    find(
+        results,
+        |path| path.extension().is_some_and(|ext| ext == "hang"),
+        &mut hangs,
+    )?;
+    if reports.is_empty() && hangs.is_empty() {
+        bail!(
+            "There're no xunit reports in {}, run xfstests with '-R xunit'",
+            results.display()
+        );
+    }
+
+    // Section directories with their reports and hangs, the hung one may
+    // have no report
+    type Section = (Vec<PathBuf>, Vec<PathBuf>);
+    let mut sections: BTreeMap<PathBuf, Section> = BTreeMap::new();
+    for report in reports {
+        let dir = report.parent().unwrap_or(results);
+        sections.entry(dir.to_path_buf()).or_default().0.push(report);
+    }
+    for hang in hangs {
+        let dir = hang
+            .parent()
+            .and_then(|group| group.parent())
+            .unwrap_or(results);
+        sections.entry(dir.to_path_buf()).or_default().1.push(hang);
+    }
+
+    let variant = variant(results);
+    let mut suites = vec![];
+    for (dir, (mut reports, hangs)) in sections {
+        let section = dir
+            .strip_prefix(results)
+            .unwrap_or(&dir)
+            .display()
+            .to_string();
+        let name = match (variant.as_deref(), section.is_empty()) {
+            (Some(variant), true) => variant.to_string(),
+            (Some(variant), false) => format!("{variant}/{section}"),
+            (None, true) => "results".to_string(),
+            (None, false) => section,
+        };
+
+        let mut suite = Suite {
+            name,
+            tests: vec![],
+        };
+        // Tests of the later report win
+        reports.sort_by_key(|report| report_order(report));
+        for report in reports {
+            let data = fs::read_to_string(&report)
+                .with_context(|| format!("Failed to read {}", report.display()))?;
+            let tests = parse_xunit(&data, &suite.name, &dir)
+                .with_context(|| format!("Failed to parse {}", report.display()))?
+                .tests;
+            suite
+                .tests
+                .retain(|case| !tests.iter().any(|test| test.name == case.name));
+            suite.tests.extend(tests);
+        }
+        add_hangs(&mut suite, &hangs);
+        suites.push(suite);
+    }
+
+    Ok(suites)
+}
+
+/// Control characters from the console are not allowed in XML
+fn xml_text(text: &str) -> String {
+    let text: String = text
+        .chars()
+        .filter(|c| !c.is_control() || matches!(c, '\n' | '\t'))
+        .collect();
+    xml_escape(&text)
+}
+
+fn failure_body(test: &TestCase) -> String {
+    let mut body = vec![];
+    if let Some(diff) = &test.diff {
+        body.push(diff.clone());
+    }
+    if let Some(dmesg) = &test.dmesg {
+        body.push(format!("dmesg:\n{dmesg}"));
+    }
+    body.join("\n\n")
+}
+
+pub fn junit(suites: &[Suite]) -> String {
+    let total = |status| {
+        suites
+            .iter()
+            .map(|suite| suite.count(status))
+            .sum::<usize>()
+    };
+    let tests: usize = suites.iter().map(|suite| suite.tests.len()).sum();
+    let time: f64 = suites.iter().map(|suite| suite.time()).sum();
+
+    let mut out = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    out.push_str(&format!(
+        "<testsuites name=\"kd\" tests=\"{}\" failures=\"{}\" skipped=\"{}\" time=\"{}\">\n",
+        tests,
+        total(Status::Fail),
+        total(Status::Skip),
+        time
+    ));
+
+    for suite in suites {
+        testsuite(&mut out, suite, "  ");
+    }
+
+    out.push_str("</testsuites>\n");
+    out
+}
+
+/// <testsuite> of the suite, each line is indented by `indent`
+fn testsuite(out: &mut String, suite: &Suite, indent: &str) {
+    out.push_str(&format!(
+        "{indent}<testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" skipped=\"{}\" time=\"{}\">\n",
+        xml_escape(&suite.name),
+        suite.tests.len(),
+        suite.count(Status::Fail),
+        suite.count(Status::Skip),
+        suite.time()
+    ));
+
+    for test in &suite.tests {
+        let open = format!(
+            "{indent}  <testcase classname=\"{}\" name=\"{}\" time=\"{}\"",
+            xml_escape(&suite.name),
+            xml_escape(&test.name),
+            test.time
+        );
+        let message = xml_escape(test.message.as_deref().unwrap_or_default());
+        match test.status {
+            Status::Pass => out.push_str(&format!("{open}/>\n")),
+            Status::Skip => out.push_str(&format!(
+                "{open}>\n{indent}    <skipped message=\"{message}\"/>\n{indent}  </testcase>\n"
+            )),
+            Status::Fail => out.push_str(&format!(
+                "{open}>\n{indent}    <failure message=\"{message}\">{}</failure>\n{indent}  </testcase>\n",
+                xml_text(&failure_body(test))
+            )),
+        }
+    }
+
+    out.push_str(&format!("{indent}</testsuite>\n"));
+}
+
+/// xunit report of one section as xfstests writes it
+pub fn xunit(suite: &Suite) -> String {
+    let mut out = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    testsuite(&mut out, suite, "");
+    out
+}
+
+pub fn json(suites: &[Suite]) -> Result<String> {
+    serde_json::to_string_pretty(suites).context("Failed to serialize results")
+}
+
+/// TAP version 13, failure details go into YAML blocks
+pub fn tap(suites: &[Suite]) -> String {
+    let tests: usize = suites.iter().map(|suite| suite.tests.len()).sum();
+    let mut out = format!("TAP version 13\n1..{tests}\n");
+    let mut number = 0;
+
+    for suite in suites {
+        for test in &suite.tests {
+            number += 1;
+            let name = format!("{} {}", suite.name, test.name);
+            match test.status {
+                Status::Pass => out.push_str(&format!("ok {number} - {name}\n")),
+                Status::Skip => out.push_str(&format!(
+                    "ok {number} - {name} # SKIP {}\n",
+                    test.message.as_deref().unwrap_or_default()
+                )),
+                Status::Fail => {
+                    out.push_str(&format!("not ok {number} - {name}\n  ---\n"));
+                    if let Some(message) = &test.message {
+                        out.push_str(&format!("  message: {:?}\n", message));
+                    }
+                    for (key, value) in [("diff", &test.diff), ("dmesg", &test.dmesg)] {
+                        if let Some(value) = value {
+                            out.push_str(&format!("  {key}: |\n"));
+                            for line in value.lines() {
+                                out.push_str(&format!("    {line}\n"));
+                            }
+                        }
+                    }
+                    out.push_str("  ...\n");
+                }
+            }
+        }
+    }
+
+    out
+}
+
+pub fn markdown(suites: &[Suite]) -> String {
+    let mut out = String::from("| Suite | Tests | Failed | Skipped | Time |\n");
+    out.push_str("|---|---|---|---|---|\n");
+    for suite in suites {
+        out.push_str(&format!(
+            "| {} | {} | {} | {} | {}s |\n",
+            suite.name,
+            suite.tests.len(),
+            suite.count(Status::Fail),
+            suite.count(Status::Skip),
+            suite.time()
+        ));
+    }
+
+    for suite in suites {
+        for test in suite
+            .tests
+            .iter()
+            .filter(|test| test.status == Status::Fail)
+        {
+            out.push_str(&format!("\n### {} {}\n\n", suite.name, test.name));
+            if let Some(message) = &test.message {
+                out.push_str(&format!("{message}\n\n"));
+            }
+            if let Some(diff) = &test.diff {
+                out.push_str(&format!("```diff\n{diff}\n```\n"));
+            }
+            if let Some(dmesg) = &test.dmesg {
+                out.push_str(&format!("\ndmesg:\n\n```\n{dmesg}\n```\n"));
+            }
+        }
+    }
+
+    out
+}
+
+pub fn render(suites: &[Suite], format: Format) -> Result<String> {
+    Ok(match format {
+        Format::Junit => junit(suites),
+        Format::Json => json(suites)?,
+        Format::Tap => tap(suites),
+        Format::Markdown => markdown(suites),
+    })
+}
@@ -0,0 +1,169 @@
+use anyhow::{bail, Context, Result};
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::config::{ShareEntry, SystemConfig};
+
+/// Where the share dir is mounted in the VM
+pub const GUEST_DIR: &str = "/root/share";
+
+/// System config of the run, the VM reads [script] from it
+pub const KD_TOML: &str = "kd.toml";
+
+/// Script from [script], run by the VM instead of xfstests
+pub const SCRIPT: &str = "script.sh";
+
+/// xfstests results of the last run
+pub const RESULTS: &str = "results";
+
+/// Copies of files from [share], the VM links them to their targets
+pub const FILES: &str = "files";
+
+/// QEMU takes mount tags up to 31 characters
+const TAG_MAX: usize = 31;
+
+/// Share dir of the environment, the VM sees it as /root/share
+pub fn dir(envdir: &Path) -> PathBuf {
+    envdir.join("share")
+}
+
+/// 9p mount tag of the [share] directory entry
+pub fn tag(name: &str) -> String {
+    format!("kd-{name}")
+}
+
+fn check_path(path: &str) -> Result<()> {
+    if let Some(c) = path
+        .chars()
+        .find(|c| c.is_whitespace() || ",\"$\\".contains(*c))
+    {
+        bail!("'{}' can not contain {:?}", path, c);
+    }
+    Ok(())
+}
+
+/// Check that sources exist and targets don't overlap in the VM
+pub fn validate(entries: &BTreeMap<String, ShareEntry>, curdir: &Path) -> Result<()> {
+    let mut targets = HashSet::new();
+
+    for (name, entry) in entries {
+        let valid = !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "-_".contains(c));
+        if !valid {
+            bail!("Invalid name '{}', use letters, digits, '-' and '_'", name);
+        }
+        if tag(name).len() > TAG_MAX {
+            bail!("Name '{}' is longer than {} characters", name, TAG_MAX - 3);
+        }
+
+        check_path(&entry.source)?;
+        let source = curdir.join(&entry.source);
+        if !source.exists() {
+            bail!("'{}' source doesn't exist: {}", name, entry.source);
+        }
+
+        let target = entry.target(name);
+        check_path(&target)?;
+        let guest = Path::new(&target);
+        if !guest.is_absolute() || guest == Path::new("/") {
+            bail!("'{}' target has to be an absolute path in the VM", name);
+        }
+        if guest.starts_with(GUEST_DIR) || guest.starts_with("/nix") {
+            bail!("'{}' target {} is managed by kd", name, target);
+        }
+        if !targets.insert(guest.to_path_buf()) {
+            bail!("'{}' target {} is used by another entry", name, target);
+        }
+    }
+
+    Ok(())
+}
+
+/// Files, not directories, of [share] with their sources on the host
+fn files(system: &SystemConfig, curdir: &Path) -> Vec<(String, PathBuf, bool)> {
+    let Some(entries) = &system.share else {
+        return vec![];
+    };
+
+    entries
+        .iter()
+        .map(|(name, entry)| (name.clone(), curdir.join(&entry.source), entry.readonly()))
+        .filter(|(_, source, _)| !source.is_dir())
+        .collect()
+}
+
+/// Lay out the share dir for the next run: results of the previous one are
+/// removed, the config, [script] and files of [share] are copied in.
+pub fn prepare(system: &SystemConfig, curdir: &Path, share: &Path) -> Result<()> {
+    for name in [RESULTS, SCRIPT, FILES] {
+        let path = share.join(name);
+        let removed = if path.is_dir() {
+            fs::remove_dir_all(&path)
+        } else if path.exists() {
+            fs::remove_file(&path)
+        } else {
+            Ok(())
+        };
+        removed.with_context(|| format!("Failed to remove {}", path.display()))?;
+    }
+    fs::create_dir_all(share.join(RESULTS))
+        .with_context(|| format!("Failed to create {}", share.display()))?;
+
+    let config = toml::to_string(system).context("Failed to serialize config for the VM")?;
+    fs::write(share.join(KD_TOML), config)
+        .with_context(|| format!("Failed to write {}", share.join(KD_TOML).display()))?;
+
+    if let Some(script) = &system.script {
+        let source = curdir.join(&script.script);
+        fs::copy(&source, share.join(SCRIPT))
+            .with_context(|| format!("Failed to copy [script] {}", source.display()))?;
+    }
+
+    for (name, source, readonly) in files(system, curdir) {
+        let target = share.join(FILES).join(&name);
+        fs::create_dir_all(share.join(FILES))?;
+        fs::copy(&source, &target).with_context(|| {
+            format!(
+                "Failed to copy {} to {}",
+                source.display(),
+                target.display()
+            )
+        })?;
+        if readonly {
+            let mut permissions = fs::metadata(&target)?.permissions();
+            permissions.set_readonly(true);
+            fs::set_permissions(&target, permissions)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Copy read-write files of [share] changed in the VM back to the host,
+/// returns the updated ones
+pub fn sync(system: &SystemConfig, curdir: &Path, share: &Path) -> Result<Vec<PathBuf>> {
+    let mut updated = vec![];
+
+    for (name, source, readonly) in files(system, curdir) {
+        let copy = share.join(FILES).join(&name);
+        if readonly || !copy.exists() {
+            continue;
+        }
+        let changed = fs::read(&copy)? != fs::read(&source).unwrap_or_default();
+        if changed {
+            fs::copy(&copy, &source).with_context(|| {
+                format!(
+                    "Failed to copy {} back to {}",
+                    copy.display(),
+                    source.display()
+                )
+            })?;
+            updated.push(source);
+        }
+    }
+
+    Ok(updated)
+}
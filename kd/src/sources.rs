@@ -0,0 +1,260 @@
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Revision which means "whatever is in the worktree right now"
+pub const DIRTY: &str = "dirty";
+
+fn nurl(repo: &str, rev: &str) -> Result<String> {
+    eprintln!("Fetching source for {} at {}", repo, rev);
+    let output = Command::new("nurl")
+        .arg("--fetcher")
+        .arg("builtins.fetchGit")
+        .arg("--arg")
+        .arg("allRefs")
+        .arg("true")
+        .arg(repo)
+        .arg(rev)
+        .output()
+        .context("Failed to fetch source with nurl (see 'kd doctor')")?;
+
+    if !output.status.success() {
+        bail!("{}", String::from_utf8_lossy(&output.stderr));
+    }
+
+    String::from_utf8(output.stdout).context("Failed to parse nurl output")
+}
+
+/// Store path of the source nurl fetched
+fn store_path(src: &str) -> Result<String> {
+    let output = Command::new("nix")
+        .arg("eval")
+        .arg("--raw")
+        .arg("--expr")
+        .arg(format!("({src}).outPath"))
+        .output()
+        .context("Failed to run 'nix eval'")?;
+
+    if !output.status.success() {
+        bail!("{}", String::from_utf8_lossy(&output.stderr));
+    }
+
+    String::from_utf8(output.stdout).context("Failed to parse 'nix eval' output")
+}
+
+/// Source fetched with nurl, `path` is where it is in the Nix store
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Pin {
+    pub src: String,
+    pub path: Option<String>,
+}
+
+/// Existing paths under `root` matching `pattern`, relative to `root`. '*'
+/// matches a whole path component.
+fn expand(root: &Path, pattern: &str) -> Vec<String> {
+    let mut found = vec![String::new()];
+    for component in pattern.split('/') {
+        let mut next = vec![];
+        for prefix in &found {
+            let dir = root.join(prefix);
+            let names = if component == "*" {
+                match fs::read_dir(&dir) {
+                    Ok(entries) => entries
+                        .filter_map(|entry| entry.ok())
+                        .map(|entry| entry.file_name().to_string_lossy().to_string())
+                        .collect(),
+                    Err(_) => vec![],
+                }
+            } else {
+                vec![component.to_string()]
+            };
+            for name in names {
+                let relative = if prefix.is_empty() {
+                    name
+                } else {
+                    format!("{prefix}/{name}")
+                };
+                if root.join(&relative).exists() {
+                    next.push(relative);
+                }
+            }
+        }
+        found = next;
+    }
+    found.sort();
+    found
+}
+
+/// Turns repositories and revisions from the config into Nix expressions.
+/// Remote sources are prefetched with nurl once and recorded in the pins file,
+/// later runs, offline ones included, use the record. Local repositories are
+/// emitted directly.
+pub struct Sources {
+    pub offline: bool,
+    pub curdir: PathBuf,
+    /// Some of the sources can not be evaluated in pure mode
+    pub impure: bool,
+    pins_path: Option<PathBuf>,
+    pins: BTreeMap<String, Pin>,
+    changed: bool,
+}
+
+impl Sources {
+    pub fn new(offline: bool, curdir: PathBuf, pins_path: Option<PathBuf>) -> Result<Self> {
+        let pins = match &pins_path {
+            Some(path) if path.exists() => {
+                let data = fs::read_to_string(path)
+                    .with_context(|| format!("Failed to read {}", path.display()))?;
+                // Older kd recorded only the expression
+                let old = toml::from_str::<BTreeMap<String, String>>(&data).map(|pins| {
+                    pins.into_iter()
+                        .map(|(key, src)| (key, Pin { src, path: None }))
+                        .collect()
+                });
+                match old {
+                    Ok(pins) => pins,
+                    Err(_) => toml::from_str(&data)
+                        .with_context(|| format!("Failed to parse {}", path.display()))?,
+                }
+            }
+            _ => BTreeMap::new(),
+        };
+
+        Ok(Self {
+            offline,
+            curdir,
+            impure: false,
+            pins_path,
+            pins,
+            changed: false,
+        })
+    }
+
+    /// Is repository on this machine, such as file:///path, /path or ../path
+    pub fn is_local(&self, repo: &str) -> bool {
+        repo.starts_with("file://")
+            || repo.starts_with('/')
+            || repo.starts_with('.')
+            || (!repo.contains(':') && self.curdir.join(repo).is_dir())
+    }
+
+    fn local_path(&self, repo: &str) -> Result<PathBuf> {
+        let path = repo.strip_prefix("file://").unwrap_or(repo);
+        let path = std::path::absolute(self.curdir.join(path))
+            .with_context(|| format!("Failed to resolve path {}", repo))?;
+
+        if !path.exists() {
+            bail!("Local repository {} doesn't exist", path.display());
+        }
+
+        Ok(path)
+    }
+
+    /// Nix expression for the source of `repo` at `rev`
+    pub fn fetch(&mut self, repo: &str, rev: &str) -> Result<String> {
+        if self.is_local(repo) {
+            let url = format!("file://{}", self.local_path(repo)?.display());
+            if rev == DIRTY {
+                // fetchGit without rev takes worktree with uncommitted changes.
+                // Modules use src.rev for the package version.
+                self.impure = true;
+                return Ok(format!(
+                    "(let src = builtins.fetchGit {{ url = \"{url}\"; }}; in src // {{ rev = src.rev or \"{DIRTY}\"; }})"
+                ));
+            }
+
+            return Ok(format!(
+                "builtins.fetchGit {{\n  url = \"{url}\";\n  rev = \"{rev}\";\n  allRefs = true;\n}}"
+            ));
+        }
+
+        if rev == DIRTY {
+            bail!("'{}' revision can only be used with local repositories", DIRTY);
+        }
+
+        let key = format!("{repo}#{rev}");
+        if self.offline {
+            let Some(pin) = self.pins.get(&key) else {
+                bail!(
+                    "{} at {} is not available offline, run once without --offline to fetch it",
+                    repo,
+                    rev
+                );
+            };
+            // Pinned, but could be garbage collected since
+            if !pin.path.as_ref().is_some_and(|path| Path::new(path).exists()) {
+                bail!(
+                    "{} at {} is not in the Nix store, run once without --offline to fetch it",
+                    repo,
+                    rev
+                );
+            }
+            return Ok(pin.src.clone());
+        }
+
+        // Pinned before, Nix fetches it again if it was garbage collected
+        if let Some(pin) = self.pins.get(&key).filter(|pin| pin.path.is_some()) {
+            return Ok(pin.src.clone());
+        }
+
+        let src = nurl(repo, rev)?;
+        let pin = Pin {
+            path: Some(store_path(&src)?),
+            src: src.clone(),
+        };
+        self.pins.insert(key, pin);
+        self.changed = true;
+
+        Ok(src)
+    }
+
+    /// Nix expression for a local directory, used as is with all uncommitted
+    /// changes. `exclude` is a list of file names which are not copied to the
+    /// store, names starting with '*' are suffixes (e.g. "*.o"). Names with
+    /// '/' are paths relative to `path`, where '*' matches any directory
+    /// (e.g. "arch/*/include/generated").
+    pub fn path(&mut self, path: &str, name: &str, exclude: &[&str]) -> Result<String> {
+        let path = self.local_path(path)?;
+        let quote = |name: &str| format!("\"{name}\"");
+        let names = exclude
+            .iter()
+            .filter(|name| !name.starts_with('*') && !name.contains('/'))
+            .map(|name| quote(name))
+            .collect::<Vec<String>>()
+            .join(" ");
+        let suffixes = exclude
+            .iter()
+            .filter_map(|name| name.strip_prefix('*'))
+            .map(quote)
+            .collect::<Vec<String>>()
+            .join(" ");
+        let mut paths = vec![];
+        for pattern in exclude.iter().filter(|name| name.contains('/')) {
+            paths.extend(expand(&path, pattern).iter().map(|path| quote(path)));
+        }
+        let paths = paths.join(" ");
+
+        self.impure = true;
+        Ok(format!(
+            "{{ outPath = builtins.path {{ path = /. + \"{path}\"; name = \"{name}\"; filter = path: type: let name = baseNameOf path; relative = pkgs.lib.removePrefix \"{path}/\" (toString path); in !(builtins.elem name [{names}]) && !(builtins.any (suffix: pkgs.lib.hasSuffix suffix name) [{suffixes}]) && !(builtins.elem relative [{paths}]); }}; rev = \"{DIRTY}\"; }}",
+            path = path.display()
+        ))
+    }
+
+    /// Record pins fetched with nurl
+    pub fn save(&self) -> Result<()> {
+        let Some(path) = &self.pins_path else {
+            return Ok(());
+        };
+
+        if !self.changed {
+            return Ok(());
+        }
+
+        let data = toml::to_string(&self.pins).context("Failed to serialize source pins")?;
+        fs::write(path, data).with_context(|| format!("Failed to write {}", path.display()))
+    }
+}
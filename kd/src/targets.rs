@@ -0,0 +1,119 @@
+use anyhow::{bail, Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::plan::Plan;
+
+/// Outputs of the kd flake everybody needs
+pub const KNOWN: &[(&str, &str)] = &[
+    ("kernel", "Kernel built from [kernel]"),
+    ("vm-kernel", "Kernel the VM boots, vmlinux is in its 'dev' output"),
+    ("kconfig", "Kernel .config"),
+    ("headers", "Kernel headers"),
+    ("initrd", "Initrd of the image"),
+    ("vm", "QEMU VM runner"),
+    ("prebuild", "QEMU VM runner with 'prebuild' kernel"),
+    ("image", "Bootable raw disk image"),
+    ("iso", "Bootable ISO image"),
+    ("qcow2", "Bootable disk image in qcow2 format"),
+];
+
+pub struct Artifact {
+    pub path: PathBuf,
+    pub size: u64,
+}
+
+/// Package names from 'nix flake show --json' output
+pub fn parse(data: &str) -> Result<Vec<String>> {
+    let show: serde_json::Value =
+        serde_json::from_str(data).context("Failed to parse 'nix flake show' output")?;
+
+    let mut targets: Vec<String> = show
+        .get("packages")
+        .and_then(|packages| packages.as_object())
+        .into_iter()
+        .flat_map(|systems| systems.values())
+        .filter_map(|packages| packages.as_object())
+        .flat_map(|packages| packages.keys().cloned())
+        .collect();
+    targets.sort();
+    targets.dedup();
+
+    Ok(targets)
+}
+
+/// Packages of the kd flake
+pub fn available(plan: &Plan) -> Result<Vec<String>> {
+    let mut cmd = plan.nix("flake show");
+    cmd.arg("--json")
+        .arg(format!("path:{}", plan.flake_dir.display()));
+
+    if plan.debug {
+        println!("command: {:?}", cmd);
+    }
+
+    let output = cmd
+        .output()
+        .context("Failed to spawn 'nix flake show' (see 'kd doctor')")?;
+    if !output.status.success() {
+        bail!(
+            "'nix flake show' failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+
+    parse(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// Check that `target` is one of the flake packages
+pub fn validate(target: &str, available: &[String]) -> Result<()> {
+    if available.iter().any(|name| name == target) {
+        return Ok(());
+    }
+
+    bail!(
+        "Unknown target '{}', available targets: {}",
+        target,
+        available.join(", ")
+    );
+}
+
+pub fn describe(target: &str) -> Option<&'static str> {
+    KNOWN
+        .iter()
+        .find(|(name, _)| *name == target)
+        .map(|(_, description)| *description)
+}
+
+/// Size of the store path, symlinks are not followed
+pub fn size(path: &Path) -> Result<u64> {
+    let metadata =
+        fs::symlink_metadata(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    if !metadata.is_dir() {
+        return Ok(metadata.len());
+    }
+
+    let mut total = 0;
+    for entry in fs::read_dir(path).with_context(|| format!("Failed to read {}", path.display()))? {
+        let entry = entry.with_context(|| format!("Failed to read {}", path.display()))?;
+        total += size(&entry.path())?;
+    }
+
+    Ok(total)
+}
+
+/// Store paths printed by 'nix build --print-out-paths'
+pub fn artifacts(output: &str) -> Result<Vec<Artifact>> {
+    output
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            let path = PathBuf::from(line);
+            Ok(Artifact {
+                size: size(&path)?,
+                path,
+            })
+        })
+        .collect()
+}
@@ -0,0 +1,511 @@
+use anyhow::{bail, Context, Result};
+use std::fs;
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::config::{Credentials, DirUpload, GitUpload, HttpUpload, ResultsConfig};
+use crate::plan::Plan;
+use crate::results;
+
+pub const DEFAULT_LAYOUT: &str = "<host>/<kernel>/<variant>/<date>";
+const DEFAULT_BRANCH: &str = "main";
+const PLACEHOLDERS: &[&str] = &["host", "kernel", "variant", "date"];
+
+/// Run which results are uploaded, fills the layout
+#[derive(Default, Debug)]
+pub struct RunInfo {
+    pub host: String,
+    pub kernel: String,
+    pub variant: String,
+    pub date: String,
+}
+
+impl RunInfo {
+    /// Describe the last run: kernel release and date are taken from the
+    /// console log, config is used if there's none
+    pub fn new(plan: &Plan) -> Self {
+        let log = latest_log(&plan.envdir.join("share"));
+        let console = log
+            .as_ref()
+            .and_then(|log| fs::read_to_string(log).ok())
+            .unwrap_or_default();
+
+        let host = plan
+            .results
+            .as_ref()
+            .and_then(|results| results.host.clone())
+            .or_else(|| fs::read_to_string("/proc/sys/kernel/hostname").ok())
+            .unwrap_or_default();
+
+        let kernel = kernel_release(&console).or_else(|| {
+            let kernel = plan.kernel()?;
+            if kernel.prebuild.is_some() {
+                Some("prebuild".to_string())
+            } else {
+                kernel.version.clone().or(kernel.rev.clone())
+            }
+        });
+
+        let date = log
+            .as_deref()
+            .and_then(log_date)
+            .or_else(|| {
+                let output = Command::new("date").arg("+%Y-%m-%d_%H-%M").output().ok()?;
+                Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
+            })
+            .unwrap_or_default();
+
+        // Recorded by the run, --name of this command may be different
+        let variant = results::variant(&plan.results_dir()).unwrap_or_else(|| plan.variant());
+
+        Self {
+            host: host.trim().to_string(),
+            kernel: kernel.unwrap_or_default(),
+            variant,
+            date,
+        }
+    }
+}
+
+/// Check that layout uses only known placeholders and stays relative
+pub fn validate_layout(layout: &str) -> Result<()> {
+    let mut rest = layout;
+    while let Some(start) = rest.find('<') {
+        let Some(end) = rest[start..].find('>') else {
+            bail!("Unclosed placeholder in layout '{}'", layout);
+        };
+        let name = &rest[start + 1..start + end];
+        if !PLACEHOLDERS.contains(&name) {
+            bail!(
+                "Unknown placeholder <{}> in layout '{}', use {}",
+                name,
+                layout,
+                PLACEHOLDERS
+                    .iter()
+                    .map(|name| format!("<{name}>"))
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            );
+        }
+        rest = &rest[start + end + 1..];
+    }
+
+    if layout.starts_with('/')
+        || layout
+            .split('/')
+            .any(|part| part.is_empty() || part == "..")
+    {
+        bail!("Layout '{}' has to be a relative path", layout);
+    }
+
+    Ok(())
+}
+
+/// Values end up as path components, no slashes or spaces
+fn component(value: &str) -> String {
+    let value: String = value
+        .trim()
+        .chars()
+        .map(|c| {
+            if c == '/' || c.is_whitespace() {
+                '-'
+            } else {
+                c
+            }
+        })
+        .collect();
+    if value.is_empty() || value == "." || value == ".." {
+        "unknown".to_string()
+    } else {
+        value
+    }
+}
+
+/// Relative path of the run in the backend
+pub fn render(layout: &str, run: &RunInfo) -> Result<String> {
+    validate_layout(layout)?;
+
+    Ok(layout
+        .replace("<host>", &component(&run.host))
+        .replace("<kernel>", &component(&run.kernel))
+        .replace("<variant>", &component(&run.variant))
+        .replace("<date>", &component(&run.date)))
+}
+
+/// The newest execution_<date>.log in the share dir
+pub fn latest_log(share: &Path) -> Option<PathBuf> {
+    let mut logs: Vec<PathBuf> = fs::read_dir(share)
+        .ok()?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.file_name()
+                .map(|name| name.to_string_lossy())
+                .is_some_and(|name| name.starts_with("execution_") && name.ends_with(".log"))
+        })
+        .collect();
+    logs.sort();
+    logs.pop()
+}
+
+/// Date of the run from the execution_<date>.log name
+pub fn log_date(log: &Path) -> Option<String> {
+    let name = log.file_name()?.to_string_lossy();
+    let date = name.strip_prefix("execution_")?.strip_suffix(".log")?;
+    Some(date.to_string())
+}
+
+/// Kernel release from the "Linux version" line the kernel prints on boot
+pub fn kernel_release(console: &str) -> Option<String> {
+    console.lines().find_map(|line| {
+        let (_, rest) = line.split_once("Linux version ")?;
+        rest.split_whitespace().next().map(str::to_string)
+    })
+}
+
+/// Token from the environment variable or the file
+pub fn token(credentials: &Credentials) -> Result<Option<String>> {
+    if let Some(name) = &credentials.token_env {
+        if let Ok(token) = std::env::var(name) {
+            if !token.trim().is_empty() {
+                return Ok(Some(token.trim().to_string()));
+            }
+        }
+    }
+
+    if let Some(file) = &credentials.token_file {
+        let token = fs::read_to_string(file)
+            .with_context(|| format!("Failed to read token from {}", file))?;
+        return Ok(Some(token.trim().to_string()));
+    }
+
+    if let Some(name) = &credentials.token_env {
+        bail!(
+            "Token is not set, ${} is empty and there's no 'token_file'",
+            name
+        );
+    }
+
+    Ok(None)
+}
+
+/// Copy results directory, symlinks (e.g. .out files linked by xfstests) are
+/// followed and dropped if broken
+fn copy_results(source: &Path, target: &Path) -> Result<()> {
+    fs::create_dir_all(target).with_context(|| format!("Unable to create {}", target.display()))?;
+
+    for entry in
+        fs::read_dir(source).with_context(|| format!("Failed to read {}", source.display()))?
+    {
+        let entry = entry.with_context(|| format!("Failed to read {}", source.display()))?;
+        let path = entry.path();
+        let destination = target.join(entry.file_name());
+
+        if path.is_dir() {
+            copy_results(&path, &destination)?;
+        } else if path.exists() {
+            fs::copy(&path, &destination).with_context(|| {
+                format!(
+                    "Failed to copy {} to {}",
+                    path.display(),
+                    destination.display()
+                )
+            })?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Pack results into .tar.gz, they are copied first to get rid of symlinks
+fn tarball(results: &Path, output: &Path) -> Result<()> {
+    if let Some(parent) = output.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Unable to create {}", parent.display()))?;
+    }
+
+    let staging = output.with_extension("staging");
+    if staging.exists() {
+        fs::remove_dir_all(&staging)
+            .with_context(|| format!("Failed to remove {}", staging.display()))?;
+    }
+    copy_results(results, &staging)?;
+
+    let status = Command::new("tar")
+        .arg("-czf")
+        .arg(output)
+        .arg("-C")
+        .arg(&staging)
+        .arg(".")
+        .status();
+    fs::remove_dir_all(&staging)
+        .with_context(|| format!("Failed to remove {}", staging.display()))?;
+    if !status.context("Failed to spawn 'tar'")?.success() {
+        bail!(
+            "Failed to pack {} into {}",
+            results.display(),
+            output.display()
+        );
+    }
+
+    Ok(())
+}
+
+/// Put results into `<path>/<dest>` or `<path>/<dest>.tar.gz`, replacing the
+/// previous upload of the same run
+pub fn to_dir(config: &DirUpload, results: &Path, dest: &str) -> Result<PathBuf> {
+    let base = PathBuf::from(&config.path);
+
+    if config.tarball.unwrap_or(false) {
+        let output = base.join(format!("{dest}.tar.gz"));
+        tarball(results, &output)?;
+        return Ok(output);
+    }
+
+    let output = base.join(dest);
+    if output.exists() {
+        fs::remove_dir_all(&output)
+            .with_context(|| format!("Failed to remove {}", output.display()))?;
+    }
+    copy_results(results, &output)?;
+
+    Ok(output)
+}
+
+/// Write file only the user can access, it's created with `mode` and not
+/// chmod'ed later
+fn write_private(path: &Path, data: &str, mode: u32) -> Result<()> {
+    let _ = fs::remove_file(path);
+    fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(mode)
+        .open(path)
+        .and_then(|mut file| file.write_all(data.as_bytes()))
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// git with token passed through askpass, so it never shows up in arguments
+/// or logs
+fn git(workdir: &Path, askpass: Option<&(PathBuf, String)>) -> Command {
+    let mut cmd = Command::new("git");
+    cmd.arg("-C").arg(workdir).env("GIT_TERMINAL_PROMPT", "0");
+    if let Some((script, token)) = askpass {
+        cmd.env("GIT_ASKPASS", script)
+            .env("KD_RESULTS_TOKEN", token);
+    }
+    cmd
+}
+
+fn run_git(cmd: &mut Command, what: &str) -> Result<String> {
+    let output = cmd
+        .output()
+        .with_context(|| format!("Failed to spawn 'git {}'", what))?;
+    if !output.status.success() {
+        bail!(
+            "'git {}' failed: {}",
+            what,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+/// Commit results into `<dest>` of the repository as one commit and push it.
+/// `workdir` is recreated on every upload.
+pub fn to_git(config: &GitUpload, results: &Path, dest: &str, workdir: &Path) -> Result<()> {
+    if workdir.exists() {
+        fs::remove_dir_all(workdir)
+            .with_context(|| format!("Failed to remove {}", workdir.display()))?;
+    }
+    fs::create_dir_all(workdir)
+        .with_context(|| format!("Unable to create {}", workdir.display()))?;
+
+    let askpass = match token(&config.credentials)? {
+        Some(token) => {
+            let script = workdir.with_extension("askpass");
+            write_private(
+                &script,
+                "#!/bin/sh\ncase \"$1\" in\nUsername*) echo x-access-token ;;\n*) echo \"$KD_RESULTS_TOKEN\" ;;\nesac\n",
+                0o700,
+            )?;
+            Some((script, token))
+        }
+        None => None,
+    };
+
+    // The script goes away even if git fails
+    let pushed = push(config, results, dest, workdir, askpass.as_ref());
+    if let Some((script, _)) = &askpass {
+        let _ = fs::remove_file(script);
+    }
+    pushed
+}
+
+fn push(
+    config: &GitUpload,
+    results: &Path,
+    dest: &str,
+    workdir: &Path,
+    askpass: Option<&(PathBuf, String)>,
+) -> Result<()> {
+    let branch = config.branch.as_deref().unwrap_or(DEFAULT_BRANCH);
+    let repo = config.repo.as_str();
+
+    run_git(git(workdir, askpass).args(["init", "-q"]), "init")?;
+    let heads = run_git(
+        git(workdir, askpass)
+            .args(["ls-remote", "--heads", repo])
+            .arg(format!("refs/heads/{branch}")),
+        "ls-remote",
+    )?;
+    if heads.trim().is_empty() {
+        run_git(
+            git(workdir, askpass).args(["checkout", "-q", "--orphan", branch]),
+            "checkout",
+        )?;
+    } else {
+        run_git(
+            git(workdir, askpass).args(["fetch", "-q", "--depth", "1", repo, branch]),
+            "fetch",
+        )?;
+        run_git(
+            git(workdir, askpass).args(["checkout", "-q", "-B", branch, "FETCH_HEAD"]),
+            "checkout",
+        )?;
+    }
+
+    let target = workdir.join(dest);
+    if target.exists() {
+        fs::remove_dir_all(&target)
+            .with_context(|| format!("Failed to remove {}", target.display()))?;
+    }
+    copy_results(results, &target)?;
+
+    run_git(git(workdir, askpass).args(["add", "-A"]), "add")?;
+    let mut commit = git(workdir, askpass);
+    if run_git(git(workdir, None).args(["config", "user.email"]), "config").is_err() {
+        commit
+            .env("GIT_AUTHOR_NAME", "kd")
+            .env("GIT_AUTHOR_EMAIL", "kd@localhost")
+            .env("GIT_COMMITTER_NAME", "kd")
+            .env("GIT_COMMITTER_EMAIL", "kd@localhost");
+    }
+    run_git(
+        commit
+            .args(["commit", "-q", "--allow-empty", "-m"])
+            .arg(format!("Results of {dest}")),
+        "commit",
+    )?;
+    run_git(
+        git(workdir, askpass)
+            .args(["push", "-q", repo])
+            .arg(format!("HEAD:refs/heads/{branch}")),
+        "push",
+    )?;
+
+    Ok(())
+}
+
+/// PUT results tarball to `<url>/<dest>.tar.gz`, token goes as Bearer in a
+/// header file, not in curl arguments
+pub fn to_http(config: &HttpUpload, results: &Path, dest: &str, tmpdir: &Path) -> Result<String> {
+    let url = format!("{}/{}.tar.gz", config.url.trim_end_matches('/'), dest);
+    let archive = tmpdir.join("results.tar.gz");
+    tarball(results, &archive)?;
+
+    let mut cmd = Command::new("curl");
+    cmd.args(["--fail", "--silent", "--show-error", "--upload-file"])
+        .arg(&archive);
+
+    let headers = tmpdir.join("results.headers");
+    let token = token(&config.credentials)?;
+    if let Some(token) = &token {
+        write_private(&headers, &format!("Authorization: Bearer {token}\n"), 0o600)?;
+        cmd.arg("--header").arg(format!("@{}", headers.display()));
+    }
+    cmd.arg(&url);
+
+    let output = cmd.output().context("Failed to spawn 'curl'");
+    let _ = fs::remove_file(&archive);
+    if token.is_some() {
+        let _ = fs::remove_file(&headers);
+    }
+    let output = output?;
+    if !output.status.success() {
+        bail!(
+            "Failed to upload results to {}: {}",
+            url,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+
+    Ok(url)
+}
+
+/// Upload results and the console `log` with every configured backend,
+/// returns where they went
+pub fn upload(
+    config: &ResultsConfig,
+    run: &RunInfo,
+    results: &Path,
+    log: Option<&Path>,
+    envdir: &Path,
+) -> Result<Vec<String>> {
+    if !results.is_dir() || fs::read_dir(results)?.next().is_none() {
+        bail!(
+            "There're no results in {}, run tests first",
+            results.display()
+        );
+    }
+    if config.git.is_none() && config.dir.is_none() && config.http.is_none() {
+        bail!(
+            "No upload backend is configured, add [results.git], [results.dir] or [results.http]"
+        );
+    }
+
+    let dest = render(config.layout.as_deref().unwrap_or(DEFAULT_LAYOUT), run)?;
+
+    // 'kd report --input' finds the kernel and splats of the run in the log
+    let staging = envdir.join("results-upload");
+    if staging.exists() {
+        fs::remove_dir_all(&staging)
+            .with_context(|| format!("Failed to remove {}", staging.display()))?;
+    }
+    copy_results(results, &staging)?;
+    if let Some(log) = log {
+        let target = staging.join(log.file_name().unwrap_or_default());
+        fs::copy(log, &target).with_context(|| format!("Failed to copy {}", log.display()))?;
+    }
+
+    let uploaded = upload_staged(config, &staging, &dest, envdir);
+    let _ = fs::remove_dir_all(&staging);
+    uploaded
+}
+
+fn upload_staged(
+    config: &ResultsConfig,
+    results: &Path,
+    dest: &str,
+    envdir: &Path,
+) -> Result<Vec<String>> {
+    let mut uploaded = vec![];
+
+    if let Some(dir) = &config.dir {
+        let output = to_dir(dir, results, dest)?;
+        uploaded.push(output.display().to_string());
+    }
+
+    if let Some(git) = &config.git {
+        to_git(git, results, dest, &envdir.join("results-git"))?;
+        uploaded.push(format!("{}:{}", git.repo, dest));
+    }
+
+    if let Some(http) = &config.http {
+        uploaded.push(to_http(http, results, dest, envdir)?);
+    }
+
+    Ok(uploaded)
+}
@@ -0,0 +1,302 @@
+use anyhow::{Context, Result};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::config::XfstestsTimeouts;
+use crate::progress::{duration, Progress};
+use crate::qmp::Qmp;
+use crate::results::{self, Suite};
+
+/// Tests xfstests skips after the VM is reset, a file per section passed
+/// with -E. It's in the share directory, so the VM sees it as
+/// /root/share/xfstests.exclude.
+pub const EXCLUDE: &str = "xfstests.exclude";
+
+/// group.list of every test directory, the VM copies them from xfstests
+pub const GROUPS: &str = "xfstests.groups";
+
+/// Exclude file of the run without sections
+pub const DEFAULT_SECTION: &str = "default";
+
+/// Sections which ran to the end, the VM doesn't run them again
+pub const FINISHED: &str = ".finished";
+
+/// Console is collected after sysrq until it's quiet for this long
+const QUIET: Duration = Duration::from_secs(5);
+
+/// Stop collecting even if kernel keeps printing
+const CAPTURE: Duration = Duration::from_secs(60);
+
+struct Capture {
+    test: String,
+    section: Option<String>,
+    timeout: Duration,
+    started: Instant,
+    last: Instant,
+    lines: Vec<String>,
+}
+
+/// Watches run time of the current test. When it's over the timeout, blocked
+/// tasks (sysrq-w) and all tasks (sysrq-t) are dumped to the console and
+/// saved as <test>.hang in the results.
+pub struct Watchdog {
+    timeouts: XfstestsTimeouts,
+    socket: PathBuf,
+    share: PathBuf,
+    capture: Option<Capture>,
+    /// Timeout of the running test, groups are read once per test
+    current: Option<(String, Option<Duration>)>,
+    /// Tests which ran so far by section
+    ran: BTreeMap<String, BTreeSet<String>>,
+    pub hung: Vec<String>,
+}
+
+impl Watchdog {
+    pub fn new(timeouts: XfstestsTimeouts, socket: &Path, share: &Path) -> Self {
+        Self {
+            timeouts,
+            socket: socket.to_path_buf(),
+            share: share.to_path_buf(),
+            capture: None,
+            current: None,
+            ran: BTreeMap::new(),
+            hung: vec![],
+        }
+    }
+
+    /// Collect the console while diagnostics are captured
+    pub fn feed(&mut self, line: &str, now: Instant) {
+        if let Some(capture) = &mut self.capture {
+            capture
+                .lines
+                .push(line.trim_end_matches(['\r', '\n']).to_string());
+            capture.last = now;
+        }
+    }
+
+    /// Check the running test, returns what happened to tell the user
+    pub fn check(&mut self, progress: &mut Progress, now: Instant) -> Result<Vec<String>> {
+        // Progress forgets them on every section
+        self.ran
+            .entry(section_name(progress))
+            .or_default()
+            .extend(progress.done.iter().cloned());
+
+        if let Some(capture) = &self.capture {
+            if now.duration_since(capture.last) >= QUIET
+                || now.duration_since(capture.started) >= CAPTURE
+            {
+                return self.finish(progress);
+            }
+            return Ok(vec![]);
+        }
+
+        let Some((test, started)) = &progress.current else {
+            return Ok(vec![]);
+        };
+        if self.hung.contains(test) {
+            return Ok(vec![]);
+        }
+        let timeout = match &self.current {
+            Some((current, timeout)) if current == test => *timeout,
+            _ => {
+                let timeout = self.timeouts.timeout(test, &groups(&self.share, test))?;
+                self.current = Some((test.clone(), timeout));
+                timeout
+            }
+        };
+        let Some(timeout) = timeout else {
+            return Ok(vec![]);
+        };
+        if now.duration_since(*started) < timeout {
+            return Ok(vec![]);
+        }
+
+        let mut messages = vec![format!(
+            "{} runs longer than {}, collecting blocked tasks",
+            test,
+            duration(timeout)
+        )];
+        let mut capture = Capture {
+            test: test.clone(),
+            section: progress.section.clone(),
+            timeout,
+            started: now,
+            last: now,
+            lines: vec![],
+        };
+        let sysrq = Qmp::connect(&self.socket).and_then(|mut qmp| {
+            qmp.sysrq('w')?;
+            qmp.sysrq('t')
+        });
+        let failed = sysrq.is_err();
+        if let Err(err) = sysrq {
+            messages.push(format!("Failed to trigger sysrq: {:#}", err));
+            capture
+                .lines
+                .push(format!("kd: failed to trigger sysrq: {:#}", err));
+        }
+
+        self.capture = Some(capture);
+        if failed {
+            // Nothing will come, save right away
+            messages.extend(self.finish(progress)?);
+        }
+        Ok(messages)
+    }
+
+    /// Results directory of the section
+    fn section_dir(&self, section: Option<&str>) -> PathBuf {
+        let mut path = self.share.join("results");
+        if let Some(section) = section {
+            path.push(section);
+        }
+        path
+    }
+
+    /// Path of the diagnostics, next to .out.bad of the test
+    pub fn hang_file(&self, section: Option<&str>, test: &str) -> PathBuf {
+        self.section_dir(section).join(format!("{test}.hang"))
+    }
+
+    fn finish(&mut self, progress: &mut Progress) -> Result<Vec<String>> {
+        let Some(capture) = self.capture.take() else {
+            return Ok(vec![]);
+        };
+
+        let path = self.hang_file(capture.section.as_deref(), &capture.test);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create {}", parent.display()))?;
+        }
+        let mut data = format!(
+            "{} hung, it was running for longer than {}\n",
+            capture.test,
+            duration(capture.timeout)
+        );
+        for line in &capture.lines {
+            data.push_str(line);
+            data.push('\n');
+        }
+        fs::write(&path, data).with_context(|| format!("Failed to write {}", path.display()))?;
+
+        self.hung.push(capture.test.clone());
+        self.ran
+            .entry(section_name(progress))
+            .or_default()
+            .insert(capture.test.clone());
+        progress.failed += 1;
+        progress.done.insert(capture.test.clone());
+        let mut messages = vec![format!(
+            "{} HUNG, diagnostics are in {}",
+            capture.test,
+            path.display()
+        )];
+
+        if self.timeouts.reboot == Some(true) {
+            self.save_results(progress)?;
+            self.exclude(progress)?;
+            match Qmp::connect(&self.socket).and_then(|mut qmp| qmp.reset()) {
+                Ok(()) => {
+                    progress.current = None;
+                    messages.push("VM is reset, remaining tests run after reboot".to_string());
+                }
+                Err(err) => messages.push(format!("Failed to reset VM: {:#}", err)),
+            }
+        }
+
+        Ok(messages)
+    }
+
+    /// xfstests writes the report at the end of the section, keep results of
+    /// the tests which ran before the reset
+    fn save_results(&self, progress: &mut Progress) -> Result<()> {
+        if progress.results.is_empty() {
+            return Ok(());
+        }
+
+        let dir = self.section_dir(progress.section.as_deref());
+        fs::create_dir_all(&dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+        let mut n = 1;
+        while results::reset_report(&dir, n).exists() {
+            n += 1;
+        }
+        let path = results::reset_report(&dir, n);
+        let suite = Suite {
+            name: section_name(progress),
+            tests: std::mem::take(&mut progress.results),
+        };
+        fs::write(&path, results::xunit(&suite))
+            .with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    /// Write tests which already ran (or hung) for xfstests to skip, by
+    /// section, and the sections which are done
+    fn exclude(&self, progress: &Progress) -> Result<()> {
+        let dir = self.share.join(EXCLUDE);
+        fs::create_dir_all(&dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+
+        let mut files: Vec<(String, String)> = self
+            .ran
+            .iter()
+            .map(|(section, tests)| {
+                let data = tests.iter().map(|test| format!("{test}\n")).collect();
+                (section.clone(), data)
+            })
+            .collect();
+        let finished = progress
+            .finished
+            .iter()
+            .map(|section| format!("{section}\n"))
+            .collect();
+        files.push((FINISHED.to_string(), finished));
+
+        for (name, data) in files {
+            let path = dir.join(name);
+            fs::write(&path, data)
+                .with_context(|| format!("Failed to write {}", path.display()))?;
+        }
+        Ok(())
+    }
+}
+
+/// Remove tests to skip of the previous run, older kd wrote a single file
+pub fn clean(share: &Path) -> Result<()> {
+    let path = share.join(EXCLUDE);
+    let removed = if path.is_dir() {
+        fs::remove_dir_all(&path)
+    } else if path.exists() {
+        fs::remove_file(&path)
+    } else {
+        Ok(())
+    };
+    removed.with_context(|| format!("Failed to remove {}", path.display()))
+}
+
+/// xfstests groups of the test from its group.list, e.g. ["auto", "quick"]
+pub fn groups(share: &Path, test: &str) -> Vec<String> {
+    let Some((dir, name)) = test.split_once('/') else {
+        return vec![];
+    };
+    let Ok(list) = fs::read_to_string(share.join(GROUPS).join(dir)) else {
+        return vec![];
+    };
+
+    list.lines()
+        .map(|line| line.split('#').next().unwrap_or_default())
+        .find_map(|line| {
+            let mut words = line.split_whitespace();
+            (words.next() == Some(name)).then(|| words.map(str::to_string).collect())
+        })
+        .unwrap_or_default()
+}
+
+/// Name of the section exclude file
+fn section_name(progress: &Progress) -> String {
+    progress
+        .section
+        .clone()
+        .unwrap_or_else(|| DEFAULT_SECTION.to_string())
+}
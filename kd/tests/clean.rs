@@ -0,0 +1,102 @@
+use anyhow::Result;
+use kd::clean::{self, ArtifactKind, Selection};
+use kd::State;
+use std::fs;
+use std::time::Duration;
+
+mod common;
+use common::temp_dir;
+
+#[test]
+fn kd_clean_artifacts() -> Result<()> {
+    let curdir = temp_dir("clean")?;
+    let envdir = curdir.join(".kd");
+    fs::create_dir_all(envdir.join("share/results/xfs_4k"))?;
+    fs::create_dir_all(envdir.join("snapshots"))?;
+    fs::create_dir_all(envdir.join("flake"))?;
+
+    fs::write(envdir.join("image.qcow2"), "root")?;
+    fs::write(envdir.join("snapshots/clean.qcow2"), "snapshot")?;
+    fs::write(envdir.join("share/execution_2026-01-01_10-00.log"), "log")?;
+    fs::write(envdir.join("share/kd.toml"), "")?;
+    fs::write(envdir.join("share/results/xfs_4k/result.xml"), "<xml/>")?;
+    fs::write(curdir.join(".config.bup"), "config")?;
+    fs::write(curdir.join(".config.1.bup"), "config")?;
+    fs::write(curdir.join(".kd.toml.1.bup"), "config")?;
+    std::os::unix::fs::symlink("/nix/store/nonexistent-linux-config", envdir.join("result"))?;
+
+    let state = State {
+        curdir: curdir.clone(),
+        envdir: envdir.clone(),
+        ..State::default()
+    };
+
+    let artifacts = clean::artifacts(&state)?;
+    let kinds = |kind: ArtifactKind| artifacts.iter().filter(|a| a.kind == kind).count();
+    assert_eq!(kinds(ArtifactKind::Result), 2);
+    assert_eq!(kinds(ArtifactKind::Image), 2);
+    assert_eq!(kinds(ArtifactKind::Log), 1);
+    assert_eq!(kinds(ArtifactKind::Backup), 3);
+    assert_eq!(kinds(ArtifactKind::Share), 1);
+    // flake is not an artifact
+    assert!(!artifacts.iter().any(|a| a.path.ends_with("flake")));
+
+    let selection = Selection {
+        logs: true,
+        results: true,
+        ..Selection::default()
+    };
+    for artifact in artifacts.iter().filter(|a| selection.includes(a.kind)) {
+        clean::remove(artifact)?;
+    }
+    assert!(!envdir.join("result").is_symlink());
+    assert!(!envdir.join("share/results").exists());
+    assert!(envdir.join("image.qcow2").exists());
+    assert!(envdir.join("share/kd.toml").exists());
+
+    // Nothing is older than a day yet
+    let artifacts = clean::artifacts(&state)?;
+    assert!(!artifacts.iter().any(|a| a.is_older_than(Duration::from_secs(86400))));
+
+    // Only links 'kd build' created are its results
+    std::os::unix::fs::symlink("/nix/store/nonexistent-linux", curdir.join("result"))?;
+    std::os::unix::fs::symlink("/nix/store/nonexistent-linux-dev", curdir.join("result-dev"))?;
+    std::os::unix::fs::symlink("/nix/store/nonexistent-other", curdir.join("result-mine"))?;
+    std::os::unix::fs::symlink(".kd", curdir.join("results"))?;
+    let artifacts = clean::artifacts(&state)?;
+    assert!(!artifacts.iter().any(|a| a.path == curdir.join("result")));
+    assert!(clean::gc_roots(&state)?.is_empty());
+
+    let outputs = [
+        "/nix/store/nonexistent-linux".into(),
+        "/nix/store/nonexistent-linux-dev".into(),
+    ];
+    clean::record_gc_roots(&curdir, &envdir, &outputs)?;
+    clean::record_gc_roots(&curdir, &envdir, &outputs)?;
+    let roots = clean::gc_roots(&state)?;
+    assert_eq!(
+        roots.iter().map(|a| a.path.clone()).collect::<Vec<_>>(),
+        [curdir.join("result"), curdir.join("result-dev")]
+    );
+
+    let selection = Selection {
+        gc_roots: true,
+        ..Selection::default()
+    };
+    assert!(!selection.is_empty());
+    assert!(!selection.includes(ArtifactKind::Result));
+
+    fs::remove_dir_all(&curdir)?;
+    Ok(())
+}
+
+#[test]
+fn kd_clean_parse_age() -> Result<()> {
+    assert_eq!(clean::parse_age("30m")?, Duration::from_secs(1800));
+    assert_eq!(clean::parse_age("12h")?, Duration::from_secs(43200));
+    assert_eq!(clean::parse_age("7d")?, Duration::from_secs(604800));
+    assert_eq!(clean::parse_age("7")?, Duration::from_secs(604800));
+    assert_eq!(clean::parse_age("2w")?, Duration::from_secs(1209600));
+    assert!(clean::parse_age("soon").is_err());
+    Ok(())
+}
@@ -0,0 +1,13 @@
+use anyhow::Result;
+use std::fs;
+use std::path::PathBuf;
+
+/// Empty directory for the test, named after it
+pub fn temp_dir(name: &str) -> Result<PathBuf> {
+    let dir = std::env::temp_dir().join(format!("kd-{}-{}", name, std::process::id()));
+    if dir.exists() {
+        fs::remove_dir_all(&dir)?;
+    }
+    fs::create_dir_all(&dir)?;
+    Ok(dir)
+}
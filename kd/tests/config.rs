@@ -1,6 +1,8 @@
-use kd::config::Config;
-use kd::{generate_uconfig, State};
+use kd::config::{parse_size, Config, SystemConfig, TraceConfig, XfstestsConfig, XfstestsTimeouts};
+use kd::plan::Plan;
+use kd::State;
 use anyhow::Result;
+use std::time::Duration;
 
 #[test]
 fn kd_normal_config() -> Result<()> {
@@ -27,3 +29,150 @@ fn kd_corrupted_config() -> Result<()> {
 //    assert_eq!(nix_config, "{\n    uconfig = {pkgs, kd}: with pkgs; {\n        services.xfstests = { arguments = \"-r -s xfs_4k -g auto\"; };\nservices.xfsprogs = { src = builtins.fetchGit {\n  url = \"file:///home/aalbersh/Release/xfsprogs-dev\";\n  rev = \"922f14a9b77638b4a3fc604169df6799d16f8fd7\";\n  allRefs = true;\n}; };\n    };\n}\n");
 //    Ok(())
 //}
+
+#[test]
+fn kd_vm_config() -> Result<()> {
+    let config = Config::load("tests/assets/vm.toml")?;
+    assert!(config.validate().is_ok());
+
+    let state = State {
+        config,
+        ..State::default()
+    };
+    let plan = Plan::new(&state)?;
+    // The image, iso and qcow2 don't have these options
+    assert!(!plan.uconfig.contains("virtualisation"));
+    assert!(!plan.uconfig.contains("/dev/vd"));
+    let vm_config = &plan.vmconfig;
+    assert!(vm_config.contains("memorySize = 8192;"));
+    assert!(vm_config.contains("cores = 8;"));
+    assert!(vm_config.contains("diskSize = 40960;"));
+    assert!(vm_config.contains("emptyDiskImages = [12288\n12288\n1024\n2048\n];"));
+    assert!(vm_config.contains("test.main = pkgs.lib.mkDefault \"/dev/vdb\";"));
+    assert!(vm_config.contains("scratch.logdev = pkgs.lib.mkDefault \"/dev/vdd\";"));
+    // Explicit device wins over the one derived from disk role
+    assert!(plan
+        .uconfig
+        .contains("scratch.main = \"/dev/disk/by-label/scratch\";"));
+    assert!(vm_config.contains("scratch.main = pkgs.lib.mkDefault \"/dev/vdc\";"));
+    Ok(())
+}
+
+#[test]
+fn kd_vm_duplicate_role() -> Result<()> {
+    let config = Config::load("tests/assets/vm-duplicate-role.toml")?;
+    assert!(config.validate().is_err());
+    Ok(())
+}
+
+#[test]
+fn kd_share_invalid() -> Result<()> {
+    let config = Config::load("tests/assets/share-invalid.toml")?;
+    let error = format!("{:#}", config.validate().unwrap_err());
+    assert!(error.starts_with("Invalid [named.small.share]"), "{error}");
+    Ok(())
+}
+
+#[test]
+fn kd_parse_size() -> Result<()> {
+    assert_eq!(parse_size("4096")?, 4096);
+    assert_eq!(parse_size("512M")?, 512);
+    assert_eq!(parse_size("12G")?, 12288);
+    assert_eq!(parse_size("1T")?, 1048576);
+    assert!(parse_size("12X").is_err());
+    assert!(parse_size("0").is_err());
+    Ok(())
+}
+
+#[test]
+fn kd_xfstests_timeouts() -> Result<()> {
+    let config = Config::load("tests/assets/timeouts.toml")?;
+    assert!(config.validate().is_ok());
+
+    let timeouts = config
+        .xfstests
+        .unwrap_or_default()
+        .timeouts
+        .unwrap_or_default();
+    assert_eq!(
+        timeouts.timeout("generic/475", &[])?,
+        Some(Duration::from_secs(7200))
+    );
+    assert_eq!(
+        timeouts.timeout("xfs/001", &[])?,
+        Some(Duration::from_secs(3600))
+    );
+    // Numbers without suffix are seconds
+    assert_eq!(
+        timeouts.timeout("generic/001", &[])?,
+        Some(Duration::from_secs(90))
+    );
+    assert_eq!(
+        timeouts.timeout("generic/002", &[])?,
+        Some(Duration::from_secs(1800))
+    );
+    // The longest of the groups, over the directory but not over the test
+    let groups = ["auto".to_string(), "quick".to_string(), "stress".to_string()];
+    assert_eq!(
+        timeouts.timeout("generic/002", &groups)?,
+        Some(Duration::from_secs(10800))
+    );
+    assert_eq!(
+        timeouts.timeout("xfs/001", &groups[..2])?,
+        Some(Duration::from_secs(600))
+    );
+    assert_eq!(
+        timeouts.timeout("generic/475", &groups)?,
+        Some(Duration::from_secs(7200))
+    );
+    assert_eq!(timeouts.reboot, Some(true));
+    assert_eq!(XfstestsTimeouts::default().timeout("generic/001", &[])?, None);
+
+    let zero = XfstestsTimeouts {
+        default: Some("0s".to_string()),
+        ..XfstestsTimeouts::default()
+    };
+    assert!(zero.validate().is_err());
+
+    let mut common = Config::load("tests/assets/timeouts.toml")?;
+    common.common = Some(SystemConfig {
+        xfstests: Some(XfstestsConfig {
+            timeouts: Some(zero),
+            ..XfstestsConfig::default()
+        }),
+        ..SystemConfig::default()
+    });
+    assert!(common.validate().is_err());
+    Ok(())
+}
+
+#[test]
+fn kd_trace_config() -> Result<()> {
+    let trace = TraceConfig {
+        events: vec!["xfs:*".to_string(), "block:block_rq_issue".to_string()],
+        buffer: Some("64M".to_string()),
+        mode: Some("failed".to_string()),
+    };
+    assert!(trace.validate().is_ok());
+    assert_eq!(trace.buffer_kb()?, Some(65536));
+
+    let invalid = [
+        TraceConfig::default(),
+        TraceConfig {
+            events: vec!["xfs:* -o /etc/passwd".to_string()],
+            ..trace.clone()
+        },
+        TraceConfig {
+            buffer: Some("64X".to_string()),
+            ..trace.clone()
+        },
+        TraceConfig {
+            mode: Some("sometimes".to_string()),
+            ..trace.clone()
+        },
+    ];
+    for trace in invalid {
+        assert!(trace.validate().is_err());
+    }
+    Ok(())
+}
@@ -0,0 +1,117 @@
+use anyhow::Result;
+use kd::deploy::{self, Deployment, Domain, Virsh};
+use std::fs;
+use std::path::PathBuf;
+
+mod common;
+use common::temp_dir;
+
+#[test]
+fn kd_deploy_domain_xml() -> Result<()> {
+    let mut domain = Domain {
+        name: "kd-linux".to_string(),
+        memory: 8192,
+        cpus: 8,
+        disk: PathBuf::from("/var/lib/libvirt/images/kd-linux.raw"),
+        network: Some("anet".to_string()),
+        sha256: "c0ffee".to_string(),
+    };
+
+    let xml = deploy::domain_xml(&domain);
+    assert!(xml.contains("<name>kd-linux</name>"));
+    assert!(xml.contains("<memory unit='MiB'>8192</memory>"));
+    assert!(xml.contains("<vcpu>8</vcpu>"));
+    assert!(xml.contains("<source file='/var/lib/libvirt/images/kd-linux.raw'/>"));
+    assert!(xml.contains("<target dev='sda' bus='sata'/>"));
+    assert!(xml.contains("<source network='anet'/>"));
+    assert!(xml.contains("<console type='pty'>"));
+    assert_eq!(deploy::deployed_sha256(&xml), Some("c0ffee".to_string()));
+
+    domain.network = None;
+    domain.disk = PathBuf::from("/tmp/it's.raw");
+    let xml = deploy::domain_xml(&domain);
+    assert!(xml.contains("<interface type='user'>"));
+    assert!(xml.contains("/tmp/it&apos;s.raw"));
+
+    // libvirt rewrites the XML with double quotes
+    assert_eq!(
+        deploy::deployed_sha256("<kd:image sha256=\"beef\"/>"),
+        Some("beef".to_string())
+    );
+    assert_eq!(deploy::deployed_sha256("<domain/>"), None);
+    Ok(())
+}
+
+#[test]
+fn kd_deploy_image() -> Result<()> {
+    assert!(deploy::validate_name("kd-linux_1.2").is_ok());
+    assert!(deploy::validate_name("kd linux").is_err());
+    assert!(deploy::validate_name("").is_err());
+
+    // 32G for partitions on top of the image
+    assert_eq!(deploy::capacity(1), 32 * 1024 + 1);
+    assert_eq!(deploy::capacity(1024 * 1024 * 1024), 33 * 1024);
+
+    let dir = temp_dir("deploy")?;
+    assert!(deploy::find_image(&dir).is_err());
+    fs::write(dir.join("image_7.0.raw"), "image")?;
+    fs::write(dir.join("image_7.0.raw.sha256sum"), "abc  image_7.0.raw\n")?;
+    let image = deploy::find_image(&dir)?;
+    assert_eq!(image, dir.join("image_7.0.raw"));
+    assert_eq!(deploy::image_sha256(&image)?, "abc");
+
+    fs::remove_file(dir.join("image_7.0.raw.sha256sum"))?;
+    assert_eq!(
+        deploy::image_sha256(&image)?,
+        "6105d6cc76af400325e94d588ce511be5bfdbb73b437dc51eca43917d7a43e3d"
+    );
+
+    fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
+/// Needs session libvirtd with 'default' storage pool, run with
+/// 'cargo test -- --ignored'
+#[test]
+#[ignore]
+fn kd_deploy_session() -> Result<()> {
+    let dir = temp_dir("deploy-session")?;
+    let image = dir.join("image.raw");
+    fs::write(&image, vec![0u8; 1024 * 1024])?;
+
+    let virsh = Virsh::new("qemu:///session", false);
+    let deployment = Deployment {
+        virsh: &virsh,
+        name: format!("kd-test-{}", std::process::id()),
+        pool: "default".to_string(),
+        network: None,
+        memory: 256,
+        cpus: 1,
+        xml: dir.join("domain.xml"),
+    };
+
+    deploy::deploy(&deployment, &image)?;
+    let xml = virsh.run(&["dumpxml", &deployment.name])?;
+    let sha256 = deploy::image_sha256(&image)?;
+    assert_eq!(deploy::deployed_sha256(&xml), Some(sha256));
+
+    // Second time the domain is replaced
+    deploy::deploy(&deployment, &image)?;
+    assert!(virsh.check(&["dominfo", &deployment.name]));
+
+    // Failed upload of the changed image leaves the domain running
+    fs::remove_file(&image)?;
+    assert!(deploy::deploy(&deployment, &image).is_err());
+    assert_eq!(virsh.run(&["domstate", &deployment.name])?, "running");
+
+    fs::write(&image, vec![1u8; 1024 * 1024])?;
+    deploy::deploy(&deployment, &image)?;
+    let vol = format!("{}.new.raw", deployment.name);
+    assert!(!virsh.check(&["vol-info", "--pool", "default", &vol]));
+
+    deploy::remove(&deployment, true)?;
+    assert!(!virsh.check(&["dominfo", &deployment.name]));
+
+    fs::remove_dir_all(&dir)?;
+    Ok(())
+}
@@ -0,0 +1,95 @@
+use anyhow::Result;
+use kd::{disks, qmp, State};
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixListener;
+use std::thread;
+
+mod common;
+use common::temp_dir;
+
+fn state(name: &str) -> Result<State> {
+    let envdir = temp_dir(&format!("disks-{}", name))?;
+
+    Ok(State {
+        envdir,
+        ..State::default()
+    })
+}
+
+#[test]
+fn kd_disks_snapshot_restore() -> Result<()> {
+    let state = state("snapshot")?;
+    let root = disks::root_image(&state);
+
+    assert!(disks::snapshot(&state, "clean").is_err());
+
+    fs::write(&root, "clean")?;
+    disks::snapshot(&state, "clean")?;
+    fs::write(&root, "dirty")?;
+
+    let names: Vec<String> = disks::list(&state)?.into_iter().map(|d| d.name).collect();
+    assert_eq!(names, vec!["root", "snapshot:clean"]);
+
+    disks::restore(&state, "clean")?;
+    assert_eq!(fs::read_to_string(&root)?, "clean");
+    assert!(disks::restore(&state, "missing").is_err());
+    assert!(disks::snapshot(&state, "../escape").is_err());
+
+    fs::remove_dir_all(&state.envdir)?;
+    Ok(())
+}
+
+#[test]
+fn kd_disks_reset() -> Result<()> {
+    let state = state("reset")?;
+    let root = disks::root_image(&state);
+
+    assert!(!disks::reset(&state)?);
+    fs::write(&root, "image")?;
+    assert!(disks::reset(&state)?);
+    assert!(!root.exists());
+
+    let image = disks::image_disk(&state);
+    assert!(!disks::reset_image(&state)?);
+    fs::write(&image, "image")?;
+    fs::write(state.envdir.join("repart-image.qcow2.sha256sum"), "abc")?;
+    let names: Vec<String> = disks::list(&state)?.into_iter().map(|d| d.name).collect();
+    assert_eq!(names, vec!["root", "image"]);
+    assert!(disks::reset_image(&state)?);
+    assert!(!image.exists());
+    assert!(!state.envdir.join("repart-image.qcow2.sha256sum").exists());
+
+    fs::remove_dir_all(&state.envdir)?;
+    Ok(())
+}
+
+#[test]
+fn kd_disks_running() -> Result<()> {
+    let state = state("running")?;
+    fs::write(disks::root_image(&state), "image")?;
+    disks::snapshot(&state, "clean")?;
+
+    // QEMU answers on the QMP socket
+    let listener = UnixListener::bind(qmp::socket(&state.envdir))?;
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(mut stream) = stream else {
+                return;
+            };
+            let _ = writeln!(stream, r#"{{"QMP": {{"version": {{}}, "capabilities": []}}}}"#);
+            let reader = BufReader::new(stream.try_clone().unwrap());
+            for _ in reader.lines() {
+                let _ = writeln!(stream, r#"{{"return": {{}}}}"#);
+            }
+        }
+    });
+
+    assert!(disks::snapshot(&state, "dirty").is_err());
+    assert!(disks::restore(&state, "clean").is_err());
+    assert!(disks::reset(&state).is_err());
+    assert!(disks::root_image(&state).exists());
+
+    fs::remove_dir_all(&state.envdir)?;
+    Ok(())
+}
@@ -0,0 +1,50 @@
+use anyhow::Result;
+use kd::doctor::{self, Status};
+use std::fs;
+use std::path::Path;
+
+mod common;
+use common::temp_dir;
+
+#[test]
+fn kd_doctor_environment() -> Result<()> {
+    let curdir = temp_dir("doctor")?;
+
+    assert_eq!(doctor::check_flake(&curdir).status, Status::Error);
+    assert_eq!(doctor::check_envrc(&curdir).status, Status::Warning);
+
+    let flake_dir = curdir.join(".kd/flake");
+    fs::create_dir_all(&flake_dir)?;
+    fs::write(flake_dir.join("flake.nix"), "")?;
+    fs::write(flake_dir.join("uconfig.nix"), "")?;
+    let check = doctor::check_flake(&curdir);
+    assert_eq!(check.status, Status::Error);
+    assert!(check.message.contains("flake.lock"));
+
+    fs::write(flake_dir.join("flake.lock"), "")?;
+    assert_eq!(doctor::check_flake(&curdir).status, Status::Warning);
+    fs::write(flake_dir.join("flake.nix"), "(import ./vmconfig.nix)")?;
+    assert_eq!(doctor::check_flake(&curdir).status, Status::Ok);
+
+    fs::write(curdir.join(".envrc"), "use flake\n")?;
+    assert_eq!(doctor::check_envrc(&curdir).status, Status::Warning);
+    fs::write(curdir.join(".envrc"), format!("{}\n", doctor::ENVRC))?;
+    assert_eq!(doctor::check_envrc(&curdir).status, Status::Ok);
+
+    fs::remove_dir_all(&curdir)?;
+    Ok(())
+}
+
+#[test]
+fn kd_doctor_config() -> Result<()> {
+    let check = doctor::check_config(Path::new("tests/assets/config.toml"));
+    assert_eq!(check.status, Status::Ok);
+
+    let check = doctor::check_config(Path::new("tests/assets/corrupted.toml"));
+    assert_eq!(check.status, Status::Error);
+    assert!(check.fix.is_some());
+
+    let check = doctor::check_config(Path::new("tests/assets/missing.toml"));
+    assert_eq!(check.status, Status::Error);
+    Ok(())
+}
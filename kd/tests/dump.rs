@@ -0,0 +1,63 @@
+use anyhow::Result;
+use kd::dump;
+use std::fs;
+use std::path::Path;
+
+mod common;
+use common::temp_dir;
+
+#[test]
+fn kd_dump_latest() -> Result<()> {
+    let envdir = temp_dir("dump-latest")?;
+    assert!(dump::latest(&envdir).is_err());
+
+    let dumps = dump::dumps_dir(&envdir);
+    for name in ["2026-10-18_10-00-00", "2026-10-19_09-00-00"] {
+        fs::create_dir_all(dumps.join(name))?;
+        fs::write(dumps.join(name).join(dump::VMCORE), "core")?;
+    }
+    // Failed dump, there's no vmcore
+    fs::create_dir_all(dumps.join("2026-10-19_12-00-00"))?;
+    assert_eq!(dump::latest(&envdir)?, dumps.join("2026-10-19_09-00-00"));
+
+    let dir = dump::new_dir(&envdir)?;
+    assert!(dir.is_dir());
+    assert_eq!(dir.parent(), Some(dumps.as_path()));
+
+    fs::remove_dir_all(&envdir)?;
+    Ok(())
+}
+
+#[test]
+fn kd_dump_drgn_args() -> Result<()> {
+    let dir = temp_dir("dump-drgn")?;
+    fs::write(dir.join(dump::VMCORE), "core")?;
+    assert!(dump::drgn_args(&dir, None, &[]).is_err());
+
+    fs::write(dir.join(dump::VMLINUX), "elf")?;
+    let vmcore = dir.join(dump::VMCORE).display().to_string();
+    let vmlinux = dir.join(dump::VMLINUX).display().to_string();
+    assert_eq!(
+        dump::drgn_args(&dir, None, &[])?,
+        vec!["--core", &vmcore, "--symbols", &vmlinux]
+    );
+    assert_eq!(
+        dump::drgn_args(
+            &dir,
+            Some(Path::new("/tmp/hung.py")),
+            &["--pid".to_string(), "100".to_string()]
+        )?,
+        vec![
+            "--core",
+            &vmcore,
+            "--symbols",
+            &vmlinux,
+            "/tmp/hung.py",
+            "--pid",
+            "100"
+        ]
+    );
+
+    fs::remove_dir_all(&dir)?;
+    Ok(())
+}
@@ -0,0 +1,114 @@
+use anyhow::Result;
+use kd::config::XfstestsConfig;
+use kd::hooks;
+use std::fs;
+use std::os::unix::fs::symlink;
+use std::path::Path;
+
+mod common;
+use common::temp_dir;
+
+const HOOK: &str = "hook_execute()\n{\n\ttrue\n}\n";
+
+fn write_hooks(dir: &Path, hooks: &[&str]) -> Result<()> {
+    for hook in hooks {
+        let path = dir.join(hook);
+        fs::create_dir_all(path.parent().unwrap())?;
+        fs::write(path, HOOK)?;
+    }
+    Ok(())
+}
+
+#[test]
+fn kd_hooks_validate() -> Result<()> {
+    let dir = temp_dir("hooks-validate")?;
+    let hooks = dir.join("hooks");
+    assert!(hooks::validate(&hooks).is_err());
+
+    write_hooks(
+        &hooks,
+        &[
+            "start/global.0",
+            "start/xfs-001.0",
+            "start/xfs-001.1",
+            "end/global.0",
+            "end/generic-475.0",
+        ],
+    )?;
+    hooks::validate(&hooks)?;
+
+    // xfstests stops at the first missing number
+    write_hooks(&hooks, &["end/global.2"])?;
+    let error = hooks::validate(&hooks).unwrap_err().to_string();
+    assert!(error.ends_with("end/global.1 is missing, hooks after it never run"));
+    fs::remove_file(hooks.join("end/global.2"))?;
+
+    for invalid in ["start/xfs/001.0", "start/generic-001", "middle/global.0"] {
+        let dir = temp_dir("hooks-invalid")?;
+        write_hooks(&dir, &[invalid])?;
+        assert!(hooks::validate(&dir).is_err(), "{invalid}");
+    }
+    fs::write(hooks.join("start/global.1"), "echo nothing\n")?;
+    assert!(hooks::validate(&hooks).is_err());
+
+    assert!(hooks::validate_builtin(&["dmesg".to_string(), "trace".to_string()]).is_ok());
+    assert!(hooks::validate_builtin(&["coffee".to_string()]).is_err());
+
+    fs::remove_dir_all(&dir)?;
+    fs::remove_dir_all(temp_dir("hooks-invalid")?)?;
+    Ok(())
+}
+
+#[test]
+fn kd_hooks_install() -> Result<()> {
+    let dir = temp_dir("hooks-install")?;
+    let share = dir.join("share");
+    let library = dir.join("library");
+    write_hooks(&library, &["trace-cmd-start"])?;
+    write_hooks(&dir.join("hooks"), &["start/global.0", "end/xfs-001.0"])?;
+    symlink(
+        library.join("trace-cmd-start"),
+        dir.join("hooks/start/xfs-001.0"),
+    )?;
+
+    let mut config = XfstestsConfig {
+        hooks: Some("hooks".to_string()),
+        builtin_hooks: Some(vec![
+            "dmesg".to_string(),
+            "slabinfo".to_string(),
+            "trace".to_string(),
+        ]),
+        ..XfstestsConfig::default()
+    };
+    // 'trace' is [trace] with XFS events, it has no hook files
+    let trace = hooks::trace(Some(&config)).unwrap();
+    assert_eq!(trace.events, ["xfs:*"]);
+    assert!(hooks::install(Some(&config), &dir, &share)?);
+
+    let installed = share.join(hooks::HOOKS);
+    // Links are copied as files, the VM doesn't see their targets
+    assert!(!installed.join("start/xfs-001.0").is_symlink());
+    assert_eq!(fs::read_to_string(installed.join("start/global.0"))?, HOOK);
+    assert!(fs::read_to_string(installed.join("start/global.1"))?.contains("/dev/kmsg"));
+    assert!(fs::read_to_string(installed.join("end/global.1"))?.contains("slabinfo.end"));
+    assert!(installed.join("end/xfs-001.0").exists());
+    assert!(!installed.join("start/global.3").exists());
+    hooks::validate(&installed)?;
+
+    // Made by kd, so it's replaced
+    config.hooks = None;
+    assert!(hooks::install(Some(&config), &dir, &share)?);
+    assert!(!installed.join("start/xfs-001.0").exists());
+    assert!(installed.join("start/global.1").exists());
+    assert!(!hooks::install(None, &dir, &share)?);
+    assert!(!installed.exists());
+
+    // Hooks put there by hand are left alone
+    write_hooks(&installed, &["start/global.0"])?;
+    assert!(!hooks::install(None, &dir, &share)?);
+    assert!(hooks::install(Some(&config), &dir, &share).is_err());
+    assert!(installed.join("start/global.0").exists());
+
+    fs::remove_dir_all(&dir)?;
+    Ok(())
+}
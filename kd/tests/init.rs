@@ -0,0 +1,72 @@
+use anyhow::Result;
+use kd::config::Config;
+use kd::init;
+use std::fs;
+use std::path::Path;
+
+mod common;
+use common::temp_dir;
+
+#[test]
+fn kd_init_presets() -> Result<()> {
+    for preset in [None, Some("xfs"), Some("ext4"), Some("script")] {
+        let content = init::config(preset)?;
+        let config: Config = toml::from_str(&content)?;
+        assert!(config.validate().is_ok());
+    }
+
+    let config: Config = toml::from_str(&init::config(Some("ext4"))?)?;
+    assert_eq!(config.xfstests.unwrap().filesystem.unwrap(), "ext4");
+    assert!(init::config(Some("btrfs")).is_err());
+    Ok(())
+}
+
+#[test]
+fn kd_init_template_ref() -> Result<()> {
+    let curdir = Path::new(env!("CARGO_MANIFEST_DIR"));
+    assert_eq!(init::template_ref(None, curdir)?, init::DEFAULT_TEMPLATE);
+    assert_eq!(
+        init::template_ref(Some("github:user/kd"), curdir)?,
+        "github:user/kd#default"
+    );
+    assert_eq!(
+        init::template_ref(Some("github:user/kd#vm"), curdir)?,
+        "github:user/kd#vm"
+    );
+
+    let template = curdir.join("../templates/vm");
+    let template = std::path::absolute(&template)?;
+    assert_eq!(
+        init::template_ref(Some("../templates/vm"), curdir)?,
+        format!("path:{}#default", template.display())
+    );
+    // Not a flake
+    assert!(init::template_ref(Some("./src"), curdir).is_err());
+    Ok(())
+}
+
+#[test]
+fn kd_init_keeps_user_files() -> Result<()> {
+    let dir = temp_dir("init")?;
+    let template = dir.join("template");
+    let flake = dir.join("flake");
+    fs::create_dir_all(&template)?;
+    fs::create_dir_all(&flake)?;
+
+    fs::write(template.join("flake.nix"), "new flake")?;
+    fs::write(template.join("flake.lock"), "new lock")?;
+    fs::write(flake.join("flake.nix"), "old flake")?;
+    fs::write(flake.join("flake.lock"), "old lock")?;
+    fs::write(flake.join("modules.nix"), "user module")?;
+
+    let kept = init::install_template(&template, &flake)?;
+    assert_eq!(kept, vec!["flake.lock"]);
+    assert_eq!(fs::read_to_string(flake.join("flake.nix"))?, "new flake");
+    assert_eq!(fs::read_to_string(flake.join("flake.lock"))?, "old lock");
+    assert_eq!(fs::read_to_string(flake.join("modules.nix"))?, "user module");
+    assert!(flake.join("uconfig.nix").exists());
+    assert!(flake.join("vmconfig.nix").exists());
+
+    fs::remove_dir_all(&dir)?;
+    Ok(())
+}
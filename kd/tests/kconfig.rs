@@ -0,0 +1,164 @@
+use anyhow::Result;
+use kd::config::Config;
+use kd::{generate_uconfig, kconfig, State};
+
+mod common;
+use common::temp_dir;
+
+#[test]
+fn kd_kconfig_invalid_value() -> Result<()> {
+    let config = Config::load("tests/assets/kconfig-invalid.toml")?;
+    let error = config.validate().unwrap_err();
+    assert!(format!("{error:#}").contains("CONFIG_FS_VERITY"));
+
+    assert!(kconfig::parse_value("yes").is_ok());
+    assert!(kconfig::parse_value("freeform \"64\"").is_ok());
+    assert!(kconfig::parse_value("freeform").is_err());
+    assert!(kconfig::parse_value("m").is_err());
+    Ok(())
+}
+
+#[test]
+fn kd_kconfig_uconfig() -> Result<()> {
+    let mut state = State {
+        config: Config::load("tests/assets/config.toml")?,
+        offline: true,
+        ..State::default()
+    };
+    state.config.xfstests = None;
+    state.config.xfsprogs = None;
+    if let Some(kernel) = state.config.kernel.as_mut() {
+        kernel.rev = None;
+        let config = kernel.config.get_or_insert_with(toml::Table::new);
+        config.insert("CONFIG_NR_CPUS".to_string(), "freeform \"64\"".into());
+        config.insert("CONFIG_64BIT".to_string(), "yes".into());
+    }
+
+    let uconfig = generate_uconfig(&mut state)?;
+    assert!(uconfig.contains("FS_VERITY = yes;"));
+    assert!(uconfig.contains("NR_CPUS = freeform \"64\";"));
+    assert!(uconfig.contains("\"64BIT\" = yes;"));
+    Ok(())
+}
+
+#[test]
+fn kd_kconfig_symbols_and_flavors() -> Result<()> {
+    let symbols = kconfig::parse_symbols("FS_VERITY\nXFS_FS\n\nDRM\n");
+    let flavors = kconfig::parse_flavors(
+        r#"{
+            "default": { "DRM": { "tristate": "n" } },
+            "debug": { "KASAN": { "tristate": "y" } },
+            "image": { "XFS_FS": { "tristate": "m" } }
+        }"#,
+    )?;
+
+    let mut config = toml::Table::new();
+    config.insert("CONFIG_FS_VERITYY".to_string(), "yes".into());
+    config.insert("CONFIG_DRM".to_string(), "yes".into());
+    config.insert("CONFIG_XFS_FS".to_string(), "yes".into());
+    config.insert("CONFIG_KASAN".to_string(), "yes".into());
+    let requested = kconfig::requested(&config)?;
+
+    assert_eq!(
+        kconfig::unknown(&requested, &symbols),
+        vec!["FS_VERITYY".to_string(), "KASAN".to_string()]
+    );
+
+    let conflicts = kconfig::conflicts(&requested, &flavors, &["debug".to_string()]);
+    assert_eq!(conflicts.len(), 1);
+    assert_eq!(conflicts[0].name, "DRM");
+    assert_eq!(conflicts[0].flavor, "default");
+    assert_eq!(conflicts[0].flavor_value, "n");
+
+    let conflicts = kconfig::conflicts(&requested, &flavors, &["image".to_string()]);
+    assert_eq!(conflicts.len(), 2);
+
+    // XFS_FS didn't make it into the final config
+    let final_config = kconfig::parse("CONFIG_DRM=y\nCONFIG_KASAN=y\n# CONFIG_XFS_FS is not set\n");
+    let missing = kconfig::check(&requested, &final_config);
+    let names: Vec<&str> = missing.iter().map(|m| m.name.as_str()).collect();
+    assert_eq!(names, vec!["FS_VERITYY", "XFS_FS"]);
+    Ok(())
+}
+
+#[test]
+fn kd_kconfig_user_flavors() -> Result<()> {
+    let config = Config::load("tests/assets/kconfig-flavors.toml")?;
+    config.validate()?;
+
+    let mut state = State {
+        curdir: std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR")),
+        config,
+        offline: true,
+        name: "small".to_string(),
+        ..State::default()
+    };
+    let uconfig = generate_uconfig(&mut state)?;
+    assert!(uconfig.contains(
+        "flavors = with pkgs.kconfigs; [(with pkgs.lib.kernel; { NR_CPUS = freeform \"8\"; })];"
+    ));
+
+    state.config.named = None;
+    state.config.kernel = state.config.common.as_ref().and_then(|c| c.kernel.clone());
+    state.name = String::new();
+    let uconfig = generate_uconfig(&mut state)?;
+    assert!(uconfig.contains("flavors = with pkgs.kconfigs; [debug (with pkgs.lib.kernel; { XFS_ONLINE_REPAIR = yes;\nXFS_ONLINE_SCRUB = yes; })];"));
+
+    let mut invalid = Config::load("tests/assets/kconfig-flavors.toml")?;
+    if let Some(flavors) = invalid.kconfig_flavors.as_mut() {
+        let mut config = toml::Table::new();
+        config.insert("XFS_FS".to_string(), "yes".into());
+        flavors.insert("broken".to_string(), config);
+    }
+    assert!(invalid.validate().is_err());
+    Ok(())
+}
+
+#[test]
+fn kd_kconfig_diff() -> Result<()> {
+    let old = kconfig::parse("CONFIG_A=y\nCONFIG_B=m\n# CONFIG_C is not set\nCONFIG_D=\"old\"\n");
+    let new = kconfig::parse("CONFIG_B=y\nCONFIG_C=y\nCONFIG_D=\"old\"\nCONFIG_E=64\n");
+
+    let changes: Vec<String> = kconfig::diff(&old, &new)
+        .iter()
+        .map(|change| change.to_string())
+        .collect();
+    assert_eq!(changes, vec!["-A y", " B m -> y", " C n -> y", "+E 64"]);
+    Ok(())
+}
+
+#[test]
+fn kd_kconfig_merge() -> Result<()> {
+    let mut config = toml::Table::new();
+    config.insert("CONFIG_A".to_string(), "no".into());
+    config.insert("CONFIG_C".to_string(), "module".into());
+    config.insert("CONFIG_NR_CPUS".to_string(), "freeform \"64\"".into());
+    config.insert("CONFIG_LOCALVERSION".to_string(), "freeform \"-kd\"".into());
+    let options = kconfig::requested(&config)?;
+
+    let merged = kconfig::merge(
+        "#\n# Linux config\n#\nCONFIG_A=y\nCONFIG_B=m\n# CONFIG_C is not set\n",
+        &options,
+    );
+    assert_eq!(
+        merged,
+        "#\n# Linux config\n#\nCONFIG_B=m\n# CONFIG_A is not set\nCONFIG_C=m\nCONFIG_LOCALVERSION=\"-kd\"\nCONFIG_NR_CPUS=64\n"
+    );
+    assert!(kconfig::check(&options, &kconfig::parse(&merged)).is_empty());
+    Ok(())
+}
+
+#[test]
+fn kd_config_backup() -> Result<()> {
+    let dir = temp_dir("backup")?;
+    let config = dir.join(".config");
+
+    std::fs::write(&config, "first")?;
+    assert_eq!(kd::backup(&config)?, dir.join(".config.1.bup"));
+    std::fs::write(&config, "second")?;
+    assert_eq!(kd::backup(&config)?, dir.join(".config.2.bup"));
+    assert_eq!(std::fs::read_to_string(dir.join(".config.1.bup"))?, "first");
+
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
@@ -0,0 +1,107 @@
+use anyhow::Result;
+use kd::config::{Config, XfstestsConfig};
+use kd::plan::{Plan, PREBUILD_KERNEL_ENV};
+use kd::State;
+use std::path::PathBuf;
+
+mod common;
+use common::temp_dir;
+
+fn state(name: &str) -> Result<State> {
+    // Not validated, prebuild kernel image doesn't exist
+    Ok(State {
+        curdir: PathBuf::from(env!("CARGO_MANIFEST_DIR")),
+        flake_dir: PathBuf::from("/tmp/flake"),
+        config: Config::load("tests/assets/plan.toml")?,
+        name: name.to_string(),
+        offline: true,
+        ..State::default()
+    })
+}
+
+#[test]
+fn kd_plan_prebuild() -> Result<()> {
+    let plan = Plan::new(&state("")?)?;
+
+    assert_eq!(plan.vm_target(), "prebuild");
+    assert_eq!(plan.package(plan.vm_target()), "path:/tmp/flake#prebuild");
+    let kernel = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("arch/x86/boot/bzImage");
+    assert_eq!(plan.prebuild()?, Some(kernel.clone()));
+    assert_eq!(
+        plan.envs.get(PREBUILD_KERNEL_ENV),
+        Some(&kernel.display().to_string())
+    );
+    assert!(plan.vmconfig.contains("cores = 2;"));
+    assert!(plan.uconfig.contains("\"-nographic\""));
+    assert!(plan.uconfig.contains(
+        "services.xfstests.trace = { enable = true;\nevents = [\"xfs:*\" \"block:block_rq_issue\"];\nbufferSize = 65536;\nrerunFailed = true; };"
+    ));
+    let assets = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/assets");
+    assert!(plan.vmconfig.contains(&format!(
+        "virtualisation.sharedDirectories = {{ kd-assets = {{ source = \"{}\"; target = \"/root/assets\"; }}; }};",
+        assets.display()
+    )));
+    assert!(plan
+        .vmconfig
+        .contains("virtualisation.fileSystems = { \"/root/assets\".options = [\"ro\"]; };"));
+    assert!(!plan.vmconfig.contains("systemd.tmpfiles.rules"));
+    assert!(!plan.uconfig.contains("sharedDirectories"));
+
+    // Environment goes to every nix command
+    let cmd = plan.nix("build");
+    assert!(cmd
+        .get_envs()
+        .any(|(key, _)| key == std::ffi::OsStr::new(PREBUILD_KERNEL_ENV)));
+    Ok(())
+}
+
+#[test]
+fn kd_plan_named() -> Result<()> {
+    let plan = Plan::new(&state("big")?)?;
+
+    assert_eq!(plan.name, "big");
+    assert_eq!(plan.vm_target(), "vm");
+    assert!(!plan.envs.contains_key(PREBUILD_KERNEL_ENV));
+    assert_eq!(
+        plan.system.packages,
+        Some(vec!["vim".to_string(), "fio".to_string()])
+    );
+    assert!(plan.vmconfig.contains("cores = 8;"));
+    assert!(plan.uconfig.contains("\"-smp 8\""));
+    assert!(!plan.uconfig.contains("-nographic"));
+    // Top level [trace] is not a default of named runs
+    assert!(!plan.uconfig.contains("services.xfstests.trace"));
+    // Named runs add files to the top level [share]
+    let share = plan.system.share.as_ref().unwrap();
+    assert_eq!(share.keys().collect::<Vec<_>>(), ["assets", "xunit"]);
+    assert!(plan.vmconfig.contains("kd-assets = {"));
+    assert!(!plan.vmconfig.contains("kd-xunit"));
+    assert!(plan.vmconfig.contains(
+        "systemd.tmpfiles.rules = [\"L+ /usr/local/share/xunit.xml - - - - /root/share/files/xunit\"];"
+    ));
+
+    assert!(Plan::new(&state("small")?).is_err());
+    Ok(())
+}
+
+#[test]
+fn kd_plan_unresolved() -> Result<()> {
+    let envdir = temp_dir("plan-unresolved")?;
+    let mut state = state("")?;
+    state.envdir = envdir.clone();
+    state.offline = false;
+    state.config.xfstests = Some(XfstestsConfig {
+        repo: Some("https://example.com/xfstests-dev.git".to_string()),
+        rev: Some("v2026.01.01".to_string()),
+        ..XfstestsConfig::default()
+    });
+
+    // Nothing is fetched or pinned for commands reading results
+    let plan = Plan::unresolved(&state)?;
+    assert!(plan.uconfig.is_empty());
+    assert!(plan.system.xfstests.is_some());
+    assert!(!envdir.join("sources.toml").exists());
+
+    std::fs::remove_dir_all(&envdir)?;
+    Ok(())
+}
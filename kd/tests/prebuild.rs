@@ -0,0 +1,105 @@
+use anyhow::Result;
+use kd::config::{Config, KernelConfig};
+use kd::{generate_uconfig, kconfig, prebuild, State};
+use std::fs;
+use std::path::PathBuf;
+
+mod common;
+use common::temp_dir;
+
+#[test]
+fn kd_kconfig_check() -> Result<()> {
+    let config = kconfig::parse(
+        "CONFIG_XFS_FS=y\n# CONFIG_DRM is not set\nCONFIG_SCSI_DEBUG=y\nCONFIG_EXT4_FS=m\nCONFIG_NR_CPUS=64\n",
+    );
+    assert_eq!(config.get("DRM").map(|v| v.as_str()), Some("n"));
+
+    let flavors = kconfig::parse_flavors(
+        r#"{
+            "default": {
+                "XFS_FS": { "tristate": "y", "optional": false },
+                "DRM": { "tristate": "n", "optional": false },
+                "SOUND": { "tristate": "n", "optional": false },
+                "SCSI_DEBUG": { "tristate": "m", "optional": false }
+            },
+            "debug": {
+                "KASAN": { "tristate": "y", "optional": false },
+                "KGDB": { "tristate": "y", "optional": true }
+            }
+        }"#,
+    )?;
+
+    let expected = kconfig::required(&flavors, &[])?;
+    assert!(kconfig::check(&expected, &config).is_empty());
+
+    let mut expected = kconfig::required(&flavors, &["debug".to_string()])?;
+    let mut requested = toml::Table::new();
+    requested.insert("CONFIG_EXT4_FS".to_string(), "yes".into());
+    requested.insert("CONFIG_NR_CPUS".to_string(), "freeform \"64\"".into());
+    expected.extend(kconfig::requested(&requested)?);
+
+    let mismatches = kconfig::check(&expected, &config);
+    let names: Vec<&str> = mismatches.iter().map(|m| m.name.as_str()).collect();
+    assert_eq!(names, vec!["EXT4_FS", "KASAN"]);
+    assert_eq!(mismatches[0].actual.as_deref(), Some("m"));
+
+    assert!(kconfig::required(&flavors, &["nope".to_string()]).is_err());
+    requested.insert("CONFIG_BTRFS_FS".to_string(), "y".into());
+    assert!(kconfig::requested(&requested).is_err());
+    Ok(())
+}
+
+#[test]
+fn kd_prebuild_modules() -> Result<()> {
+    let dir = temp_dir("prebuild")?;
+    let tree = dir.join("linux");
+    fs::create_dir_all(tree.join("arch/x86/boot"))?;
+    fs::write(tree.join("Makefile"), "")?;
+    fs::write(tree.join("Kconfig"), "")?;
+    assert_eq!(
+        prebuild::tree(&tree.join("arch/x86/boot/bzImage")),
+        Some(tree.clone())
+    );
+    assert_eq!(prebuild::tree(&dir.join("bzImage")), None);
+
+    let staging = dir.join("staging");
+    let share = dir.join("share");
+    assert!(prebuild::stage_modules(&staging, &share).is_err());
+
+    let release = staging.join("lib/modules/7.1.0-rc3");
+    fs::create_dir_all(release.join("kernel/fs/xfs"))?;
+    fs::write(release.join("kernel/fs/xfs/xfs.ko"), "")?;
+    fs::write(release.join("modules.dep"), "")?;
+    std::os::unix::fs::symlink(&tree, release.join("build"))?;
+
+    let releases = prebuild::stage_modules(&staging, &share)?;
+    assert_eq!(releases, vec!["7.1.0-rc3".to_string()]);
+    let target = share.join(prebuild::SHARE_MODULES).join("7.1.0-rc3");
+    assert!(target.join("kernel/fs/xfs/xfs.ko").exists());
+    assert!(target.join("modules.dep").exists());
+    assert!(!target.join("build").exists());
+
+    fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[test]
+fn kd_prebuild_uconfig() -> Result<()> {
+    let mut state = State {
+        curdir: PathBuf::from(env!("CARGO_MANIFEST_DIR")),
+        config: Config {
+            kernel: Some(KernelConfig {
+                prebuild: Some("arch/x86/boot/bzImage".to_string()),
+                modules: Some(".kd/modules".to_string()),
+                ..KernelConfig::default()
+            }),
+            ..Config::default()
+        },
+        ..State::default()
+    };
+
+    let uconfig = generate_uconfig(&mut state)?;
+    assert!(uconfig.contains("kernel.prebuildModules = true;"));
+    assert!(state.envs.contains_key("NIXPKGS_QEMU_KERNEL_kd"));
+    Ok(())
+}
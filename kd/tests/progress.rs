@@ -0,0 +1,208 @@
+use anyhow::Result;
+use kd::progress::{self, Event, Output, Progress};
+use kd::results::Status;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Cursor;
+use std::time::{Duration, Instant};
+
+mod common;
+use common::temp_dir;
+
+const CONSOLE: &str = "\
+SECTION       -- xfs_4k
+FSTYP         -- xfs (debug)
+generic/001 [   12.000000] run fstests generic/001 at 2026-10-19 12:00:01
+ 3s ...  4s
+generic/002       [not run] this test requires a valid $SCRATCH_DEV
+generic/003 [   14.000000] run fstests generic/003 at 2026-10-19 12:00:05
+[   15.000000] WARNING: CPU: 1 PID: 42 at fs/xfs/xfs_inode.c:100 xfs_foo+0x10/0x20
+- output mismatch (see /root/results/xfs_4k/generic/003.out.bad)
+generic/004 5s ...  6s
+Ran: generic/001 generic/002 generic/003 generic/004
+Failed 1 of 4 tests
+";
+
+#[test]
+fn kd_progress_parse() {
+    assert_eq!(
+        progress::parse("SECTION       -- xfs_4k\r\n", None),
+        Some(Event::Section("xfs_4k".to_string()))
+    );
+    assert_eq!(
+        progress::parse("[   12.000000] run fstests xfs/001 at 2026-10-19", None),
+        Some(Event::Start("xfs/001".to_string()))
+    );
+    // Kernel without PRINTK_TIME
+    assert_eq!(
+        progress::parse("generic/002 run fstests generic/002 at 2026-10-19", None),
+        Some(Event::Start("generic/002".to_string()))
+    );
+    assert_eq!(
+        progress::parse(
+            "INFO: task fsstress:100 blocked for more than 120 seconds.",
+            None
+        ),
+        Some(Event::Warning(
+            "INFO: task fsstress:100 blocked for more than 120 seconds.".to_string()
+        ))
+    );
+    assert_eq!(
+        progress::parse("generic/001 3s ...  4s", None),
+        Some(Event::Pass("generic/001".to_string()))
+    );
+    assert_eq!(
+        progress::parse("generic/110 - output mismatch (see 110.out.bad)", None),
+        Some(Event::Fail(
+            "generic/110".to_string(),
+            "output mismatch (see 110.out.bad)".to_string()
+        ))
+    );
+    assert_eq!(
+        progress::parse("xfs/050 [expunged]", None),
+        Some(Event::NotRun("xfs/050".to_string(), "expunged".to_string()))
+    );
+    assert_eq!(
+        progress::parse(" 7s", Some("generic/110")),
+        Some(Event::Pass("generic/110".to_string()))
+    );
+    assert_eq!(
+        progress::parse("Passed all 4 tests", None),
+        Some(Event::Done("Passed all 4 tests".to_string()))
+    );
+
+    // Not a result without a running test, or not a test at all
+    assert_eq!(progress::parse(" 7s", None), None);
+    assert_eq!(progress::parse("", Some("generic/110")), None);
+    assert_eq!(
+        progress::parse("[  OK  ] Started xfstests.service", None),
+        None
+    );
+    assert_eq!(progress::parse("2026/10 backup done", None), None);
+    assert_eq!(
+        progress::parse("[   13.0] XFS (vdb): Mounting V5 Filesystem", None),
+        None
+    );
+}
+
+#[test]
+fn kd_progress_feed() {
+    let now = Instant::now();
+    let history = BTreeMap::from([
+        ("generic/001".to_string(), 4.0),
+        ("generic/002".to_string(), 0.0),
+        ("generic/003".to_string(), 60.0),
+        ("generic/004".to_string(), 6.0),
+    ]);
+    let mut progress = Progress::new(history, now);
+    assert_eq!(progress.eta(now), Some(Duration::from_secs(70)));
+
+    let mut lines = CONSOLE.lines();
+    for line in lines.by_ref().take(5) {
+        progress.feed(line, now);
+    }
+    assert_eq!(progress.section.as_deref(), Some("xfs_4k"));
+    assert_eq!((progress.passed, progress.notrun), (1, 1));
+
+    // generic/003 is running for 10 seconds
+    progress.feed(lines.next().unwrap(), now);
+    let later = now + Duration::from_secs(10);
+    assert_eq!(progress.eta(later), Some(Duration::from_secs(56)));
+    assert!(progress.status(later).starts_with(
+        "xfs_4k | generic/003 0:10 | 1 pass 0 fail 1 notrun | elapsed 0:10 | ETA 0:56"
+    ));
+
+    for line in lines {
+        progress.feed(line, later);
+    }
+    assert_eq!(
+        (progress.passed, progress.failed, progress.notrun),
+        (2, 1, 1)
+    );
+    assert_eq!(progress.warnings.len(), 1);
+    assert!(progress.current.is_none());
+    assert_eq!(progress.eta(later), Some(Duration::ZERO));
+    assert_eq!(Progress::new(BTreeMap::new(), now).eta(now), None);
+
+    // Outcomes are kept for the watchdog, xfstests reports at the end
+    let results: Vec<(&str, Status, f64)> = progress
+        .results
+        .iter()
+        .map(|test| (test.name.as_str(), test.status, test.time))
+        .collect();
+    assert_eq!(
+        results,
+        [
+            ("generic/001", Status::Pass, 0.0),
+            ("generic/002", Status::Skip, 0.0),
+            ("generic/003", Status::Fail, 10.0),
+            ("generic/004", Status::Pass, 0.0),
+        ]
+    );
+    assert_eq!(progress.finished, ["xfs_4k"]);
+
+    // Failed tests run again with [trace], they are counted already
+    assert_eq!(
+        progress.feed("Re-running failed tests with tracing: generic/003", later),
+        Some(Event::Rerun("generic/003".to_string()))
+    );
+    progress.feed("SECTION       -- xfs_4k", later);
+    progress.feed("generic/003 5s ...  5s", later);
+    assert_eq!(
+        (progress.passed, progress.failed, progress.notrun),
+        (2, 1, 1)
+    );
+}
+
+#[test]
+fn kd_progress_follow() -> Result<()> {
+    let progress = Progress::new(BTreeMap::new(), Instant::now());
+    let mut out = vec![];
+    let progress = progress::follow(
+        Cursor::new(CONSOLE),
+        progress,
+        &mut out,
+        Output::Events,
+        None,
+    )?;
+    assert_eq!(progress.failed, 1);
+
+    let out = String::from_utf8(out)?;
+    assert!(out.starts_with("section xfs_4k\ngeneric/001 pass\ngeneric/002 not run"));
+    assert!(out.contains("kernel: WARNING: CPU: 1"));
+    assert!(out.contains("generic/003 FAIL output mismatch"));
+    assert!(out.contains("Failed 1 of 4 tests\n2 passed, 1 failed, 1 not run in 0:00\n"));
+    assert!(!out.contains('\x1b'));
+    Ok(())
+}
+
+#[test]
+fn kd_progress_history() -> Result<()> {
+    let dir = temp_dir("progress-history")?;
+    let results = dir.join("share/results");
+    fs::create_dir_all(results.join("xfs_4k"))?;
+    fs::create_dir_all(results.join("xfs_1k"))?;
+
+    // Nothing to save yet
+    progress::save_history(&results, &dir)?;
+    assert!(progress::load_history(&dir).is_empty());
+
+    fs::write(
+        dir.join(progress::HISTORY),
+        "generic/001 10\ngeneric/002 3\n",
+    )?;
+    fs::write(results.join("xfs_4k/check.time"), "generic/001 4\n")?;
+    fs::write(results.join("xfs_1k/check.time"), "generic/110 7\nbroken\n")?;
+    progress::save_history(&results, &dir)?;
+    assert_eq!(
+        progress::load_history(&dir),
+        BTreeMap::from([
+            ("generic/001".to_string(), 4.0),
+            ("generic/002".to_string(), 3.0),
+            ("generic/110".to_string(), 7.0),
+        ])
+    );
+
+    fs::remove_dir_all(&dir)?;
+    Ok(())
+}
@@ -0,0 +1,124 @@
+use anyhow::Result;
+use kd::qmp::{self, Qmp};
+use serde_json::{json, Value};
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixListener;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::thread;
+
+mod common;
+use common::temp_dir;
+
+/// QEMU pretending QMP server for one connection. Requests go to the channel,
+/// `answer` returns messages to send back.
+fn qmp_server(
+    dir: &Path,
+    answer: fn(&Value, usize) -> Vec<Value>,
+) -> Result<(PathBuf, mpsc::Receiver<Value>)> {
+    let socket = qmp::socket(dir);
+    let listener = UnixListener::bind(&socket)?;
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let Ok((mut stream, _)) = listener.accept() else {
+            return;
+        };
+        let greeting = json!({"QMP": {"version": {}, "capabilities": []}});
+        let _ = writeln!(stream, "{}", greeting);
+        let reader = BufReader::new(stream.try_clone().unwrap());
+        for (index, line) in reader.lines().enumerate() {
+            let Ok(request) = serde_json::from_str::<Value>(&line.unwrap_or_default()) else {
+                return;
+            };
+            let _ = sender.send(request.clone());
+            for message in answer(&request, index) {
+                let _ = writeln!(stream, "{}", message);
+            }
+        }
+    });
+    Ok((socket, receiver))
+}
+
+fn answer(request: &Value, index: usize) -> Vec<Value> {
+    match request["execute"].as_str() {
+        Some("query-status") => vec![
+            json!({"event": "STOP", "timestamp": {}}),
+            json!({"return": {"running": false, "status": "guest-panicked"}}),
+        ],
+        // Still writing on the first poll
+        Some("query-dump") if index < 5 => {
+            vec![json!({"return": {"status": "active", "completed": 10, "total": 4096}})]
+        }
+        Some("query-dump") => {
+            vec![json!({"return": {"status": "completed", "completed": 4096, "total": 4096}})]
+        }
+        Some("quit") => vec![json!({"error": {"class": "GenericError", "desc": "nope"}})],
+        _ => vec![json!({"return": {}})],
+    }
+}
+
+#[test]
+fn kd_qmp_commands() -> Result<()> {
+    let dir = temp_dir("qmp-commands")?;
+    let (socket, requests) = qmp_server(&dir, answer)?;
+    let mut qmp = Qmp::connect(&socket)?;
+
+    // Events are skipped
+    assert_eq!(qmp.status()?, "guest-panicked");
+    qmp.sysrq('w')?;
+    assert!(qmp.sysrq('W').is_err());
+    assert!(qmp.sysrq('/').is_err());
+    let vmcore = dir.join("vmcore");
+    assert_eq!(qmp.dump(&vmcore)?, 4096);
+    qmp.powerdown()?;
+    qmp.reset()?;
+    let error = qmp.quit().unwrap_err();
+    assert_eq!(format!("{:#}", error), "QMP command 'quit' failed: nope");
+
+    let requests: Vec<Value> = requests.try_iter().collect();
+    let commands: Vec<&str> = requests
+        .iter()
+        .map(|request| request["execute"].as_str().unwrap_or_default())
+        .collect();
+    assert_eq!(
+        commands,
+        vec![
+            "qmp_capabilities",
+            "query-status",
+            "send-key",
+            "dump-guest-memory",
+            "query-dump",
+            "query-dump",
+            "system_powerdown",
+            "system_reset",
+            "quit",
+        ]
+    );
+    assert_eq!(
+        requests[2]["arguments"]["keys"],
+        json!([
+            {"type": "qcode", "data": "alt"},
+            {"type": "qcode", "data": "sysrq"},
+            {"type": "qcode", "data": "w"},
+        ])
+    );
+    assert_eq!(
+        requests[3]["arguments"]["protocol"],
+        format!("file:{}", vmcore.display())
+    );
+    assert_eq!(requests[3]["arguments"]["detach"], true);
+
+    fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[test]
+fn kd_qmp_not_running() -> Result<()> {
+    let dir = temp_dir("qmp-not-running")?;
+    let error = Qmp::connect(&qmp::socket(&dir)).err().unwrap();
+    assert!(format!("{:#}", error).contains("is VM running?"));
+
+    fs::remove_dir_all(&dir)?;
+    Ok(())
+}
@@ -0,0 +1,179 @@
+use anyhow::Result;
+use kd::config::{Config, DirUpload, KernelConfig, ResultsConfig};
+use kd::kconfig;
+use kd::plan::Plan;
+use kd::report::{self, Report};
+use kd::results;
+use kd::upload::{self, RunInfo};
+use kd::State;
+use std::fs;
+use std::path::Path;
+
+mod common;
+use common::temp_dir;
+
+const XUNIT: &str = include_str!("assets/xunit.xml");
+
+const CONSOLE: &str = "\
+[    0.000000] Linux version 7.0.0-rc3 (nixbld@localhost) #1 SMP
+[   12.000000] run fstests generic/110 at 2026-10-19 12:00:05
+[   13.000000] ------------[ cut here ]------------
+[   13.000001] WARNING: CPU: 1 PID: 42 at fs/xfs/xfs_inode.c:100 xfs_foo+0x10/0x20
+[   13.000002] Call Trace:
+[   13.000003]  xfs_bar+0x1/0x2
+[   13.000004] ---[ end trace 0000000000000000 ]---
+[   14.000000] XFS (vdb): Unmounting Filesystem
+[   20.000000] INFO: task fsstress:100 blocked for more than 120 seconds.
+";
+
+fn run() -> RunInfo {
+    RunInfo {
+        host: "builder".to_string(),
+        kernel: "7.0.0-rc3".to_string(),
+        variant: "default".to_string(),
+        date: "2026-10-19_12-00".to_string(),
+    }
+}
+
+#[test]
+fn kd_report_splats() {
+    let splats = report::splats(CONSOLE);
+    assert_eq!(splats.len(), 2);
+    assert!(splats[0].contains("WARNING: CPU: 1"));
+    assert!(splats[0].ends_with("---[ end trace 0000000000000000 ]---"));
+    assert!(splats[1].starts_with("[   20.000000] INFO: task fsstress"));
+    assert!(report::splats("[    1.0] XFS (vdb): Mounting V5 Filesystem\n").is_empty());
+}
+
+#[test]
+fn kd_report_run() -> Result<()> {
+    let dir = temp_dir("report-run")?;
+    let share = dir.join("share");
+    fs::create_dir_all(share.join("results/xfs_4k"))?;
+    fs::write(share.join("results/xfs_4k/result.xml"), XUNIT)?;
+    fs::write(share.join("execution_2026-10-19_12-00.log"), CONSOLE)?;
+    // The run was made with --name fix
+    results::save_variant(&share.join("results"), "fix")?;
+
+    let mut config = toml::Table::new();
+    config.insert("CONFIG_XFS_DEBUG".to_string(), "yes".into());
+    config.insert("CONFIG_KASAN".to_string(), "no".into());
+    config.insert("CONFIG_FS_VERITY".to_string(), "yes".into());
+    // Built-in flavors come from the kd flake
+    let flavors = kconfig::parse_flavors(
+        r#"{
+            "default": { "XFS_DEBUG": { "tristate": "y" } },
+            "kasan": { "KASAN": { "tristate": "y" } }
+        }"#,
+    )?;
+    let state = State {
+        curdir: dir.clone(),
+        envdir: dir.clone(),
+        config: Config {
+            kernel: Some(KernelConfig {
+                rev: Some("v7.0-rc3".to_string()),
+                flavors: Some(vec!["kasan".to_string()]),
+                config: Some(config),
+                ..KernelConfig::default()
+            }),
+            ..Config::default()
+        },
+        offline: true,
+        ..State::default()
+    };
+    let plan = Plan::new(&state)?;
+
+    let changes: Vec<String> = report::kconfig_changes(&plan, &flavors)?
+        .iter()
+        .map(|change| change.to_string())
+        .collect();
+    assert_eq!(changes, vec!["+FS_VERITY y", " KASAN y -> n"]);
+
+    let run = Report::new(&plan, &flavors)?;
+    assert_eq!(run.title, "fix 7.0.0-rc3 2026-10-19_12-00");
+    assert_eq!(run.splats.len(), 2);
+    assert!(run
+        .summary
+        .contains(&("Kconfig flavors".to_string(), "default, kasan".to_string())));
+
+    let html = report::html(&run);
+    assert!(html.contains("<td>fix/xfs_4k</td>"));
+    assert!(html.contains("+md5sum mismatch"));
+    assert!(html.contains("<svg"));
+    assert!(html.contains("WARNING: CPU: 1"));
+
+    let out = dir.join("out");
+    assert_eq!(report::write(&out, &[run])?, out.join("index.html"));
+    assert!(!out.join("run-1.html").exists());
+
+    fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[test]
+fn kd_report_matrix() -> Result<()> {
+    let dir = temp_dir("report-matrix")?;
+    let mut reports = vec![];
+    for (run, xunit) in [
+        ("first", XUNIT.to_string()),
+        (
+            "second",
+            XUNIT.replace(
+                "<failure message=\"output mismatch",
+                "<skipped message=\"output mismatch",
+            ),
+        ),
+    ] {
+        fs::create_dir_all(dir.join(run).join("xfs_4k"))?;
+        fs::write(dir.join(run).join("xfs_4k/result.xml"), xunit)?;
+        reports.push(Report::from_dir(&dir.join(run))?);
+    }
+    assert!(reports[0].splats.is_empty());
+    assert_eq!(reports[1].count(kd::results::Status::Fail), 0);
+
+    let out = dir.join("out");
+    let index = report::write(&out, &reports)?;
+    assert!(out.join("run-1.html").exists());
+    assert!(out.join("run-2.html").exists());
+    let matrix = fs::read_to_string(index)?;
+    assert!(matrix.contains("<a href=\"run-2.html\">"));
+    assert!(matrix.contains(
+        "<tr><td>xfs_4k generic/110</td><td class=\"fail\">fail</td><td class=\"skip\">skip</td></tr>"
+    ));
+
+    assert!(report::write(&out, &[]).is_err());
+    fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[test]
+fn kd_report_uploaded() -> Result<()> {
+    let dir = temp_dir("report-uploaded")?;
+    // The console log goes with the results, it describes the run
+    let share = dir.join("share");
+    fs::create_dir_all(share.join("results/xfs_4k"))?;
+    fs::write(share.join("results/xfs_4k/result.xml"), XUNIT)?;
+    results::save_variant(&share.join("results"), "fix")?;
+    let log = share.join("execution_2026-10-19_12-00.log");
+    fs::write(&log, CONSOLE)?;
+    let config = ResultsConfig {
+        dir: Some(DirUpload {
+            path: dir.join("archive").display().to_string(),
+            tarball: None,
+        }),
+        ..ResultsConfig::default()
+    };
+    let uploaded = upload::upload(&config, &run(), &share.join("results"), Some(&log), &dir)?;
+    let uploaded = Report::from_dir(Path::new(&uploaded[0]))?;
+    assert_eq!(uploaded.suites[0].name, "fix/xfs_4k");
+    assert_eq!(uploaded.splats.len(), 2);
+    assert!(uploaded
+        .summary
+        .contains(&("Kernel".to_string(), "7.0.0-rc3".to_string())));
+    assert!(uploaded
+        .summary
+        .contains(&("Date".to_string(), "2026-10-19_12-00".to_string())));
+
+    fs::remove_dir_all(&dir)?;
+    Ok(())
+}
@@ -0,0 +1,175 @@
+use anyhow::Result;
+use kd::results::{self, Format, Status, Suite, TestCase};
+use std::fs;
+
+mod common;
+use common::temp_dir;
+
+const XUNIT: &str = include_str!("assets/xunit.xml");
+
+#[test]
+fn kd_results_parse() -> Result<()> {
+    let dir = temp_dir("results-parse")?;
+    fs::create_dir_all(dir.join("generic"))?;
+    fs::write(
+        dir.join("generic/110.dmesg"),
+        "XFS (vdb): Corruption detected\n",
+    )?;
+
+    let suite = results::parse_xunit(XUNIT, "default/xfs_4k", &dir)?;
+    assert_eq!(suite.tests.len(), 3);
+    assert_eq!(suite.count(Status::Pass), 1);
+    assert_eq!(suite.count(Status::Skip), 1);
+    assert_eq!(suite.count(Status::Fail), 1);
+    assert_eq!(suite.time(), 12.0);
+
+    let failed = &suite.tests[2];
+    assert_eq!(failed.name, "generic/110");
+    assert!(failed.diff.as_ref().unwrap().contains("+md5sum mismatch"));
+    assert_eq!(
+        failed.dmesg.as_deref(),
+        Some("XFS (vdb): Corruption detected")
+    );
+
+    assert!(results::parse_xunit("<testsuites/>", "x", &dir).is_err());
+    assert!(results::parse_xunit("not xml", "x", &dir).is_err());
+
+    fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[test]
+fn kd_results_formats() -> Result<()> {
+    let dir = temp_dir("results-formats")?;
+    for section in ["xfs_4k", "xfs_1k"] {
+        fs::create_dir_all(dir.join(section))?;
+        fs::write(
+            dir.join(section).join("result.xml"),
+            XUNIT.replace("xfs_4k", section),
+        )?;
+    }
+    // Without xunit report in system-err the diff comes from .out.bad
+    fs::create_dir_all(dir.join("xfs_1k/generic"))?;
+    fs::write(
+        dir.join("xfs_1k/result.xml"),
+        XUNIT
+            .replace("<system-err>", "<system-out>")
+            .replace("</system-err>", "</system-out>"),
+    )?;
+    fs::write(dir.join("xfs_1k/generic/110.out.bad"), "bad <output>\n")?;
+
+    results::save_variant(&dir, "default")?;
+    assert_eq!(results::variant(&dir).as_deref(), Some("default"));
+    let suites = results::load(&dir)?;
+    let names: Vec<&str> = suites.iter().map(|suite| suite.name.as_str()).collect();
+    assert_eq!(names, vec!["default/xfs_1k", "default/xfs_4k"]);
+    assert_eq!(suites[0].tests[2].diff.as_deref(), Some("bad <output>"));
+
+    let junit = results::render(&suites, Format::Junit)?;
+    assert!(junit.contains(r#"<testsuites name="kd" tests="6" failures="2" skipped="2""#));
+    assert!(junit.contains(r#"<testsuite name="default/xfs_4k""#));
+    assert!(junit.contains("bad &lt;output&gt;"));
+    assert!(junit.contains("+md5sum mismatch"));
+
+    let json: serde_json::Value = serde_json::from_str(&results::render(&suites, Format::Json)?)?;
+    assert_eq!(json[1]["tests"][2]["status"], "fail");
+
+    let tap = results::render(&suites, Format::Tap)?;
+    assert!(tap.starts_with("TAP version 13\n1..6\n"));
+    assert!(tap.contains("ok 2 - default/xfs_1k generic/002 # SKIP"));
+    assert!(tap.contains("not ok 3 - default/xfs_1k generic/110\n  ---\n"));
+
+    let markdown = results::render(&suites, Format::Markdown)?;
+    assert!(markdown.contains("| default/xfs_4k | 3 | 1 | 1 | 12s |"));
+    assert!(markdown.contains("### default/xfs_4k generic/110"));
+
+    assert!(Format::parse("html").is_err());
+    assert!(results::load(&dir.join("nope")).is_err());
+    fs::remove_dir_all(dir.join("xfs_1k"))?;
+    fs::remove_dir_all(dir.join("xfs_4k"))?;
+    assert!(results::load(&dir).is_err());
+
+    fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[test]
+fn kd_results_hang() -> Result<()> {
+    let dir = temp_dir("results-hang")?;
+    fs::create_dir_all(dir.join("xfs_4k/generic"))?;
+    fs::write(dir.join("xfs_4k/result.xml"), XUNIT)?;
+    fs::write(
+        dir.join("xfs_4k/generic/001.hang"),
+        "generic/001 hung\ntask:fsstress state:D\n",
+    )?;
+    // VM was reset before xfstests wrote the report
+    fs::create_dir_all(dir.join("xfs_1k/xfs"))?;
+    fs::write(dir.join("xfs_1k/xfs/475.hang"), "xfs/475 hung\n")?;
+
+    let suites = results::load(&dir)?;
+    assert_eq!(suites[0].name, "xfs_1k");
+    assert_eq!(suites[0].tests.len(), 1);
+    assert_eq!(suites[0].tests[0].name, "xfs/475");
+    assert_eq!(suites[0].tests[0].status, Status::Fail);
+
+    let hung = &suites[1].tests[0];
+    assert_eq!(hung.name, "generic/001");
+    assert_eq!(hung.status, Status::Fail);
+    assert!(hung.message.as_deref().unwrap().starts_with("hung"));
+    assert!(hung.dmesg.as_deref().unwrap().contains("state:D"));
+    assert_eq!(suites[1].tests.len(), 3);
+
+    fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[test]
+fn kd_results_reset() -> Result<()> {
+    let dir = temp_dir("results-reset")?;
+    let section = dir.join("xfs_4k");
+    fs::create_dir_all(&section)?;
+
+    // Tests which ran before the VM reset, saved by the watchdog
+    let case = |name: &str, status, message: Option<&str>| TestCase {
+        name: name.to_string(),
+        time: 1.0,
+        status,
+        message: message.map(str::to_string),
+        diff: None,
+        dmesg: None,
+    };
+    let before = Suite {
+        name: "xfs_4k".to_string(),
+        tests: vec![
+            case("generic/100", Status::Pass, None),
+            case("generic/001", Status::Fail, Some("output mismatch")),
+        ],
+    };
+    fs::write(results::reset_report(&section, 1), results::xunit(&before))?;
+    let parsed = results::parse_xunit(&results::xunit(&before), "xfs_4k", &section)?;
+    assert_eq!(parsed.tests.len(), 2);
+    assert_eq!(parsed.tests[1].status, Status::Fail);
+    assert_eq!(parsed.tests[1].message.as_deref(), Some("output mismatch"));
+
+    // The report xfstests wrote after the reboot wins
+    fs::write(section.join("result.xml"), XUNIT)?;
+    let suites = results::load(&dir)?;
+    assert_eq!(suites.len(), 1);
+    let tests: Vec<(&str, Status)> = suites[0]
+        .tests
+        .iter()
+        .map(|test| (test.name.as_str(), test.status))
+        .collect();
+    assert_eq!(
+        tests,
+        [
+            ("generic/100", Status::Pass),
+            ("generic/001", Status::Pass),
+            ("generic/002", Status::Skip),
+            ("generic/110", Status::Fail),
+        ]
+    );
+
+    fs::remove_dir_all(&dir)?;
+    Ok(())
+}
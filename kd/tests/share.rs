@@ -0,0 +1,121 @@
+use anyhow::Result;
+use kd::config::{ScriptConfig, ShareEntry, SystemConfig};
+use kd::share;
+use std::collections::BTreeMap;
+use std::fs;
+
+mod common;
+use common::temp_dir;
+
+fn entry(source: &str, target: Option<&str>, readonly: Option<bool>) -> ShareEntry {
+    ShareEntry {
+        source: source.to_string(),
+        target: target.map(str::to_string),
+        readonly,
+    }
+}
+
+#[test]
+fn kd_share_validate() -> Result<()> {
+    let dir = temp_dir("share-validate")?;
+    fs::create_dir_all(dir.join("build"))?;
+    fs::write(dir.join("xfs_repair"), "binary")?;
+
+    let entries = BTreeMap::from([
+        ("build".to_string(), entry("build", None, None)),
+        (
+            "xfs_repair".to_string(),
+            entry("xfs_repair", Some("/usr/local/bin/xfs_repair"), Some(false)),
+        ),
+    ]);
+    share::validate(&entries, &dir)?;
+
+    let invalid = [
+        ("missing", entry("missing", None, None)),
+        ("bad name", entry("build", Some("/root/other"), None)),
+        ("relative", entry("build", Some("root/build"), None)),
+        ("root", entry("build", Some("/"), None)),
+        ("results", entry("build", Some("/root/share/results"), None)),
+        ("store", entry("build", Some("/nix/store/build"), None)),
+        ("comma", entry("build", Some("/root/a,b"), None)),
+        ("twice", entry("build", Some("/root/build"), None)),
+        (
+            "very-long-name-of-the-share-entry",
+            entry("build", Some("/root/long"), None),
+        ),
+    ];
+    for (name, invalid) in invalid {
+        let mut entries = entries.clone();
+        entries.insert(name.to_string(), invalid);
+        assert!(share::validate(&entries, &dir).is_err(), "{name}");
+    }
+    Ok(())
+}
+
+#[test]
+fn kd_share_prepare() -> Result<()> {
+    let dir = temp_dir("share-prepare")?;
+    let share = share::dir(&dir.join(".kd"));
+    fs::create_dir_all(dir.join("build"))?;
+    fs::write(dir.join("test.sh"), "echo test\n")?;
+    fs::write(dir.join("xfs_repair"), "patched")?;
+    fs::write(dir.join("data"), "data")?;
+
+    // Left by the previous run
+    fs::create_dir_all(share.join("results/xfs"))?;
+    fs::create_dir_all(share.join("files"))?;
+    fs::write(share.join("files/old"), "old")?;
+
+    let system = SystemConfig {
+        script: Some(ScriptConfig {
+            script: "test.sh".to_string(),
+        }),
+        share: Some(BTreeMap::from([
+            ("build".to_string(), entry("build", None, None)),
+            ("data".to_string(), entry("data", None, None)),
+            (
+                "xfs_repair".to_string(),
+                entry("xfs_repair", Some("/usr/local/bin/xfs_repair"), Some(false)),
+            ),
+        ])),
+        ..SystemConfig::default()
+    };
+    share::prepare(&system, &dir, &share)?;
+
+    assert!(share.join(share::RESULTS).is_dir());
+    assert!(!share.join("results/xfs").exists());
+    assert_eq!(
+        fs::read_to_string(share.join(share::SCRIPT))?,
+        "echo test\n"
+    );
+    let config: SystemConfig = toml::from_str(&fs::read_to_string(share.join(share::KD_TOML))?)?;
+    assert_eq!(config.script.unwrap().script, "test.sh");
+
+    // Directories are mounted, only files are copied
+    let files = share.join(share::FILES);
+    let mut copied: Vec<String> = fs::read_dir(&files)?
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+        .collect();
+    copied.sort();
+    assert_eq!(copied, ["data", "xfs_repair"]);
+    assert!(fs::metadata(files.join("data"))?.permissions().readonly());
+    assert!(!fs::metadata(files.join("xfs_repair"))?
+        .permissions()
+        .readonly());
+
+    // Read-write files changed in the VM go back to the host
+    assert!(share::sync(&system, &dir, &share)?.is_empty());
+    fs::write(files.join("xfs_repair"), "rebuilt")?;
+    assert_eq!(
+        share::sync(&system, &dir, &share)?,
+        vec![dir.join("xfs_repair")]
+    );
+    assert_eq!(fs::read_to_string(dir.join("xfs_repair"))?, "rebuilt");
+
+    // Next run starts over
+    let system = SystemConfig::default();
+    share::prepare(&system, &dir, &share)?;
+    assert!(!files.exists());
+    assert!(!share.join(share::SCRIPT).exists());
+    Ok(())
+}
@@ -0,0 +1,154 @@
+use anyhow::Result;
+use kd::config::{Config, KernelConfig, XfsprogsConfig};
+use kd::sources::Sources;
+use kd::{generate_uconfig, kernel_version, State};
+use std::fs;
+use std::path::PathBuf;
+
+mod common;
+use common::temp_dir;
+
+fn curdir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+}
+
+#[test]
+fn kd_sources_local() -> Result<()> {
+    let mut sources = Sources::new(true, curdir(), None)?;
+    let repo = format!("file://{}", curdir().join("..").display());
+
+    let src = sources.fetch(&repo, "ca58485b0b9566d5bfa3cfe1d88fdee5b78e1516")?;
+    assert!(src.starts_with("builtins.fetchGit {"));
+    assert!(src.contains("rev = \"ca58485b0b9566d5bfa3cfe1d88fdee5b78e1516\";"));
+    assert!(!sources.impure);
+
+    let src = sources.fetch("..", "dirty")?;
+    assert!(!src.contains("rev = \"dirty\";\n"));
+    assert!(src.contains("src.rev or \"dirty\""));
+    assert!(sources.impure);
+
+    assert!(sources.fetch("../does-not-exist", "dirty").is_err());
+    assert!(sources.fetch("git@github.com:torvalds/linux.git", "dirty").is_err());
+    Ok(())
+}
+
+#[test]
+fn kd_sources_offline_pins() -> Result<()> {
+    let dir = temp_dir("sources")?;
+    let pins = dir.join("sources.toml");
+    // The directory stands for the store path of the fetched source
+    fs::write(
+        &pins,
+        format!(
+            "[\"git@github.com:alberand/linux.git#v7.0\"]\nsrc = \"builtins.fetchGit {{ }}\"\npath = \"{}\"\n\n\
+             [\"git@github.com:alberand/linux.git#v6.9\"]\nsrc = \"builtins.fetchGit {{ }}\"\npath = \"/nix/store/gone-source\"\n",
+            dir.display()
+        ),
+    )?;
+
+    let mut sources = Sources::new(true, curdir(), Some(pins.clone()))?;
+    assert_eq!(
+        sources.fetch("git@github.com:alberand/linux.git", "v7.0")?,
+        "builtins.fetchGit { }"
+    );
+    assert!(sources
+        .fetch("git@github.com:alberand/linux.git", "v7.1")
+        .is_err());
+    // Garbage collected
+    let err = sources
+        .fetch("git@github.com:alberand/linux.git", "v6.9")
+        .unwrap_err();
+    assert!(err.to_string().contains("not in the Nix store"));
+
+    // Online the pins are used as well, nothing is fetched
+    let mut sources = Sources::new(false, curdir(), Some(pins.clone()))?;
+    for rev in ["v7.0", "v6.9"] {
+        assert_eq!(
+            sources.fetch("git@github.com:alberand/linux.git", rev)?,
+            "builtins.fetchGit { }"
+        );
+    }
+
+    // Pins of older kd have no store path
+    fs::write(
+        &pins,
+        "\"git@github.com:alberand/linux.git#v7.0\" = \"builtins.fetchGit { }\"\n",
+    )?;
+    let mut sources = Sources::new(true, curdir(), Some(pins))?;
+    assert!(sources
+        .fetch("git@github.com:alberand/linux.git", "v7.0")
+        .is_err());
+
+    fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[test]
+fn kd_sources_path() -> Result<()> {
+    let mut state = State {
+        curdir: curdir(),
+        offline: true,
+        config: Config {
+            xfsprogs: Some(XfsprogsConfig {
+                path: Some("src".to_string()),
+                ..XfsprogsConfig::default()
+            }),
+            ..Config::default()
+        },
+        ..State::default()
+    };
+
+    let nix_config = generate_uconfig(&mut state)?;
+    assert!(nix_config.contains("outPath = builtins.path { path = /. + "));
+    assert!(nix_config.contains("rev = \"dirty\";"));
+    assert!(state.args.contains(&"--impure".to_string()));
+    Ok(())
+}
+
+#[test]
+fn kd_sources_kernel_worktree() -> Result<()> {
+    let dir = temp_dir("sources-kernel")?;
+    fs::write(
+        dir.join("Makefile"),
+        "# SPDX-License-Identifier: GPL-2.0\nVERSION = 7\nPATCHLEVEL = 1\nSUBLEVEL = 0\nEXTRAVERSION = -rc3\nNAME = Baby Opossum Posse\n",
+    )?;
+    assert_eq!(kernel_version(&dir)?, "v7.1-rc3");
+    // Left by an in-tree build, 'make O=' refuses such tree
+    fs::create_dir_all(dir.join("include/config"))?;
+    fs::create_dir_all(dir.join("include/linux"))?;
+    fs::create_dir_all(dir.join("arch/x86/include/generated"))?;
+    fs::create_dir_all(dir.join("arch/arm64/include/asm"))?;
+
+    let mut kconfig = toml::Table::new();
+    kconfig.insert("CONFIG_FS_VERITY".to_string(), "yes".into());
+    let mut state = State {
+        curdir: dir.clone(),
+        config: Config {
+            kernel: Some(KernelConfig {
+                source: Some(".".to_string()),
+                flavors: Some(vec!["debug".to_string()]),
+                config: Some(kconfig),
+                ..KernelConfig::default()
+            }),
+            ..Config::default()
+        },
+        ..State::default()
+    };
+
+    let nix_config = generate_uconfig(&mut state)?;
+    assert!(nix_config.contains("version = \"v7.1-rc3\";"));
+    assert!(nix_config.contains(&format!("path = /. + \"{}\";", dir.display())));
+    assert!(nix_config.contains("\"vmlinux\""));
+    assert!(nix_config.contains("\".o\""));
+    assert!(nix_config.contains(
+        "!(builtins.elem relative [\"include/config\" \"arch/x86/include/generated\"])"
+    ));
+    assert!(!nix_config.contains("include/linux"));
+    assert!(!nix_config.contains("arch/arm64"));
+    assert!(nix_config.contains("flavors = with pkgs.kconfigs; [debug];"));
+    assert!(nix_config.contains("FS_VERITY = yes;"));
+    assert!(state.args.contains(&"--impure".to_string()));
+
+    fs::remove_dir_all(&dir)?;
+    Ok(())
+}
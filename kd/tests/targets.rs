@@ -0,0 +1,59 @@
+use anyhow::Result;
+use kd::targets;
+use std::fs;
+
+mod common;
+use common::temp_dir;
+
+#[test]
+fn kd_targets_parse() -> Result<()> {
+    let available = targets::parse(
+        r#"{
+            "devShells": { "x86_64-linux": { "default": { "type": "derivation" } } },
+            "packages": {
+                "x86_64-linux": {
+                    "vm": { "name": "run-kd-vm", "type": "derivation" },
+                    "image": { "name": "image", "type": "derivation" },
+                    "kernel": { "name": "linux-7.0", "type": "derivation" }
+                }
+            }
+        }"#,
+    )?;
+    assert_eq!(available, vec!["image", "kernel", "vm"]);
+
+    targets::validate("kernel", &available)?;
+    let err = targets::validate("kernal", &available).unwrap_err();
+    assert!(err.to_string().contains("image, kernel, vm"));
+
+    assert!(targets::describe("qcow2").is_some());
+    assert!(targets::describe("kernal").is_none());
+    assert!(targets::parse("not json").is_err());
+    Ok(())
+}
+
+#[test]
+fn kd_targets_artifacts() -> Result<()> {
+    let dir = temp_dir("targets")?;
+    fs::create_dir_all(dir.join("out/lib"))?;
+    fs::write(dir.join("out/bzImage"), vec![0u8; 1000])?;
+    fs::write(dir.join("out/lib/module.ko"), vec![0u8; 24])?;
+    std::os::unix::fs::symlink("/nix/store", dir.join("out/link"))?;
+    fs::write(dir.join("image.raw"), vec![0u8; 10])?;
+
+    let output = format!(
+        "{}\n{}\n\n",
+        dir.join("out").display(),
+        dir.join("image.raw").display()
+    );
+    let artifacts = targets::artifacts(&output)?;
+    assert_eq!(artifacts.len(), 2);
+    assert_eq!(artifacts[0].path, dir.join("out"));
+    // Link itself is counted, not what it points to
+    let link = fs::symlink_metadata(dir.join("out/link"))?.len();
+    assert_eq!(artifacts[0].size, 1024 + link);
+    assert_eq!(artifacts[1].size, 10);
+
+    assert!(targets::artifacts(&dir.join("missing").display().to_string()).is_err());
+    fs::remove_dir_all(&dir)?;
+    Ok(())
+}
@@ -0,0 +1,254 @@
+use anyhow::Result;
+use kd::config::{Config, Credentials, DirUpload, GitUpload, ResultsConfig};
+use kd::plan::Plan;
+use kd::upload::{self, RunInfo};
+use kd::State;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+mod common;
+use common::temp_dir;
+
+fn results(dir: &Path) -> Result<PathBuf> {
+    let results = dir.join("share/results");
+    fs::create_dir_all(results.join("xfs_4k/generic"))?;
+    fs::write(results.join("xfs_4k/check.log"), "generic/001 1s\n")?;
+    fs::write(results.join("xfs_4k/generic/110.out.bad"), "bad\n")?;
+    std::os::unix::fs::symlink(
+        "/nonexistent/110.out",
+        results.join("xfs_4k/generic/110.out"),
+    )?;
+    Ok(results)
+}
+
+fn run() -> RunInfo {
+    RunInfo {
+        host: "builder".to_string(),
+        kernel: "7.0.0-rc3".to_string(),
+        variant: "default".to_string(),
+        date: "2026-10-19_12-00".to_string(),
+    }
+}
+
+fn git(dir: &Path, args: &[&str]) -> Result<String> {
+    let output = Command::new("git").arg("-C").arg(dir).args(args).output()?;
+    assert!(output.status.success(), "git {:?} failed", args);
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+#[test]
+fn kd_upload_layout() -> Result<()> {
+    assert_eq!(
+        upload::render(upload::DEFAULT_LAYOUT, &run())?,
+        "builder/7.0.0-rc3/default/2026-10-19_12-00"
+    );
+
+    let mut run = run();
+    run.kernel = "7.0 dirty/1".to_string();
+    run.host = String::new();
+    assert_eq!(
+        upload::render("xfstests/<kernel>/<host>", &run)?,
+        "xfstests/7.0-dirty-1/unknown"
+    );
+
+    assert!(upload::validate_layout("<host>/<arch>").is_err());
+    assert!(upload::validate_layout("<host>/<date").is_err());
+    assert!(upload::validate_layout("/srv/<host>").is_err());
+    assert!(upload::validate_layout("../<host>").is_err());
+
+    let mut config = Config {
+        results: Some(ResultsConfig {
+            layout: Some("<nope>".to_string()),
+            ..ResultsConfig::default()
+        }),
+        ..Config::default()
+    };
+    assert!(config.validate().is_err());
+    config.results = Some(ResultsConfig {
+        upload: Some(true),
+        ..ResultsConfig::default()
+    });
+    assert!(config.validate().is_err());
+    Ok(())
+}
+
+#[test]
+fn kd_upload_run_info() -> Result<()> {
+    let dir = temp_dir("upload-run")?;
+    let share = dir.join("share");
+    fs::create_dir_all(&share)?;
+    assert_eq!(upload::latest_log(&share), None);
+
+    fs::write(share.join("execution_2026-10-18_09-00.log"), "")?;
+    fs::write(
+        share.join("execution_2026-10-19_12-00.log"),
+        "[    0.000000] Linux version 7.0.0-rc3-dirty (nixbld@localhost) #1 SMP\n",
+    )?;
+    let log = upload::latest_log(&share).unwrap();
+    assert_eq!(upload::log_date(&log), Some("2026-10-19_12-00".to_string()));
+
+    let state = State {
+        curdir: dir.clone(),
+        envdir: dir.clone(),
+        config: Config {
+            results: Some(ResultsConfig {
+                host: Some("builder".to_string()),
+                ..ResultsConfig::default()
+            }),
+            ..Config::default()
+        },
+        offline: true,
+        ..State::default()
+    };
+    let run = RunInfo::new(&Plan::new(&state)?);
+    assert_eq!(run.host, "builder");
+    assert_eq!(run.kernel, "7.0.0-rc3-dirty");
+    assert_eq!(run.variant, "default");
+    assert_eq!(run.date, "2026-10-19_12-00");
+
+    // Variant the run was made with, not --name of this command
+    fs::create_dir_all(share.join("results"))?;
+    kd::results::save_variant(&share.join("results"), "fix")?;
+    assert_eq!(RunInfo::new(&Plan::new(&state)?).variant, "fix");
+
+    fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[test]
+fn kd_upload_token() -> Result<()> {
+    let dir = temp_dir("upload-token")?;
+    let file = dir.join("token");
+    fs::write(&file, "secret\n")?;
+
+    assert_eq!(upload::token(&Credentials::default())?, None);
+
+    let var = format!("KD_TEST_TOKEN_{}", std::process::id());
+    let mut credentials = Credentials {
+        token_env: Some(var.clone()),
+        token_file: None,
+    };
+    assert!(upload::token(&credentials).is_err());
+
+    credentials.token_file = Some(file.display().to_string());
+    assert_eq!(upload::token(&credentials)?, Some("secret".to_string()));
+
+    std::env::set_var(&var, "from-env");
+    assert_eq!(upload::token(&credentials)?, Some("from-env".to_string()));
+    std::env::remove_var(&var);
+
+    fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[test]
+fn kd_upload_dir() -> Result<()> {
+    let dir = temp_dir("upload-dir")?;
+    let results = results(&dir)?;
+    let dest = upload::render(upload::DEFAULT_LAYOUT, &run())?;
+
+    let mut config = DirUpload {
+        path: dir.join("archive").display().to_string(),
+        tarball: None,
+    };
+    let output = upload::to_dir(&config, &results, &dest)?;
+    assert_eq!(output, dir.join("archive").join(&dest));
+    assert!(output.join("xfs_4k/generic/110.out.bad").exists());
+    assert!(!output.join("xfs_4k/generic/110.out").exists());
+
+    config.tarball = Some(true);
+    let output = upload::to_dir(&config, &results, &dest)?;
+    assert_eq!(output, dir.join("archive").join(format!("{dest}.tar.gz")));
+    let listing = Command::new("tar").arg("-tzf").arg(&output).output()?;
+    assert!(String::from_utf8_lossy(&listing.stdout).contains("./xfs_4k/check.log"));
+
+    fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[test]
+fn kd_upload_git() -> Result<()> {
+    let dir = temp_dir("upload-git")?;
+    let results = results(&dir)?;
+    let bare = dir.join("results.git");
+    fs::create_dir_all(&bare)?;
+    git(&bare, &["init", "-q", "--bare"])?;
+
+    let config = GitUpload {
+        repo: bare.display().to_string(),
+        branch: Some("results".to_string()),
+        credentials: Credentials::default(),
+    };
+    let envdir = dir.join(".kd");
+    let config = ResultsConfig {
+        git: Some(config),
+        ..ResultsConfig::default()
+    };
+
+    let uploaded = upload::upload(&config, &run(), &results, None, &envdir)?;
+    assert_eq!(uploaded.len(), 1);
+
+    let mut second = run();
+    second.date = "2026-10-20_08-30".to_string();
+    upload::upload(&config, &second, &results, None, &envdir)?;
+
+    // One commit per run on top of each other
+    let log = git(&bare, &["log", "--format=%s", "results"])?;
+    assert_eq!(
+        log.lines().collect::<Vec<&str>>(),
+        vec![
+            "Results of builder/7.0.0-rc3/default/2026-10-20_08-30",
+            "Results of builder/7.0.0-rc3/default/2026-10-19_12-00"
+        ]
+    );
+    let files = git(&bare, &["ls-tree", "-r", "--name-only", "results"])?;
+    assert!(files.contains("builder/7.0.0-rc3/default/2026-10-19_12-00/xfs_4k/check.log"));
+    assert!(files.contains("builder/7.0.0-rc3/default/2026-10-20_08-30/xfs_4k/generic/110.out.bad"));
+
+    fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[test]
+fn kd_upload_no_results() -> Result<()> {
+    let dir = temp_dir("upload-empty")?;
+    let config = ResultsConfig {
+        dir: Some(DirUpload {
+            path: dir.join("archive").display().to_string(),
+            tarball: None,
+        }),
+        ..ResultsConfig::default()
+    };
+
+    assert!(upload::upload(&config, &run(), &dir.join("share/results"), None, &dir).is_err());
+    let results = results(&dir)?;
+    assert!(upload::upload(&ResultsConfig::default(), &run(), &results, None, &dir).is_err());
+
+    fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[test]
+fn kd_upload_git_failed() -> Result<()> {
+    let dir = temp_dir("upload-git-failed")?;
+    let results = results(&dir)?;
+    let file = dir.join("token");
+    fs::write(&file, "secret\n")?;
+
+    let config = GitUpload {
+        repo: dir.join("missing.git").display().to_string(),
+        branch: None,
+        credentials: Credentials {
+            token_env: None,
+            token_file: Some(file.display().to_string()),
+        },
+    };
+    let workdir = dir.join(".kd/results-git");
+    assert!(upload::to_git(&config, &results, "run", &workdir).is_err());
+    // askpass script is removed even if git fails
+    assert!(!workdir.with_extension("askpass").exists());
+
+    fs::remove_dir_all(&dir)?;
+    Ok(())
+}
@@ -0,0 +1,181 @@
+use anyhow::Result;
+use kd::config::XfstestsTimeouts;
+use kd::progress::Progress;
+use kd::results::{self, Status};
+use kd::watchdog::{self, Watchdog};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixListener;
+use std::path::Path;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+mod common;
+use common::temp_dir;
+
+/// QEMU pretending QMP server, sends every command it gets to the channel
+fn qmp_server(socket: &Path) -> Result<mpsc::Receiver<String>> {
+    let listener = UnixListener::bind(socket)?;
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(mut stream) = stream else {
+                return;
+            };
+            let _ = writeln!(
+                stream,
+                r#"{{"QMP": {{"version": {{}}, "capabilities": []}}}}"#
+            );
+            let reader = BufReader::new(stream.try_clone().unwrap());
+            for line in reader.lines() {
+                let Ok(line) = line else {
+                    break;
+                };
+                if sender.send(line).is_err() {
+                    return;
+                }
+                let _ = writeln!(stream, r#"{{"event": "RESET"}}"#);
+                let _ = writeln!(stream, r#"{{"return": {{}}}}"#);
+            }
+        }
+    });
+    Ok(receiver)
+}
+
+#[test]
+fn kd_watchdog_hang() -> Result<()> {
+    let dir = temp_dir("watchdog-hang")?;
+    let socket = dir.join("qmp.sock");
+    let requests = qmp_server(&socket)?;
+    let timeouts = XfstestsTimeouts {
+        tests: Some(BTreeMap::from([(
+            "generic/475".to_string(),
+            "10m".to_string(),
+        )])),
+        reboot: Some(true),
+        ..XfstestsTimeouts::default()
+    };
+    let mut watchdog = Watchdog::new(timeouts, &socket, &dir);
+
+    let now = Instant::now();
+    let mut progress = Progress::new(BTreeMap::new(), now);
+    for line in [
+        "SECTION       -- xfs_1k",
+        "generic/001 3s ...  4s",
+        "Passed all 1 tests",
+    ] {
+        progress.feed(line, now);
+    }
+    assert!(watchdog.check(&mut progress, now)?.is_empty());
+    for line in [
+        "SECTION       -- xfs_4k",
+        "generic/001 3s ...  4s",
+        "generic/475 run fstests generic/475 at 2026-10-19 12:00:01",
+    ] {
+        progress.feed(line, now);
+    }
+
+    // Still within the timeout
+    let later = now + Duration::from_secs(300);
+    assert!(watchdog.check(&mut progress, later)?.is_empty());
+
+    let later = now + Duration::from_secs(600);
+    let messages = watchdog.check(&mut progress, later)?;
+    assert!(messages[0].starts_with("generic/475 runs longer than 10:00"));
+    let mut commands: Vec<String> = requests.try_iter().collect();
+    assert_eq!(commands.len(), 3);
+    assert!(commands[0].contains("qmp_capabilities"));
+    assert!(commands[1].contains("send-key") && commands[1].contains(r#""data":"w""#));
+    assert!(commands[2].contains("send-key") && commands[2].contains(r#""data":"t""#));
+
+    watchdog.feed("sysrq: Show Blocked State\n", later);
+    watchdog.feed("task:fsstress state:D stack:0 pid:100\n", later);
+    assert!(watchdog.check(&mut progress, later)?.is_empty());
+
+    // Console is quiet, diagnostics are saved and the VM reset
+    let later = later + Duration::from_secs(5);
+    let messages = watchdog.check(&mut progress, later)?;
+    assert!(messages[0].starts_with("generic/475 HUNG"));
+    assert_eq!(messages[1], "VM is reset, remaining tests run after reboot");
+    commands = requests.try_iter().collect();
+    assert!(commands
+        .iter()
+        .any(|command| command.contains("system_reset")));
+
+    let hang = fs::read_to_string(dir.join("results/xfs_4k/generic/475.hang"))?;
+    assert!(hang.starts_with("generic/475 hung, it was running for longer than 10:00\n"));
+    assert!(hang.contains("task:fsstress state:D"));
+
+    // Tests to skip are by section, the finished one doesn't run again
+    let exclude = dir.join(watchdog::EXCLUDE);
+    assert_eq!(
+        fs::read_to_string(exclude.join("xfs_1k"))?,
+        "generic/001\n"
+    );
+    assert_eq!(
+        fs::read_to_string(exclude.join("xfs_4k"))?,
+        "generic/001\ngeneric/475\n"
+    );
+    assert_eq!(
+        fs::read_to_string(exclude.join(watchdog::FINISHED))?,
+        "xfs_1k\n"
+    );
+
+    // Results of the section before the reset are kept
+    assert!(dir.join("results/xfs_4k/result.reset-1.xml").exists());
+    let suites = results::load(&dir.join("results"))?;
+    let tests: Vec<(&str, Status)> = suites[0]
+        .tests
+        .iter()
+        .map(|test| (test.name.as_str(), test.status))
+        .collect();
+    assert_eq!(
+        tests,
+        [("generic/001", Status::Pass), ("generic/475", Status::Fail)]
+    );
+    assert_eq!(watchdog.hung, vec!["generic/475".to_string()]);
+    assert_eq!(progress.failed, 1);
+    assert!(progress.current.is_none());
+
+    fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[test]
+fn kd_watchdog_no_qmp() -> Result<()> {
+    let dir = temp_dir("watchdog-no-qmp")?;
+    let timeouts = XfstestsTimeouts {
+        default: Some("1h".to_string()),
+        groups: Some(BTreeMap::from([("quick".to_string(), "1m".to_string())])),
+        ..XfstestsTimeouts::default()
+    };
+    fs::create_dir_all(dir.join(watchdog::GROUPS))?;
+    fs::write(
+        dir.join(watchdog::GROUPS).join("xfs"),
+        "# QA groups control file\n0010 auto\n001 auto quick # mkfs\n",
+    )?;
+    assert_eq!(watchdog::groups(&dir, "xfs/001"), ["auto", "quick"]);
+    assert!(watchdog::groups(&dir, "generic/001").is_empty());
+    let mut watchdog = Watchdog::new(timeouts, &dir.join("qmp.sock"), &dir);
+
+    let now = Instant::now();
+    let mut progress = Progress::new(BTreeMap::new(), now);
+    progress.feed("xfs/001 run fstests xfs/001 at 2026-10-19 12:00:01", now);
+
+    // Without the socket the test is marked as hung right away
+    let messages = watchdog.check(&mut progress, now + Duration::from_secs(60))?;
+    assert!(messages[1].starts_with("Failed to trigger sysrq"));
+    assert!(messages[2].starts_with("xfs/001 HUNG"));
+    let hang = fs::read_to_string(dir.join("results/xfs/001.hang"))?;
+    assert!(hang.contains("kd: failed to trigger sysrq"));
+    assert!(!dir.join(watchdog::EXCLUDE).exists());
+
+    // Reported once
+    let messages = watchdog.check(&mut progress, now + Duration::from_secs(120))?;
+    assert!(messages.is_empty());
+
+    fs::remove_dir_all(&dir)?;
+    Ok(())
+}
@@ -34,6 +34,8 @@
     pkgs,
     useGcc ? false,
     user-modules ? [],
+    # Only for the VMs, the image, iso and qcow2 don't have qemu-vm.nix options
+    vm-modules ? [],
   }: let
     stdenv =
       if useGcc
@@ -61,7 +63,8 @@
             ./vm.nix
             ./input.nix
           ]
-          ++ user-modules;
+          ++ user-modules
+          ++ vm-modules;
       }).config;
     useConfig = builtins.hasAttr "kernel" userSystem;
     version =
@@ -76,6 +79,11 @@
       if useConfig && builtins.hasAttr "kconfig" userSystem.kernel
       then userSystem.kernel.kconfig
       else sources.options.kernel.kconfig.default;
+    # The VM kernel applies them under [kernel.config], see input.nix
+    kflavors =
+      if useConfig && builtins.hasAttr "flavors" userSystem.kernel
+      then userSystem.kernel.flavors
+      else [];
     kconfigBuild = {config}:
       buildKernelConfig {
         inherit src version;
@@ -149,16 +157,28 @@
           ++ user-modules;
       }).config.system.build;
   in rec {
-    inherit (pkgs) xfsprogs xfstests;
+    inherit (pkgs) xfsprogs xfstests drgn;
 
+    # Same .config as the one of the VM kernel
     kconfig = buildKernelConfig {
       inherit src version;
-      kconfig = kkconfig;
+      kconfig = pkgs.lib.foldl (acc: set: acc // set) {} (kflavors ++ [kkconfig]);
     };
 
     kconfig-debug = kconfigBuild {config = "debug";};
     kconfig-image = kconfigBuild {config = "image";};
 
+    # Kernel config flavors for kd, it checks prebuild kernels against them
+    kconfig-flavors = pkgs.writeText "kconfig-flavors.json" (builtins.toJSON pkgs.kconfigs);
+
+    # All the symbols defined in Kconfig files of the kernel source, kd checks
+    # [kernel.config] against them
+    kconfig-symbols = pkgs.runCommand "kconfig-symbols-${version}" {} ''
+      find ${src} -name 'Kconfig*' -type f -exec \
+        sed -n 's/^[[:space:]]*\(menu\)\?config[[:space:]]\+\([A-Za-z0-9_]\+\).*/\2/p' {} + \
+        | sort -u > $out
+    '';
+
     headers = buildKernelHeaders {
       inherit src version;
     };
@@ -167,11 +187,16 @@
       inherit src kconfig version;
     };
 
+    # Kernel the VM boots, with flavors applied. Its 'dev' output has vmlinux
+    # for 'kd dump' and 'kd drgn'.
+    vm-kernel = userSystem.boot.kernelPackages.kernel;
+
     vm = pkgs.callPackage ./runner.nix {
       nixos = mkVmImage {
         inherit pkgs;
         user-modules =
           user-modules
+          ++ vm-modules
           ++ [
             ({...}: {
               kernel = pkgs.lib.mkDefault {
@@ -188,6 +213,7 @@
         inherit pkgs;
         user-modules =
           user-modules
+          ++ vm-modules
           ++ [
             ({...}: {
               kernel = pkgs.lib.mkDefault {
@@ -208,6 +234,7 @@
         inherit pkgs;
         user-modules =
           user-modules
+          ++ vm-modules
           ++ [
             ({...}: {
               kernel = pkgs.lib.mkDefault {
@@ -233,9 +260,7 @@
       };
     };
 
-    #initrd = pkgs.callPackage (import ./initrd/default.nix) {
-    #  inherit nixpkgs;
-    #};
+    initrd = build.initialRamdisk;
 
     # The image/repart.nix module has no built-in checksum option, so hook into
     # the image derivation's install phase and drop a sha256sum next to the raw
@@ -254,8 +279,38 @@
 
     image-toplevel = build.toplevel;
 
+    qcow2 = pkgs.runCommand "image-qcow2" {nativeBuildInputs = [pkgs.qemu-utils];} ''
+      mkdir -p $out
+      for f in ${image}/*.raw; do
+        qemu-img convert -f raw -O qcow2 "$f" "$out/$(basename "$f" .raw).qcow2"
+      done
+    '';
+
+    iso =
+      (nixosSystem {
+        inherit pkgs;
+        system = "x86_64-linux";
+        modules =
+          [
+            ./xfstests/module.nix
+            ./xfsprogs/module.nix
+            ./input.nix
+            ./system.nix
+            ({modulesPath, ...}: {
+              imports = [
+                (modulesPath + "/installer/cd-dvd/iso-image.nix")
+              ];
+
+              isoImage.makeEfiBootable = true;
+              isoImage.makeUsbBootable = true;
+            })
+          ]
+          ++ user-modules;
+      }).config.system.build.isoImage;
+
     run-image = pkgs.callPackage ./run-image.nix {
       inherit image;
+      inherit (userSystem.virtualisation) memorySize cores;
     };
   };
 }
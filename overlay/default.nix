@@ -104,10 +104,6 @@
     '';
   };
 
-  xfstests-upload = prev.writeShellScriptBin "xfstests-upload" (
-    builtins.readFile ../xfstests/github-upload.sh
-  );
-
   xfstests = prev.xfstests.overrideAttrs (
     old: let
       sources = prev.lib.importJSON ../sources/xfstests.json;
@@ -3,25 +3,45 @@
   qemu,
   image,
   OVMF,
+  memorySize ? 4096,
+  cores ? 4,
+  # Free space for partitions systemd-repart creates on the first boot
+  extraSize ? "+32G",
 }:
 writeShellScriptBin "repart-image-qemu" ''
   set -euo pipefail
 
-  DISK_IMAGE="demo-disk.raw"
+  ENVDIR="$PWD/.kd"
+  RUNDIR="$ENVDIR/share"
+  LOG_FILE="$RUNDIR/execution_$(date +"%Y-%m-%d_%H-%M").log"
+  DISK_IMAGE="''${KD_IMAGE_DISK:-$ENVDIR/repart-image.qcow2}"
+
+  mkdir -p "$RUNDIR"
+
+  IMAGE="$(ls ${image}/*.raw | head -n 1)"
+  # Start over if the image changed, the disk is modified by the running system
+  if ! cmp -s "$IMAGE.sha256sum" "$DISK_IMAGE.sha256sum"; then
+    rm -f "$DISK_IMAGE" "$DISK_IMAGE.sha256sum"
+  fi
 
   if [[ ! -f "$DISK_IMAGE" ]]; then
-    cp ${image}/image.raw "$DISK_IMAGE"
-    chmod +w "$DISK_IMAGE"
-    ${qemu}/bin/qemu-img resize -f raw "$DISK_IMAGE" "+30G"
+    ${qemu}/bin/qemu-img convert -f raw -O qcow2 "$IMAGE" "$DISK_IMAGE"
+    ${qemu}/bin/qemu-img resize -f qcow2 "$DISK_IMAGE" "${extraSize}"
+    cp "$IMAGE.sha256sum" "$DISK_IMAGE.sha256sum"
+    chmod +w "$DISK_IMAGE.sha256sum"
   fi
 
   ${qemu}/bin/qemu-system-x86_64 \
-    -smp 4 \
-    -m 2048 \
+    -smp ${toString cores} \
+    -m ${toString memorySize} \
     --enable-kvm \
     -cpu host \
     -bios "${OVMF.fd}/FV/OVMF.fd" \
     -hda "$DISK_IMAGE" \
     -serial mon:stdio \
-    -nographic
+    -nographic \
+    -qmp "unix:$ENVDIR/qmp.sock,server=on,wait=off" \
+    -device vmcoreinfo \
+    ''${QEMU_OPTS:-} 2>&1 | tee -a "$LOG_FILE"
+  echo "Log is in $LOG_FILE"
 ''
@@ -5,13 +5,11 @@
   runCommand,
   bash,
   coreutils-full,
-  tomlq,
 }: let
   src = ./runner.sh;
   binName = "runner";
   deps = [
     bash
-    tomlq
     coreutils-full
   ];
 in
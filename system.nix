@@ -94,6 +94,9 @@
 
   environment.variables.EDITOR = "nvim";
 
+  # kd sends sysrq over QMP to dump tasks of a hung test
+  boot.kernel.sysctl."kernel.sysrq" = 1;
+
   services.xfstests.enable = true;
   services.xfsprogs.enable = true;
 
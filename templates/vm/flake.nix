@@ -36,6 +36,7 @@
           (import ./uconfig.nix)
         ]
         ++ (pkgs.lib.optional (builtins.pathExists ./modules.nix) ./modules.nix);
+      vm-modules = pkgs.lib.optional (builtins.pathExists ./vmconfig.nix) (import ./vmconfig.nix);
     };
   in {
     packages.${system} = packages;
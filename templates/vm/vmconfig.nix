@@ -0,0 +1 @@
+{pkgs, kd, ...}: {}
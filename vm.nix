@@ -29,6 +29,13 @@ in {
         1000
       ];
       diskImage = lib.mkDefault "$ENVDIR/${config.system.name}.qcow2";
+      # QEMU monitor for 'kd vm', the socket is removed when QEMU exits.
+      # The guest puts VMCOREINFO into vmcoreinfo device, 'kd dump' needs it
+      # for drgn to read the dump.
+      qemu.options = [
+        "-qmp unix:$ENVDIR/qmp.sock,server=on,wait=off"
+        "-device vmcoreinfo"
+      ];
     };
   };
 }
@@ -7,6 +7,12 @@
 with lib; let
   cfg = config.services.xfstests;
 in {
+  imports = [
+    (mkRemovedOptionModule ["services" "xfstests" "upload-results"] "Results are uploaded by kd on the host, see [results] in .kd.toml")
+    (mkRemovedOptionModule ["services" "xfstests" "repository"] "Results are uploaded by kd on the host, see [results] in .kd.toml")
+    (mkRemovedOptionModule ["services" "xfstests" "hooks"] "kd copies hooks to /root/share/hooks, see 'hooks' in [xfstests] of .kd.toml")
+  ];
+
   options.services.xfstests = {
     enable = mkEnableOption {
       name = "xfstests";
@@ -94,11 +100,29 @@ in {
       type = types.bool;
     };
 
-    hooks = mkOption {
-      description = "Path to hooks folder. 20210722064725.3077558-1-david@fromorbit.com";
-      default = null;
-      example = "./xfstests-hooks";
-      type = types.nullOr types.path;
+    trace = {
+      enable = mkEnableOption "trace-cmd recording of every test into <test>.trace.dat";
+
+      events = mkOption {
+        description = "Events to enable, as in 'trace-cmd start -e'";
+        default = [];
+        example = ["xfs:*" "block:*"];
+        type = types.listOf types.str;
+      };
+
+      bufferSize = mkOption {
+        description = "Ring buffer size per CPU in KB, trace-cmd default if null";
+        default = null;
+        example = 65536;
+        type = types.nullOr types.int;
+      };
+
+      rerunFailed = mkOption {
+        description = "Run tests without tracing and re-run the failed ones with it";
+        default = false;
+        example = true;
+        type = types.bool;
+      };
     };
 
     filesystem = mkOption {
@@ -113,20 +137,6 @@ in {
       default = pkgs.fetchgit (pkgs.lib.importJSON ../sources/xfstests.json);
     };
 
-    repository = mkOption {
-      description = "GitHub repository to upload results to";
-      default = "";
-      example = "https://github.com/alberand/xfstests-results";
-      type = types.str;
-    };
-
-    upload-results = mkOption {
-      description = "Upload results to GitHub repository";
-      default = false;
-      example = true;
-      type = types.bool;
-    };
-
     kernelHeaders = mkOption {
       type = types.package;
       description = "Linux kernel headers to compile xfstests against";
@@ -148,6 +158,23 @@ in {
 
   config = let
     xfspcfg = config.services.xfsprogs;
+    # Hooks of the hook infrastructure patch, they're sourced by xfstests and
+    # run in the test environment, $seqres is the test's results path
+    trace-start = pkgs.writeText "trace-cmd-start" ''
+      hook_execute()
+      {
+        ${pkgs.trace-cmd}/bin/trace-cmd start \
+          ${optionalString (cfg.trace.bufferSize != null) "-b ${toString cfg.trace.bufferSize}"} \
+          ${concatMapStringsSep " " (event: "-e ${escapeShellArg event}") cfg.trace.events}
+      }
+    '';
+    trace-stop = pkgs.writeText "trace-cmd-stop" ''
+      hook_execute()
+      {
+        ${pkgs.trace-cmd}/bin/trace-cmd extract -o $seqres.trace.dat
+        ${pkgs.trace-cmd}/bin/trace-cmd reset
+      }
+    '';
     xfsprogs = pkgs.xfsprogs.overrideAttrs (
       _final: prev: (
         {
@@ -235,12 +262,6 @@ in {
     );
   in
     mkIf cfg.enable {
-      warnings = (
-        lib.optionals (
-          cfg.upload-results && (cfg.repository == "")
-        ) "To upload results set services.xfstests.repository"
-      );
-
       # Setup envirionment
       environment.variables = {
         XFSTESTS_SRC = "${xfstests.src}";
@@ -463,6 +484,22 @@ in {
             if [ -d /root/share/hooks ]; then
               export HOOK_DIR="/root/share/hooks"
             fi
+            ${optionalString cfg.trace.enable ''
+            # Trace hooks go after the global hooks of the shared directory
+            trace_hooks=$(mktemp -d)
+            if [ -n "''${HOOK_DIR:-}" ]; then
+              cp -r "$HOOK_DIR/." "$trace_hooks"
+            fi
+            mkdir -p "$trace_hooks/start" "$trace_hooks/end"
+            n=0
+            while [ -e "$trace_hooks/start/global.$n" ]; do n=$((n + 1)); done
+            cp ${trace-start} "$trace_hooks/start/global.$n"
+            n=0
+            while [ -e "$trace_hooks/end/global.$n" ]; do n=$((n + 1)); done
+            cp ${trace-stop} "$trace_hooks/end/global.$n"
+            ${optionalString (!cfg.trace.rerunFailed) ''
+            export HOOK_DIR="$trace_hooks"
+            ''}''}
             ${cfg.extraEnv}
 
             env_log=$(mktemp)
@@ -476,18 +513,102 @@ in {
               source /root/xfstests.env
             fi
 
-            echo "Running:"
-            echo -e "\txfstests-check $ARGUMENTS"
-            ${pkgs.bash}/bin/bash -lc \
-              "echo $ARGUMENTS | xargs ${xfstests}/bin/xfstests-check"
+            # kd looks up timeouts of xfstests groups in the group lists
+            groups=/root/share/xfstests.groups
+            rm -rf "$groups"
+            mkdir -p "$groups"
+            for list in ${xfstests}/lib/xfstests/tests/*/group.list; do
+              [ -f "$list" ] || continue
+              cp "$list" "$groups/$(basename "$(dirname "$list")")"
+            done
+
+            # Tests which ran before kd reset the VM stuck in a hung test are
+            # excluded by section, sections which ran to the end are skipped
+            runs=("$ARGUMENTS")
+            exclude=/root/share/xfstests.exclude
+            if [ -d "$exclude" ]; then
+              args=""
+              sections=""
+              set -- $ARGUMENTS
+              while [ $# -gt 0 ]; do
+                if [ "$1" = "-s" ] && [ $# -gt 1 ]; then
+                  sections="$sections $2"
+                  shift 2
+                else
+                  args="$args $1"
+                  shift
+                fi
+              done
+              if [ -z "$sections" ]; then
+                sections=$(sed -n 's/^\[\(.*\)\]$/\1/p' \
+                  ${config.environment.variables.HOST_OPTIONS})
+              fi
+
+              runs=()
+              if [ -z "$sections" ]; then
+                runs+=("-E $exclude/default $args")
+              fi
+              for section in $sections; do
+                if grep -qxF "$section" "$exclude/.finished"; then
+                  continue
+                fi
+                if [ -f "$exclude/$section" ]; then
+                  runs+=("-s $section -E $exclude/$section $args")
+                else
+                  runs+=("-s $section $args")
+                fi
+              done
+            fi
 
+            # Later sections still run if the tests of one of them fail
+            status=0
+            for ARGUMENTS in "''${runs[@]}"; do
+              echo "Running:"
+              echo -e "\txfstests-check $ARGUMENTS"
           ''
-          + (optionalString (cfg.upload-results) ''
-            ${pkgs.xfstests-upload}/bin/xfstests-upload \
-              ${cfg.repository} \
-              ${config.networking.hostName} \
-              /root/results
-          '');
+          + (
+            if cfg.trace.enable && cfg.trace.rerunFailed
+            then ''
+              check_log=$(mktemp)
+              ${pkgs.bash}/bin/bash -lc \
+                "printf '%s ' $ARGUMENTS | xargs ${xfstests}/bin/xfstests-check" \
+                | tee "$check_log"
+              # The first run decides the status, the traced one doesn't
+              check_status=''${PIPESTATUS[0]}
+              if [ "$check_status" -ne 0 ]; then
+                status=$check_status
+              fi
+
+              # Everything which ran but didn't fail is excluded from the
+              # second run, so only the failed tests run again
+              failed=$(sed -n 's/^Failures: //p' "$check_log" | tr ' ' '\n' | sort -u)
+              if [ -n "$failed" ]; then
+                passed=$(mktemp)
+                sed -n 's/^\(Ran\|Not run\): //p' "$check_log" | tr ' ' '\n' \
+                  | sort -u | grep -vxF "$failed" > "$passed" || true
+
+                # Reports of the first run are the results, the second one is
+                # only for the traces
+                saved=$(mktemp -d)
+                (cd /root/share/results && find . \( -name result.xml -o -name check.time \) \
+                  -exec cp --parents {} "$saved" \;)
+
+                echo "Re-running failed tests with tracing:" $failed
+                HOOK_DIR="$trace_hooks" ${pkgs.bash}/bin/bash -lc \
+                  "printf '%s ' -E $passed $ARGUMENTS | xargs ${xfstests}/bin/xfstests-check" || true
+                cp -r "$saved/." /root/share/results/
+              fi
+            ''
+            else ''
+              ${pkgs.bash}/bin/bash -lc \
+                "printf '%s ' $ARGUMENTS | xargs ${xfstests}/bin/xfstests-check" \
+                || status=$?
+            ''
+          )
+          + ''
+            done
+            exit $status
+          '';
       };
     };
 }